@@ -9,3 +9,14 @@ export const TOKEN_PROGRAM_ID = new PublicKey(
 export const MAX_STAKE_COUNT = 65535;
 
 export const ZERO_TS = 0;
+
+/**
+ * Layout version of the accounts written by the program
+ */
+export const CURRENT_VERSION = 3;
+
+/**
+ * Discriminators naming the kind of each program account
+ */
+export const STAKE_STORE_DISCRIMINATOR = Buffer.from("nftstore");
+export const STAKE_LIST_DISCRIMINATOR = Buffer.from("nftslist");
@@ -5,20 +5,48 @@ import {
   TransactionInstruction,
   SYSVAR_CLOCK_PUBKEY,
   SYSVAR_RENT_PUBKEY,
+  SystemProgram,
 } from "@solana/web3.js";
-import { Uint64Layout } from "./layout";
+import { Uint64Layout, PoolConfigLayout, defaultPoolConfig } from "./layout";
 import BN from "bn.js";
 
+/**
+ * Turns the authority of an instruction into an spl-token multisig: the
+ * authority stops signing and its signing members are appended after the
+ * instruction's accounts, where the program looks for them
+ */
+export const withAuthoritySigners = (instruction, authorityKey, signerKeys) => {
+  instruction.keys.forEach((key) => {
+    if (key.pubkey.equals(authorityKey)) {
+      key.isSigner = false;
+    }
+  });
+  // writable since the first member pays or receives rent for the authority
+  instruction.keys.push(
+    ...signerKeys.map((pubkey) => ({
+      pubkey,
+      isSigner: true,
+      isWritable: true,
+    }))
+  );
+  return instruction;
+};
+
 export const createInitStakingInstruction = (
   stakeStoreKey,
   stakeListKey,
   managerKey,
-  stakingProgramId
+  stakingProgramId,
+  config = defaultPoolConfig(),
+  managerSignerKeys = []
 ) => {
-  const dataLayout = BufferLayout.struct([BufferLayout.u8("instruction")]);
+  const dataLayout = BufferLayout.struct([
+    BufferLayout.u8("instruction"),
+    PoolConfigLayout("config"),
+  ]);
   const data = Buffer.alloc(dataLayout.span);
   const encodeLength = dataLayout.encode(
-    { instruction: 0 /* Initialize Instruction */ },
+    { instruction: 0 /* Initialize Instruction */, config },
     data
   );
   const keys = [
@@ -27,11 +55,15 @@ export const createInitStakingInstruction = (
     { pubkey: managerKey, isSigner: true, isWritable: false },
     { pubkey: SYSVAR_RENT_PUBKEY, isSigner: false, isWritable: false },
   ];
-  return new TransactionInstruction({
+  const instruction = new TransactionInstruction({
     keys,
     programId: stakingProgramId,
     data: data.slice(0, encodeLength),
   });
+  if (managerSignerKeys.length === 0) {
+    return instruction;
+  }
+  return withAuthoritySigners(instruction, managerKey, managerSignerKeys);
 };
 
 export const stakeInstruction = (
@@ -40,6 +72,7 @@ export const stakeInstruction = (
   stakeKey,
   stakeStoreKey,
   stakeListKey,
+  stakerKey,
   tokenProgramId,
   amount,
   stakingProgramId
@@ -56,14 +89,17 @@ export const stakeInstruction = (
     },
     data
   );
+  // the depositor pays for their staker account on their first deposit
   const keys = [
-    { pubkey: userKey, isSigner: true, isWritable: false },
+    { pubkey: userKey, isSigner: true, isWritable: true },
     { pubkey: mintKey, isSigner: false, isWritable: false },
     { pubkey: SYSVAR_CLOCK_PUBKEY, isSigner: false, isWritable: false },
     { pubkey: stakeKey, isSigner: false, isWritable: true },
     { pubkey: stakeStoreKey, isSigner: false, isWritable: true },
     { pubkey: stakeListKey, isSigner: false, isWritable: true },
     { pubkey: tokenProgramId, isSigner: false, isWritable: false },
+    { pubkey: stakerKey, isSigner: false, isWritable: true },
+    { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
   ];
   return new TransactionInstruction({
     keys,
@@ -72,28 +108,115 @@ export const stakeInstruction = (
   });
 };
 
+/**
+ * What a withdrawal does with rewards it can't pay out, see
+ * `UnsettledRewards` in the program
+ */
+export const UnsettledRewards = {
+  Credit: 0,
+  Abort: 1,
+  Forfeit: 2,
+};
+
+export const withdrawInstruction = (
+  withdrawerKey,
+  mintKey,
+  stakeStoreKey,
+  stakeListKey,
+  stakeKey,
+  pdaStakeKey,
+  stakerKey,
+  tokenProgramId,
+  rewardAccounts,
+  unsettledRewards,
+  stakingProgramId
+) => {
+  const dataLayout = BufferLayout.struct([
+    BufferLayout.u8("instruction"),
+    BufferLayout.u8("unsettledRewards"),
+  ]);
+  const data = Buffer.alloc(dataLayout.span);
+  dataLayout.encode(
+    { instruction: 2 /* Withdraw Instruction */, unsettledRewards },
+    data
+  );
+  const keys = [
+    { pubkey: withdrawerKey, isSigner: true, isWritable: true },
+    { pubkey: mintKey, isSigner: false, isWritable: false },
+    { pubkey: SYSVAR_CLOCK_PUBKEY, isSigner: false, isWritable: false },
+    { pubkey: stakeStoreKey, isSigner: false, isWritable: true },
+    { pubkey: stakeListKey, isSigner: false, isWritable: true },
+    { pubkey: stakeKey, isSigner: false, isWritable: true },
+    { pubkey: pdaStakeKey, isSigner: false, isWritable: false },
+    { pubkey: tokenProgramId, isSigner: false, isWritable: false },
+    { pubkey: stakerKey, isSigner: false, isWritable: true },
+  ];
+  if (rewardAccounts) {
+    const { vaultKey, destinationKey, authorityKey } = rewardAccounts;
+    keys.push(
+      { pubkey: vaultKey, isSigner: false, isWritable: true },
+      { pubkey: destinationKey, isSigner: false, isWritable: true },
+      { pubkey: authorityKey, isSigner: false, isWritable: false }
+    );
+  }
+  return new TransactionInstruction({
+    keys,
+    programId: stakingProgramId,
+    data,
+  });
+};
+
 export const reclaimInstruction = (
-  userKey,
+  ownerKey,
   mintKey,
   stakeStoreKey,
   stakeListKey,
   stakeKey,
   pdaStakeKey,
+  associatedTokenKey,
+  stakerKey,
   tokenProgramId,
   stakingProgramId
 ) => {
   const dataLayout = BufferLayout.struct([BufferLayout.u8("instruction")]);
   const data = Buffer.alloc(dataLayout.span);
-  dataLayout.encode({ instruction: 2 }, data);
+  dataLayout.encode({ instruction: 24 /* Reclaim Instruction */ }, data);
+  // permissionless, the owner doesn't sign
   const keys = [
-    { pubkey: userKey, isSigner: true, isWritable: false },
+    { pubkey: ownerKey, isSigner: false, isWritable: false },
     { pubkey: mintKey, isSigner: false, isWritable: false },
     { pubkey: SYSVAR_CLOCK_PUBKEY, isSigner: false, isWritable: false },
-    { pubkey: stakeStoreKey, isSigner: false, isWritable: false },
-    { pubkey: stakeListKey, isSigner: false, isWritable: false },
-    { pubkey: stakeKey, isSigner: false, isWritable: false },
+    { pubkey: stakeStoreKey, isSigner: false, isWritable: true },
+    { pubkey: stakeListKey, isSigner: false, isWritable: true },
+    { pubkey: stakeKey, isSigner: false, isWritable: true },
     { pubkey: pdaStakeKey, isSigner: false, isWritable: false },
+    { pubkey: associatedTokenKey, isSigner: false, isWritable: true },
     { pubkey: tokenProgramId, isSigner: false, isWritable: false },
+    { pubkey: stakerKey, isSigner: false, isWritable: true },
+  ];
+  return new TransactionInstruction({
+    keys,
+    programId: stakingProgramId,
+    data,
+  });
+};
+
+const stakeViewInstruction = (
+  instruction,
+  mintKey,
+  stakeStoreKey,
+  stakeListKey,
+  stakingProgramId
+) => {
+  const dataLayout = BufferLayout.struct([BufferLayout.u8("instruction")]);
+  const data = Buffer.alloc(dataLayout.span);
+  dataLayout.encode({ instruction }, data);
+  // read-only and unsigned so the instruction can be simulated
+  const keys = [
+    { pubkey: mintKey, isSigner: false, isWritable: false },
+    { pubkey: SYSVAR_CLOCK_PUBKEY, isSigner: false, isWritable: false },
+    { pubkey: stakeStoreKey, isSigner: false, isWritable: false },
+    { pubkey: stakeListKey, isSigner: false, isWritable: false },
   ];
   return new TransactionInstruction({
     keys,
@@ -101,3 +224,31 @@ export const reclaimInstruction = (
     data,
   });
 };
+
+export const getPendingRewardsInstruction = (
+  mintKey,
+  stakeStoreKey,
+  stakeListKey,
+  stakingProgramId
+) =>
+  stakeViewInstruction(
+    19 /* GetPendingRewards Instruction */,
+    mintKey,
+    stakeStoreKey,
+    stakeListKey,
+    stakingProgramId
+  );
+
+export const getStakeInfoInstruction = (
+  mintKey,
+  stakeStoreKey,
+  stakeListKey,
+  stakingProgramId
+) =>
+  stakeViewInstruction(
+    20 /* GetStakeInfo Instruction */,
+    mintKey,
+    stakeStoreKey,
+    stakeListKey,
+    stakingProgramId
+  );
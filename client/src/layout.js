@@ -14,11 +14,126 @@ export const Uint64Layout = (property = "uint64") => {
   return BufferLayout.blob(8, property);
 };
 
+/**
+ * Layout for a 128bit unsigned value
+ */
+export const Uint128Layout = (property = "uint128") => {
+  return BufferLayout.blob(16, property);
+};
+
+/**
+ * Prefix of every program account, naming its kind and layout version
+ */
+export const AccountHeaderLayout = (property = "accountHeader") =>
+  BufferLayout.struct(
+    [BufferLayout.blob(8, "discriminator"), BufferLayout.u8("version")],
+    property
+  );
+
+export const MAX_EMISSION_SEGMENTS = 16;
+export const MAX_STAKE_TIERS = 8;
+export const MAX_FEE_EXEMPTIONS = 8;
+
+export const EmissionScheduleLayout = (property) =>
+  BufferLayout.struct(
+    [
+      BufferLayout.u8("segmentCount"),
+      BufferLayout.seq(
+        BufferLayout.struct([Uint64Layout("startTs"), Uint64Layout("rate")]),
+        MAX_EMISSION_SEGMENTS,
+        "segments"
+      ),
+      Uint64Layout("endTs"),
+    ],
+    property
+  );
+
+export const StakeTiersLayout = (property) =>
+  BufferLayout.struct(
+    [
+      BufferLayout.u8("tierCount"),
+      BufferLayout.seq(Uint64Layout(), MAX_STAKE_TIERS, "thresholds"),
+    ],
+    property
+  );
+
+export const FeeConfigLayout = (property) =>
+  BufferLayout.struct(
+    [
+      Uint64Layout("depositFee"),
+      Uint64Layout("withdrawFee"),
+      PublicKeyLayout("feeMint"),
+      PublicKeyLayout("treasury"),
+      BufferLayout.struct(
+        [
+          BufferLayout.u8("walletCount"),
+          BufferLayout.seq(PublicKeyLayout(), MAX_FEE_EXEMPTIONS, "wallets"),
+        ],
+        "exemptions"
+      ),
+    ],
+    property
+  );
+
+/**
+ * Settings of a stake store, sent with Initialize
+ */
+export const PoolConfigLayout = (property = "config") =>
+  BufferLayout.struct(
+    [
+      EmissionScheduleLayout("emissionSchedule"),
+      BufferLayout.u32("maxPerOwner"),
+      BufferLayout.u32("maxTotal"),
+      StakeTiersLayout("stakeTiers"),
+      BufferLayout.u16("maxItems"),
+      Uint64Layout("lockupSeconds"),
+      PublicKeyLayout("rewardMint"),
+      PublicKeyLayout("requiredCollection"),
+      FeeConfigLayout("fees"),
+      BufferLayout.u8("paused"),
+      Uint64Layout("reclaimTimeout"),
+      Uint64Layout("reclaimBounty"),
+      PublicKeyLayout("rewardOperator"),
+      PublicKeyLayout("pauser"),
+      Uint64Layout("configDelay"),
+    ],
+    property
+  );
+
+/**
+ * All-zero config, which the program reads as its defaults
+ */
+export const defaultPoolConfig = () => {
+  const layout = PoolConfigLayout();
+  return layout.decode(Buffer.alloc(layout.span));
+};
+
 export const StakeStoreLayout = BufferLayout.struct([
+  AccountHeaderLayout(),
   BufferLayout.u8("isInitialized"),
   PublicKeyLayout("manager"),
   BufferLayout.u16("stakedCount"),
   PublicKeyLayout("stakeList"),
+  PublicKeyLayout("rarityTable"),
+  BufferLayout.blob(32, "allowListRoot"),
+  PublicKeyLayout("requiredCreator"),
+  PublicKeyLayout("rewardVault"),
+  Uint64Layout("totalShares"),
+  Uint128Layout("rewardPerShare"),
+  Uint64Layout("rewardRate"),
+  Uint64Layout("rewardEpochEnd"),
+  Uint64Layout("lastRewardTs"),
+  Uint64Layout("unclaimedRewards"),
+  PoolConfigLayout("config"),
+  BufferLayout.u8("sunset"),
+  BufferLayout.struct(
+    [
+      BufferLayout.u8("isPending"),
+      Uint64Layout("effectiveAt"),
+      PoolConfigLayout("config"),
+    ],
+    "pendingConfig"
+  ),
 ]);
 
 export const StakeListHeaderLayout = (property) =>
@@ -27,7 +142,6 @@ export const StakeListHeaderLayout = (property) =>
       BufferLayout.u8("isInitialized"),
       BufferLayout.u16("maxItems"),
       BufferLayout.u16("count"),
-      //... need reclaim timeout
     ],
     property
   );
@@ -36,10 +150,41 @@ export const StakeItemLayout = BufferLayout.struct([
   PublicKeyLayout("tokenMint"),
   PublicKeyLayout("holder"),
   Uint64Layout("stakeTime"),
+  BufferLayout.u16("multiplierBps"),
+  Uint64Layout("rewardDebt"),
 ]);
 
+/**
+ * Stake list holding room for `n` items, of which `length` are staked
+ */
 export const StakeListLayout = (n) =>
   BufferLayout.struct([
+    AccountHeaderLayout(),
     StakeListHeaderLayout("header"),
+    BufferLayout.u32("length"),
     BufferLayout.seq(StakeItemLayout, n, "items"),
   ]);
+
+/**
+ * Return data of the GetPendingRewards view instruction
+ */
+export const PendingRewardsLayout = BufferLayout.struct([
+  PublicKeyLayout("owner"),
+  PublicKeyLayout("tokenMint"),
+  Uint64Layout("pending"),
+  Uint64Layout("asOf"),
+]);
+
+/**
+ * Return data of the GetStakeInfo view instruction
+ */
+export const StakeInfoLayout = BufferLayout.struct([
+  PublicKeyLayout("owner"),
+  PublicKeyLayout("tokenMint"),
+  Uint64Layout("stakeTime"),
+  Uint64Layout("stakeAge"),
+  BufferLayout.u16("multiplierBps"),
+  BufferLayout.u8("tier"),
+  Uint64Layout("pendingRewards"),
+  Uint64Layout("asOf"),
+]);
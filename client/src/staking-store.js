@@ -1,9 +1,54 @@
 import { PublicKey, SystemProgram, Transaction } from "@solana/web3.js";
 import * as Layout from "./layout";
 import { loadAccount } from "./util/account";
-import { TOKEN_PROGRAM_ID, ZERO_TS, MAX_STAKE_COUNT } from "./constants";
+import {
+  TOKEN_PROGRAM_ID,
+  ZERO_TS,
+  MAX_STAKE_COUNT,
+  CURRENT_VERSION,
+  STAKE_STORE_DISCRIMINATOR,
+} from "./constants";
 import * as instructions from "./instruction";
 import { sendAndConfirmTransaction } from "./util/send-and-confirm-transaction";
+import { getReturnData, decodeReturnData } from "./util/return-data";
+import BN from "bn.js";
+
+const u64 = (blob) => new BN(blob, "le");
+
+/**
+ * Checks an account is of the expected kind and current layout version
+ */
+const checkAccountHeader = ({ discriminator, version }, expected) => {
+  if (!Buffer.from(discriminator).equals(expected)) {
+    throw new Error(`Invalid account type, expected ${expected.toString()}`);
+  }
+  if (version !== CURRENT_VERSION) {
+    throw new Error(
+      `Account layout version ${version} must be migrated to ${CURRENT_VERSION}`
+    );
+  }
+};
+
+const findAddress = async (seeds, programId) =>
+  (await PublicKey.findProgramAddress(seeds, programId))[0];
+
+export const findTransientStakeAddress = (programId, ownerKey, mintKey) =>
+  findAddress(
+    [Buffer.from("transient"), ownerKey.toBuffer(), mintKey.toBuffer()],
+    programId
+  );
+
+export const findStakerAccountAddress = (programId, storeKey, ownerKey) =>
+  findAddress(
+    [Buffer.from("staker"), storeKey.toBuffer(), ownerKey.toBuffer()],
+    programId
+  );
+
+export const findRewardAuthorityAddress = (programId, storeKey) =>
+  findAddress([Buffer.from("rewards"), storeKey.toBuffer()], programId);
+
+export const findRewardVaultAddress = (programId, storeKey) =>
+  findAddress([Buffer.from("reward_vault"), storeKey.toBuffer()], programId);
 
 export class StakeStore {
   /**
@@ -47,15 +92,14 @@ export class StakeStore {
   static async loadStakingStore(connection, storeKey, programId) {
     const data = await loadAccount(connection, storeKey, programId);
     const stakingStoreData = Layout.StakeStoreLayout.decode(data);
+    checkAccountHeader(
+      stakingStoreData.accountHeader,
+      STAKE_STORE_DISCRIMINATOR
+    );
     if (!stakingStoreData.isInitialized) {
       throw new Error(`Invalid staking store state`);
     }
 
-    const [authorityKey] = await PublicKey.findProgramAddress(
-      [storeKey.toBuffer()],
-      programId
-    );
-
     const manager = new PublicKey(stakingStoreData.manager);
     const stakeList = new PublicKey(stakingStoreData.stakeList);
     const tokenProgramId = TOKEN_PROGRAM_ID;
@@ -64,8 +108,8 @@ export class StakeStore {
       connection,
       programId,
       storeKey,
+      stakeList,
       tokenProgramId,
-      authorityKey,
       manager
     );
   }
@@ -77,7 +121,8 @@ export class StakeStore {
     stakeListKeyPair,
     managerKeyPair,
     tokenProgramId,
-    stakingProgramId
+    stakingProgramId,
+    config = Layout.defaultPoolConfig()
   ) {
     let balanceNeeded = await StakeStore.getMinBlanaceRentForExemptStakingStore(
       connection
@@ -139,7 +184,8 @@ export class StakeStore {
       stakeStoreKeyPair.publicKey,
       stakeListKeyPair.publicKey,
       managerKeyPair.publicKey,
-      stakingProgramId
+      stakingProgramId,
+      config
     );
     transaction = new Transaction().add(instruction);
     try {
@@ -165,13 +211,19 @@ export class StakeStore {
     );
   }
 
-  stake(userKeypair, mintKeyapir, stakeKey, nftAmount) {
+  async stake(userKeypair, mintKeyapir, stakeKey, nftAmount) {
+    const stakerKey = await findStakerAccountAddress(
+      this.stakingProgramId,
+      this.stakeStoreKey,
+      userKeypair.publicKey
+    );
     const instruction = instructions.stakeInstruction(
       userKeypair.publicKey,
       mintKeyapir.publicKey,
       stakeKey,
       this.stakeStoreKey,
       this.stakeListKey,
+      stakerKey,
       this.tokenProgramId,
       nftAmount,
       this.stakingProgramId
@@ -179,17 +231,126 @@ export class StakeStore {
     return new Transaction().add(instruction);
   }
 
-  reclaim(userAccountNFT, mintAccount, stakeAccount, pdaStakeAccount) {
+  /**
+   * Returns the NFT held by `stakeKey`, paying its rewards to
+   * `rewardDestinationKey` when given. Without it the rewards follow
+   * `unsettledRewards`, credited to the staker account by default.
+   */
+  async withdraw(
+    userKeypair,
+    mintKey,
+    stakeKey,
+    rewardDestinationKey,
+    unsettledRewards = instructions.UnsettledRewards.Credit
+  ) {
+    const ownerKey = userKeypair.publicKey;
+    const pdaStakeKey = await findTransientStakeAddress(
+      this.stakingProgramId,
+      ownerKey,
+      mintKey
+    );
+    const stakerKey = await findStakerAccountAddress(
+      this.stakingProgramId,
+      this.stakeStoreKey,
+      ownerKey
+    );
+    const rewardAccounts = rewardDestinationKey && {
+      vaultKey: await findRewardVaultAddress(
+        this.stakingProgramId,
+        this.stakeStoreKey
+      ),
+      destinationKey: rewardDestinationKey,
+      authorityKey: await findRewardAuthorityAddress(
+        this.stakingProgramId,
+        this.stakeStoreKey
+      ),
+    };
+    const instruction = instructions.withdrawInstruction(
+      ownerKey,
+      mintKey,
+      this.stakeStoreKey,
+      this.stakeListKey,
+      stakeKey,
+      pdaStakeKey,
+      stakerKey,
+      this.tokenProgramId,
+      rewardAccounts,
+      unsettledRewards,
+      this.stakingProgramId
+    );
+    return new Transaction().add(instruction);
+  }
+
+  reclaim(
+    ownerKey,
+    mintKey,
+    stakeKey,
+    pdaStakeKey,
+    associatedTokenKey,
+    stakerKey
+  ) {
     const instruction = instructions.reclaimInstruction(
-      userAccountNFT,
-      mintAccount.publicKey,
+      ownerKey,
+      mintKey,
       this.stakeStoreKey,
       this.stakeListKey,
-      stakeAccount.publicKey,
-      pdaStakeAccount.publicKey,
+      stakeKey,
+      pdaStakeKey,
+      associatedTokenKey,
+      stakerKey,
       this.tokenProgramId,
       this.stakingProgramId
     );
     return new Transaction().add(instruction);
   }
+
+  /**
+   * Simulates a view instruction and returns the data it published
+   */
+  async simulateView(connection, instruction, feePayerKey) {
+    const transaction = new Transaction().add(instruction);
+    transaction.feePayer = feePayerKey;
+    const { blockhash } = await connection.getRecentBlockhash();
+    transaction.recentBlockhash = blockhash;
+    const simulation = await connection.simulateTransaction(transaction);
+    return getReturnData(simulation, this.stakingProgramId);
+  }
+
+  async getPendingRewards(connection, mintKey, feePayerKey) {
+    const instruction = instructions.getPendingRewardsInstruction(
+      mintKey,
+      this.stakeStoreKey,
+      this.stakeListKey,
+      this.stakingProgramId
+    );
+    const data = await this.simulateView(connection, instruction, feePayerKey);
+    const decoded = decodeReturnData(Layout.PendingRewardsLayout, data);
+    return {
+      owner: new PublicKey(decoded.owner),
+      tokenMint: new PublicKey(decoded.tokenMint),
+      pending: u64(decoded.pending),
+      asOf: u64(decoded.asOf).toNumber(),
+    };
+  }
+
+  async getStakeInfo(connection, mintKey, feePayerKey) {
+    const instruction = instructions.getStakeInfoInstruction(
+      mintKey,
+      this.stakeStoreKey,
+      this.stakeListKey,
+      this.stakingProgramId
+    );
+    const data = await this.simulateView(connection, instruction, feePayerKey);
+    const decoded = decodeReturnData(Layout.StakeInfoLayout, data);
+    return {
+      owner: new PublicKey(decoded.owner),
+      tokenMint: new PublicKey(decoded.tokenMint),
+      stakeTime: u64(decoded.stakeTime).toNumber(),
+      stakeAge: u64(decoded.stakeAge).toNumber(),
+      multiplierBps: decoded.multiplierBps,
+      tier: decoded.tier,
+      pendingRewards: u64(decoded.pendingRewards),
+      asOf: u64(decoded.asOf).toNumber(),
+    };
+  }
 }
@@ -0,0 +1,47 @@
+const RETURN_LOG_PREFIX = "Program return: ";
+
+/**
+ * Extracts the data returned by the program from a simulateTransaction
+ * response, from `returnData` when the RPC node reports it and from the
+ * "Program return" log line otherwise
+ */
+export const getReturnData = (simulation, programId) => {
+  const value = simulation.value || simulation;
+  if (value.err) {
+    throw new Error(`Simulation failed: ${JSON.stringify(value.err)}`);
+  }
+  const program = programId.toBase58();
+
+  if (value.returnData) {
+    if (value.returnData.programId !== program) {
+      return null;
+    }
+    return Buffer.from(value.returnData.data[0], "base64");
+  }
+
+  const logs = value.logs || [];
+  for (let i = logs.length - 1; i >= 0; i--) {
+    if (logs[i].startsWith(RETURN_LOG_PREFIX)) {
+      const [returnProgram, encoded] = logs[i]
+        .slice(RETURN_LOG_PREFIX.length)
+        .split(" ");
+      return returnProgram === program
+        ? Buffer.from(encoded || "", "base64")
+        : null;
+    }
+  }
+  return null;
+};
+
+/**
+ * Decodes return data with the layout; the runtime trims trailing zero
+ * bytes, so they are restored first
+ */
+export const decodeReturnData = (layout, data) => {
+  if (data === null || data.length > layout.span) {
+    throw new Error("Unexpected return data");
+  }
+  const padded = Buffer.alloc(layout.span);
+  data.copy(padded);
+  return layout.decode(padded);
+};
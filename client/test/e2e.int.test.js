@@ -9,6 +9,8 @@ import {
 import {
   DEFAULT_TOKEN_DECIMALS,
   MAX_STAKE_COUNT,
+  STAKE_LIST_DISCRIMINATOR,
+  STAKE_STORE_DISCRIMINATOR,
   TOKEN_PROGRAM_ID,
 } from "../src/constants";
 import {
@@ -104,6 +106,9 @@ describe("e2e test", () => {
           )
         ).data
       );
+      expect(
+        Buffer.from(stakeStoreData.accountHeader.discriminator)
+      ).toEqual(STAKE_STORE_DISCRIMINATOR);
       expect(stakeStoreData.isInitialized).toBe(1);
       expect(new PublicKey(stakeStoreData.manager).toString()).toBe(
         ownerKeyPair.publicKey.toString()
@@ -121,6 +126,9 @@ describe("e2e test", () => {
         ).data
       );
       console.log("===", stakeListData.header.count);
+      expect(
+        Buffer.from(stakeListData.accountHeader.discriminator)
+      ).toEqual(STAKE_LIST_DISCRIMINATOR);
       expect(stakeListData.header.isInitialized).toBe(1);
       expect(stakeListData.header.maxItems).toBe(MAX_STAKE_COUNT);
       expect(stakeListData.items.length).toBe(stakeStoreData.stakedCount);
@@ -234,14 +242,14 @@ describe("e2e test", () => {
     }
 
     try {
-      const transaction = stakeStore.stake(
+      const transaction = await stakeStore.stake(
         userKeyPair,
         mintNFT1,
         stakeAccountNFT1Key,
         1
       );
       transaction.add(
-        stakeStore.stake(userKeyPair, mintNFT2, stakeAccountNFT2Key, 1)
+        await stakeStore.stake(userKeyPair, mintNFT2, stakeAccountNFT2Key, 1)
       );
       await sendAndConfirmTransaction(
         "stake",
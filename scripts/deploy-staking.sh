@@ -5,7 +5,7 @@ if [ ! -d "./target/deploy" ]; then
     ./do.sh build
 fi
 
-solana_version="1.7.7"
+solana_version="1.9.29"
 
 if ! hash solana 2>/dev/null; then
     echo Installing Solana tool suite ...
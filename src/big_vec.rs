@@ -2,11 +2,17 @@
 
 use {
     arrayref::array_ref,
-    borsh::{BorshDeserialize, BorshSerialize},
+    borsh::BorshSerialize,
     solana_program::{
         program_error::ProgramError, program_memory::sol_memmove, program_pack::Pack,
     },
-    std::marker::PhantomData,
+    std::{
+        cmp::Ordering,
+        fmt,
+        marker::PhantomData,
+        ops::{Deref, DerefMut},
+        slice::ChunksExactMut,
+    },
 };
 
 /// Contains easy to use utilities for a big vector of Borsh-compatible types,
@@ -35,6 +41,15 @@ impl<'data> BigVec<'data> {
         &mut self,
         predicate: fn(&[u8], &[u8]) -> bool,
         holder_address_bytes: &[u8],
+    ) -> Result<(), ProgramError> {
+        self.retain_by::<T, _>(|slice| predicate(slice, holder_address_bytes))
+    }
+
+    /// Retain all elements whose packed bytes match the provided closure,
+    /// discard all others
+    pub fn retain_by<T: Pack, F: FnMut(&[u8]) -> bool>(
+        &mut self,
+        mut predicate: F,
     ) -> Result<(), ProgramError> {
         let mut vec_len = self.len();
         let mut removals_found = 0;
@@ -46,7 +61,7 @@ impl<'data> BigVec<'data> {
         for start_index in (data_start_index..data_end_index).step_by(T::LEN) {
             let end_index = start_index + T::LEN;
             let slice = &self.data[start_index..end_index];
-            if !predicate(slice, holder_address_bytes) {
+            if !predicate(slice) {
                 let gap = removals_found * T::LEN;
                 if removals_found > 0 {
                     // In case the compute budget is ever bumped up, allowing us
@@ -87,14 +102,37 @@ impl<'data> BigVec<'data> {
         Ok(())
     }
 
-    /// Extracts a slice of the data types
-    pub fn deserialize_mut_slice<T: Pack>(
+    /// Retain all elements for which the closure returns true once unpacked,
+    /// discard all others. Every element is unpacked before any is moved, so
+    /// the vector is left untouched if one fails to unpack
+    pub fn retain_unpacked<T: Pack, F: FnMut(&T) -> bool>(
         &mut self,
+        mut predicate: F,
+    ) -> Result<(), ProgramError> {
+        // one bit per element keeps the heap usage low on large vectors
+        let mut retained = vec![0u8; self.len() as usize / 8 + 1];
+        for (i, element) in self.iter::<T>().enumerate() {
+            if predicate(&element?) {
+                retained[i / 8] |= 1 << (i % 8);
+            }
+        }
+        let mut i = 0;
+        self.retain_by::<T, _>(|_| {
+            let retain = retained[i / 8] & (1 << (i % 8)) != 0;
+            i += 1;
+            retain
+        })
+    }
+
+    /// Extracts a slice of the data types, each element unpacked into a
+    /// guard that packs it back into the vector when committed or dropped
+    pub fn deserialize_mut_slice<'vec, T: Pack>(
+        &'vec mut self,
         skip: usize,
         len: usize,
-    ) -> Result<Vec<&'data mut T>, ProgramError> {
+    ) -> Result<Vec<BigVecEntry<'vec, T>>, ProgramError> {
         let vec_len = self.len();
-        if skip + len > vec_len as usize {
+        if skip.saturating_add(len) > vec_len as usize {
             return Err(ProgramError::AccountDataTooSmall);
         }
 
@@ -102,31 +140,42 @@ impl<'data> BigVec<'data> {
         let end_index = start_index.saturating_add(len.saturating_mul(T::LEN));
         let mut deserialized = vec![];
         for slice in self.data[start_index..end_index].chunks_exact_mut(T::LEN) {
-            deserialized.push(unsafe { &mut *(slice.as_ptr() as *mut T) });
+            deserialized.push(BigVecEntry::new(slice)?);
         }
         Ok(deserialized)
     }
 
+    /// Empty the vector, leaving the bytes past its length as they are
+    pub fn clear(&mut self) -> Result<(), ProgramError> {
+        let mut vec_len_ref = self
+            .data
+            .get_mut(0..VEC_SIZE_BYTES)
+            .ok_or(ProgramError::AccountDataTooSmall)?;
+        0u32.serialize(&mut vec_len_ref)?;
+        Ok(())
+    }
+
     /// Add new element to the end
     pub fn push<T: Pack>(&mut self, element: T) -> Result<(), ProgramError> {
-        let mut vec_len_ref = &mut self.data[0..VEC_SIZE_BYTES];
-        let mut vec_len = u32::try_from_slice(vec_len_ref)?;
+        let mut vec_len = self.len();
 
         let start_index = VEC_SIZE_BYTES + vec_len as usize * T::LEN;
         let end_index = start_index + T::LEN;
 
-        vec_len += 1;
-        vec_len.serialize(&mut vec_len_ref)?;
-
         if self.data.len() < end_index {
             return Err(ProgramError::AccountDataTooSmall);
         }
-        let mut element_ref = &mut self.data[start_index..start_index + T::LEN];
-        element.pack_into_slice(&mut element_ref);
+
+        vec_len += 1;
+        let mut vec_len_ref = &mut self.data[0..VEC_SIZE_BYTES];
+        vec_len.serialize(&mut vec_len_ref)?;
+
+        let element_ref = &mut self.data[start_index..start_index + T::LEN];
+        element.pack_into_slice(element_ref);
         Ok(())
     }
 
-    /// Get an iterator for the type provided
+    /// Get an iterator for the type provided, unpacking each element
     pub fn iter<'vec, T: Pack>(&'vec self) -> Iter<'data, 'vec, T> {
         Iter {
             len: self.len() as usize,
@@ -137,32 +186,24 @@ impl<'data> BigVec<'data> {
         }
     }
 
-    /// Get a mutable iterator for the type provided
-    pub fn iter_mut<'vec, T: Pack>(&'vec mut self) -> IterMut<'data, 'vec, T> {
+    /// Get a mutable iterator for the type provided, yielding guards which
+    /// pack each element back when dropped
+    pub fn iter_mut<'vec, T: Pack>(&'vec mut self) -> IterMut<'vec, T> {
+        let len = self.len() as usize;
+        let end_index = VEC_SIZE_BYTES.saturating_add(len.saturating_mul(T::LEN));
         IterMut {
-            len: self.len() as usize,
-            current: 0,
-            current_index: VEC_SIZE_BYTES,
-            inner: self,
+            chunks: self.data[VEC_SIZE_BYTES..end_index].chunks_exact_mut(T::LEN),
             phantom: PhantomData,
         }
     }
 
     /// Find matching data in the array
-    pub fn find<T: Pack>(&self, data: &[u8], predicate: fn(&[u8], &[u8]) -> bool) -> Option<&T> {
-        let len = self.len() as usize;
-        let mut current = 0;
-        let mut current_index = VEC_SIZE_BYTES;
-        while current != len {
-            let end_index = current_index + T::LEN;
-            let current_slice = &self.data[current_index..end_index];
-            if predicate(current_slice, data) {
-                return Some(unsafe { &*(current_slice.as_ptr() as *const T) });
-            }
-            current_index = end_index;
-            current += 1;
-        }
-        None
+    pub fn find<T: Pack>(
+        &self,
+        data: &[u8],
+        predicate: fn(&[u8], &[u8]) -> bool,
+    ) -> Result<Option<T>, ProgramError> {
+        self.find_by::<T, _>(|slice| predicate(slice, data))
     }
 
     /// Find matching data in the array
@@ -171,42 +212,283 @@ impl<'data> BigVec<'data> {
         data1: &[u8],
         data2: &[u8],
         predicate: fn(&[u8], &[u8], &[u8]) -> bool,
-    ) -> Option<&T> {
-        let len = self.len() as usize;
-        let mut current = 0;
-        let mut current_index = VEC_SIZE_BYTES;
-        while current != len {
-            let end_index = current_index + T::LEN;
-            let current_slice = &self.data[current_index..end_index];
-            if predicate(current_slice, data1, data2) {
-                return Some(unsafe { &*(current_slice.as_ptr() as *const T) });
+    ) -> Result<Option<T>, ProgramError> {
+        self.find_by::<T, _>(|slice| predicate(slice, data1, data2))
+    }
+
+    /// Find the first element whose packed bytes match the closure
+    pub fn find_by<T: Pack, F: FnMut(&[u8]) -> bool>(
+        &self,
+        predicate: F,
+    ) -> Result<Option<T>, ProgramError> {
+        match self.position::<T, _>(predicate) {
+            Some(index) => T::unpack_from_slice(self.element_slice::<T>(index)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Find the first element matching the closure once unpacked
+    pub fn find_unpacked<T: Pack, F: FnMut(&T) -> bool>(
+        &self,
+        mut predicate: F,
+    ) -> Result<Option<T>, ProgramError> {
+        for element in self.iter::<T>() {
+            let element = element?;
+            if predicate(&element) {
+                return Ok(Some(element));
             }
-            current_index = end_index;
-            current += 1;
         }
-        None
+        Ok(None)
+    }
+
+    /// Collect every element matching the closure once unpacked
+    pub fn filter_unpacked<T: Pack, F: FnMut(&T) -> bool>(
+        &self,
+        mut predicate: F,
+    ) -> Result<Vec<T>, ProgramError> {
+        let mut found = vec![];
+        for element in self.iter::<T>() {
+            let element = element?;
+            if predicate(&element) {
+                found.push(element);
+            }
+        }
+        Ok(found)
     }
 
     /// Find matching data in the array
-    pub fn find_mut<T: Pack>(
-        &mut self,
+    pub fn find_mut<'vec, T: Pack>(
+        &'vec mut self,
         data: &[u8],
         predicate: fn(&[u8], &[u8]) -> bool,
-    ) -> Option<&mut T> {
+    ) -> Result<Option<BigVecEntry<'vec, T>>, ProgramError> {
+        self.find_mut_by::<T, _>(|slice| predicate(slice, data))
+    }
+
+    /// Find the first element whose packed bytes match the closure, for
+    /// modification
+    pub fn find_mut_by<'vec, T: Pack, F: FnMut(&[u8]) -> bool>(
+        &'vec mut self,
+        predicate: F,
+    ) -> Result<Option<BigVecEntry<'vec, T>>, ProgramError> {
+        match self.position::<T, _>(predicate) {
+            Some(index) => {
+                let start_index = VEC_SIZE_BYTES + index * T::LEN;
+                BigVecEntry::new(&mut self.data[start_index..start_index + T::LEN]).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Index of the first element whose packed bytes match the closure
+    pub fn position<T: Pack, F: FnMut(&[u8]) -> bool>(&self, mut predicate: F) -> Option<usize> {
         let len = self.len() as usize;
         let mut current = 0;
         let mut current_index = VEC_SIZE_BYTES;
         while current != len {
             let end_index = current_index + T::LEN;
-            let current_slice = &self.data[current_index..end_index];
-            if predicate(current_slice, data) {
-                return Some(unsafe { &mut *(current_slice.as_ptr() as *mut T) });
+            if predicate(&self.data[current_index..end_index]) {
+                return Some(current);
             }
             current_index = end_index;
             current += 1;
         }
         None
     }
+
+    /// Index of the first element matching the closure once unpacked
+    pub fn position_unpacked<T: Pack, F: FnMut(&T) -> bool>(
+        &self,
+        mut predicate: F,
+    ) -> Result<Option<usize>, ProgramError> {
+        for (index, element) in self.iter::<T>().enumerate() {
+            if predicate(&element?) {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Unpack the element at `index`, `None` if it's out of bounds
+    pub fn get<T: Pack>(&self, index: usize) -> Result<Option<T>, ProgramError> {
+        if index >= self.len() as usize {
+            return Ok(None);
+        }
+        T::unpack_from_slice(self.element_slice::<T>(index)).map(Some)
+    }
+
+    /// Search a vector sorted by the comparator, which orders an element's
+    /// packed bytes against the target. Returns the index of a matching
+    /// element, or the index where it could be inserted to keep the order
+    pub fn binary_search_by<T: Pack, F: FnMut(&[u8]) -> Ordering>(
+        &self,
+        mut compare: F,
+    ) -> Result<usize, usize> {
+        let mut low = 0;
+        let mut high = self.len() as usize;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match compare(self.element_slice::<T>(mid)) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Ok(mid),
+            }
+        }
+        Err(low)
+    }
+
+    /// Insert an element at `index`, shifting all elements after it
+    pub fn insert<T: Pack>(&mut self, index: usize, element: T) -> Result<(), ProgramError> {
+        let mut vec_len = self.len();
+        if index > vec_len as usize {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        let start_index = VEC_SIZE_BYTES + index * T::LEN;
+        let data_end_index = VEC_SIZE_BYTES + vec_len as usize * T::LEN;
+        if self.data.len() < data_end_index + T::LEN {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+
+        if start_index < data_end_index {
+            // In case the compute budget is ever bumped up, allowing us
+            // to use this safe code instead:
+            // self.data.copy_within(start_index..data_end_index, start_index + T::LEN);
+            unsafe {
+                sol_memmove(
+                    self.data[start_index + T::LEN..data_end_index + T::LEN].as_mut_ptr(),
+                    self.data[start_index..data_end_index].as_mut_ptr(),
+                    data_end_index - start_index,
+                );
+            }
+        }
+
+        vec_len += 1;
+        let mut vec_len_ref = &mut self.data[0..VEC_SIZE_BYTES];
+        vec_len.serialize(&mut vec_len_ref)?;
+
+        element.pack_into_slice(&mut self.data[start_index..start_index + T::LEN]);
+        Ok(())
+    }
+
+    /// Sort the elements by their packed bytes in place. Heapsort, so no
+    /// copy of the vector is needed however big it is
+    pub fn sort_by<T: Pack, F: FnMut(&[u8], &[u8]) -> Ordering>(&mut self, mut compare: F) {
+        let len = self.len() as usize;
+        for root in (0..len / 2).rev() {
+            self.sift_down::<T, F>(root, len, &mut compare);
+        }
+        for end in (1..len).rev() {
+            self.swap::<T>(0, end);
+            self.sift_down::<T, F>(0, end, &mut compare);
+        }
+    }
+
+    /// Move the element at `root` down the max-heap made of the first `end`
+    /// elements
+    fn sift_down<T: Pack, F: FnMut(&[u8], &[u8]) -> Ordering>(
+        &mut self,
+        mut root: usize,
+        end: usize,
+        compare: &mut F,
+    ) {
+        loop {
+            let mut child = 2 * root + 1;
+            if child >= end {
+                break;
+            }
+            if child + 1 < end
+                && compare(self.element_slice::<T>(child), self.element_slice::<T>(child + 1))
+                    == Ordering::Less
+            {
+                child += 1;
+            }
+            if compare(self.element_slice::<T>(root), self.element_slice::<T>(child))
+                != Ordering::Less
+            {
+                break;
+            }
+            self.swap::<T>(root, child);
+            root = child;
+        }
+    }
+
+    /// Swap the elements at `a` and `b`, `a` being before `b`
+    fn swap<T: Pack>(&mut self, a: usize, b: usize) {
+        if a == b {
+            return;
+        }
+        let (front, back) = self.data.split_at_mut(VEC_SIZE_BYTES + b * T::LEN);
+        let a_index = VEC_SIZE_BYTES + a * T::LEN;
+        front[a_index..a_index + T::LEN].swap_with_slice(&mut back[..T::LEN]);
+    }
+
+    /// Packed bytes of the element at `index`, which must be in bounds
+    fn element_slice<T: Pack>(&self, index: usize) -> &[u8] {
+        let start_index = VEC_SIZE_BYTES + index * T::LEN;
+        &self.data[start_index..start_index + T::LEN]
+    }
+}
+
+/// Mutable access to a single element of a BigVec.
+///
+/// The element is unpacked with `Pack` when the guard is created, and packed
+/// back into the vector's bytes on `commit` or when the guard is dropped, so
+/// no reference into the (unaligned, Borsh-packed) account data is ever handed
+/// out.
+pub struct BigVecEntry<'vec, T: Pack> {
+    slice: &'vec mut [u8],
+    element: T,
+    write_back: bool,
+}
+
+impl<'vec, T: Pack> BigVecEntry<'vec, T> {
+    fn new(slice: &'vec mut [u8]) -> Result<Self, ProgramError> {
+        let element = T::unpack_from_slice(slice)?;
+        Ok(Self {
+            slice,
+            element,
+            write_back: true,
+        })
+    }
+
+    /// Pack the element back into the vector now
+    pub fn commit(self) {
+        drop(self)
+    }
+
+    /// Drop the guard without writing any changes back into the vector
+    pub fn discard(mut self) {
+        self.write_back = false;
+    }
+}
+
+impl<'vec, T: Pack> Deref for BigVecEntry<'vec, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.element
+    }
+}
+
+impl<'vec, T: Pack> DerefMut for BigVecEntry<'vec, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.element
+    }
+}
+
+impl<'vec, T: Pack> Drop for BigVecEntry<'vec, T> {
+    fn drop(&mut self) {
+        if self.write_back {
+            self.element.pack_into_slice(self.slice);
+        }
+    }
+}
+
+impl<'vec, T: Pack + fmt::Debug> fmt::Debug for BigVecEntry<'vec, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("BigVecEntry").field(&self.element).finish()
+    }
 }
 
 /// Iterator wrapper over a BigVec
@@ -218,17 +500,17 @@ pub struct Iter<'data, 'vec, T> {
     phantom: PhantomData<T>,
 }
 
-impl<'data, 'vec, T: Pack + 'data> Iterator for Iter<'data, 'vec, T> {
-    type Item = &'data T;
+impl<'data, 'vec, T: Pack> Iterator for Iter<'data, 'vec, T> {
+    type Item = Result<T, ProgramError>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.current == self.len {
             None
         } else {
             let end_index = self.current_index + T::LEN;
-            let value = Some(unsafe {
-                &*(self.inner.data[self.current_index..end_index].as_ptr() as *const T)
-            });
+            let value = Some(T::unpack_from_slice(
+                &self.inner.data[self.current_index..end_index],
+            ));
             self.current += 1;
             self.current_index = end_index;
             value
@@ -237,29 +519,16 @@ impl<'data, 'vec, T: Pack + 'data> Iterator for Iter<'data, 'vec, T> {
 }
 
 /// Iterator wrapper over a BigVec
-pub struct IterMut<'data, 'vec, T> {
-    len: usize,
-    current: usize,
-    current_index: usize,
-    inner: &'vec mut BigVec<'data>,
+pub struct IterMut<'vec, T> {
+    chunks: ChunksExactMut<'vec, u8>,
     phantom: PhantomData<T>,
 }
 
-impl<'data, 'vec, T: Pack + 'data> Iterator for IterMut<'data, 'vec, T> {
-    type Item = &'data mut T;
+impl<'vec, T: Pack> Iterator for IterMut<'vec, T> {
+    type Item = Result<BigVecEntry<'vec, T>, ProgramError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.current == self.len {
-            None
-        } else {
-            let end_index = self.current_index + T::LEN;
-            let value = Some(unsafe {
-                &mut *(self.inner.data[self.current_index..end_index].as_ptr() as *mut T)
-            });
-            self.current += 1;
-            self.current_index = end_index;
-            value
-        }
+        self.chunks.next().map(BigVecEntry::new)
     }
 }
 
@@ -267,7 +536,12 @@ impl<'data, 'vec, T: Pack + 'data> Iterator for IterMut<'data, 'vec, T> {
 mod tests {
     use {
         super::*,
-        solana_program::{program_memory::sol_memcmp, program_pack::Sealed},
+        crate::{state::StakedNFT, utils::DEFAULT_MULTIPLIER_BPS},
+        borsh::BorshDeserialize,
+        proptest::{collection::vec, prelude::*},
+        solana_program::{
+            borsh::get_packed_len, program_memory::sol_memcmp, program_pack::Sealed, pubkey::Pubkey,
+        },
     };
 
     #[derive(Debug, PartialEq)]
@@ -283,10 +557,12 @@ mod tests {
             let mut data = data;
             self.value.serialize(&mut data).unwrap();
         }
+        /// `u64::MAX` stands for a corrupt element
         fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
-            Ok(TestStruct {
-                value: u64::try_from_slice(src).unwrap(),
-            })
+            match u64::try_from_slice(src).unwrap() {
+                u64::MAX => Err(ProgramError::InvalidAccountData),
+                value => Ok(TestStruct { value }),
+            }
         }
     }
 
@@ -305,11 +581,12 @@ mod tests {
     }
 
     fn check_big_vec_eq(big_vec: &BigVec, slice: &[u64]) {
+        assert_eq!(big_vec.len() as usize, slice.len());
         assert!(big_vec
             .iter::<TestStruct>()
-            .map(|x| &x.value)
+            .map(|x| x.unwrap().value)
             .zip(slice.iter())
-            .all(|(a, b)| a == b));
+            .all(|(a, b)| a == *b));
     }
 
     #[test]
@@ -328,58 +605,184 @@ mod tests {
         );
     }
 
+    fn mod_2_predicate(data: &[u8]) -> bool {
+        u64::try_from_slice(data).unwrap() % 2 == 0
+    }
+
+    fn find_predicate(a: &[u8], b: &[u8]) -> bool {
+        if a.len() != b.len() {
+            false
+        } else {
+            sol_memcmp(a, b, a.len()) == 0
+        }
+    }
+
+    #[test]
+    fn retain() {
+        let mut data = [0u8; 4 + 8 * 4];
+        let mut v = from_slice(&mut data, &[1, 2, 3, 4]);
+        v.retain::<TestStruct>(find_predicate, &2u64.to_le_bytes())
+            .unwrap();
+        check_big_vec_eq(&v, &[2]);
+    }
+
+    #[test]
+    fn retain_by() {
+        let mut data = [0u8; 4 + 8 * 4];
+        let mut v = from_slice(&mut data, &[1, 2, 3, 4]);
+        v.retain_by::<TestStruct, _>(mod_2_predicate).unwrap();
+        check_big_vec_eq(&v, &[2, 4]);
+
+        let mut data = [0u8; 4 + 8 * 5];
+        let mut v = from_slice(&mut data, &[1, 2, 3, 4, 5]);
+        let mut visited = 0;
+        v.retain_by::<TestStruct, _>(|slice| {
+            visited += 1;
+            !mod_2_predicate(slice)
+        })
+        .unwrap();
+        assert_eq!(visited, 5);
+        check_big_vec_eq(&v, &[1, 3, 5]);
+    }
+
+    #[test]
+    fn retain_unpacked() {
+        let mut data = [0u8; 4 + 8 * 6];
+        let mut v = from_slice(&mut data, &[5, 1, 6, 2, 7, 3]);
+        let threshold = 4;
+        v.retain_unpacked::<TestStruct, _>(|x| x.value > threshold)
+            .unwrap();
+        check_big_vec_eq(&v, &[5, 6, 7]);
+
+        // a corrupt element fails the call before anything is moved
+        let mut data = [0u8; 4 + 8 * 4];
+        let mut v = from_slice(&mut data, &[1, 2, 3, u64::MAX]);
+        let untouched = v.data.to_vec();
+        assert_eq!(
+            v.retain_unpacked::<TestStruct, _>(|x| x.value > 2),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(v.data, &untouched[..]);
+    }
+
+    fn cmp_value(data: &[u8], value: u64) -> Ordering {
+        u64::try_from_slice(data).unwrap().cmp(&value)
+    }
+
+    #[test]
+    fn binary_search_and_insert() {
+        let mut data = [0u8; 4 + 8 * 5];
+        let mut v = from_slice(&mut data, &[2, 4, 6]);
+        assert_eq!(v.binary_search_by::<TestStruct, _>(|x| cmp_value(x, 4)), Ok(1));
+        assert_eq!(v.get::<TestStruct>(1), Ok(Some(TestStruct::new(4))));
+        assert_eq!(v.get::<TestStruct>(3), Ok(None));
+        for (value, index) in [(5, 2), (1, 0)] {
+            assert_eq!(
+                v.binary_search_by::<TestStruct, _>(|x| cmp_value(x, value)),
+                Err(index)
+            );
+            v.insert(index, TestStruct::new(value)).unwrap();
+        }
+        check_big_vec_eq(&v, &[1, 2, 4, 5, 6]);
+        assert_eq!(
+            v.insert(5, TestStruct::new(7)).unwrap_err(),
+            ProgramError::AccountDataTooSmall
+        );
+
+        let mut data = [0u8; 4 + 8 * 2];
+        let mut v = from_slice(&mut data, &[1]);
+        assert_eq!(
+            v.insert(2, TestStruct::new(2)).unwrap_err(),
+            ProgramError::InvalidArgument
+        );
+        v.insert(1, TestStruct::new(2)).unwrap();
+        check_big_vec_eq(&v, &[1, 2]);
+    }
+
     #[test]
-    // fn retain() {
-    //     fn mod_2_predicate(data: &[u8]) -> bool {
-    //         u64::try_from_slice(data).unwrap() % 2 == 0
-    //     }
-
-    //     let mut data = [0u8; 4 + 8 * 4];
-    //     let mut v = from_slice(&mut data, &[1, 2, 3, 4]);
-    //     v.retain::<TestStruct>(mod_2_predicate).unwrap();
-    //     check_big_vec_eq(&v, &[2, 4]);
-    // }
-
-    // fn find_predicate(a: &[u8], b: &[u8]) -> bool {
-    //     if a.len() != b.len() {
-    //         false
-    //     } else {
-    //         sol_memcmp(a, b, a.len()) == 0
-    //     }
-    // }
-
-    // #[test]
-    // fn find() {
-    //     let mut data = [0u8; 4 + 8 * 4];
-    //     let v = from_slice(&mut data, &[1, 2, 3, 4]);
-    //     assert_eq!(
-    //         v.find::<TestStruct>(&1u64.to_le_bytes(), find_predicate),
-    //         Some(&TestStruct::new(1))
-    //     );
-    //     assert_eq!(
-    //         v.find::<TestStruct>(&4u64.to_le_bytes(), find_predicate),
-    //         Some(&TestStruct::new(4))
-    //     );
-    //     assert_eq!(
-    //         v.find::<TestStruct>(&5u64.to_le_bytes(), find_predicate),
-    //         None
-    //     );
-    // }
-
-    // #[test]
-    // fn find_mut() {
-    //     let mut data = [0u8; 4 + 8 * 4];
-    //     let mut v = from_slice(&mut data, &[1, 2, 3, 4]);
-    //     let mut test_struct = v
-    //         .find_mut::<TestStruct>(&1u64.to_le_bytes(), find_predicate)
-    //         .unwrap();
-    //     test_struct.value = 0;
-    //     check_big_vec_eq(&v, &[0, 2, 3, 4]);
-    //     assert_eq!(
-    //         v.find_mut::<TestStruct>(&5u64.to_le_bytes(), find_predicate),
-    //         None
-    //     );
-    // }
+    fn find() {
+        let mut data = [0u8; 4 + 8 * 4];
+        let v = from_slice(&mut data, &[1, 2, 3, 4]);
+        assert_eq!(
+            v.find::<TestStruct>(&1u64.to_le_bytes(), find_predicate)
+                .unwrap(),
+            Some(TestStruct::new(1))
+        );
+        assert_eq!(
+            v.find::<TestStruct>(&4u64.to_le_bytes(), find_predicate)
+                .unwrap(),
+            Some(TestStruct::new(4))
+        );
+        assert_eq!(
+            v.find::<TestStruct>(&5u64.to_le_bytes(), find_predicate)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn find_by() {
+        let mut data = [0u8; 4 + 8 * 4];
+        let v = from_slice(&mut data, &[1, 3, 4, 6]);
+        assert_eq!(
+            v.find_by::<TestStruct, _>(mod_2_predicate).unwrap(),
+            Some(TestStruct::new(4))
+        );
+        assert_eq!(
+            v.find_unpacked::<TestStruct, _>(|x| x.value > 4).unwrap(),
+            Some(TestStruct::new(6))
+        );
+        assert_eq!(
+            v.find_unpacked::<TestStruct, _>(|x| x.value > 6).unwrap(),
+            None
+        );
+        assert_eq!(
+            v.filter_unpacked::<TestStruct, _>(|x| x.value % 3 == 0)
+                .unwrap(),
+            vec![TestStruct::new(3), TestStruct::new(6)]
+        );
+    }
+
+    #[test]
+    fn position() {
+        let mut data = [0u8; 4 + 8 * 4];
+        let v = from_slice(&mut data, &[1, 3, 4, 6]);
+        assert_eq!(v.position::<TestStruct, _>(mod_2_predicate), Some(2));
+        assert_eq!(
+            v.position::<TestStruct, _>(|slice| find_predicate(slice, &7u64.to_le_bytes())),
+            None
+        );
+        assert_eq!(
+            v.position_unpacked::<TestStruct, _>(|x| x.value == 6)
+                .unwrap(),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn find_mut() {
+        let mut data = [0u8; 4 + 8 * 4];
+        let mut v = from_slice(&mut data, &[1, 2, 3, 4]);
+        let mut test_struct = v
+            .find_mut::<TestStruct>(&1u64.to_le_bytes(), find_predicate)
+            .unwrap()
+            .unwrap();
+        test_struct.value = 0;
+        test_struct.commit();
+        check_big_vec_eq(&v, &[0, 2, 3, 4]);
+        assert!(v
+            .find_mut::<TestStruct>(&5u64.to_le_bytes(), find_predicate)
+            .unwrap()
+            .is_none());
+
+        let mut test_struct = v
+            .find_mut_by::<TestStruct, _>(|slice| !mod_2_predicate(slice))
+            .unwrap()
+            .unwrap();
+        test_struct.value = 8;
+        test_struct.commit();
+        check_big_vec_eq(&v, &[0, 2, 8, 4]);
+    }
 
     #[test]
     fn deserialize_mut_slice() {
@@ -388,6 +791,7 @@ mod tests {
         let mut slice = v.deserialize_mut_slice::<TestStruct>(1, 2).unwrap();
         slice[0].value = 10;
         slice[1].value = 11;
+        drop(slice);
         check_big_vec_eq(&v, &[1, 10, 11, 4]);
         assert_eq!(
             v.deserialize_mut_slice::<TestStruct>(1, 4).unwrap_err(),
@@ -398,4 +802,166 @@ mod tests {
             ProgramError::AccountDataTooSmall
         );
     }
+
+    #[test]
+    fn iter_mut() {
+        let mut data = [0u8; 4 + 8 * 4];
+        let mut v = from_slice(&mut data, &[1, 2, 3, 4]);
+        for entry in v.iter_mut::<TestStruct>() {
+            let mut entry = entry.unwrap();
+            entry.value *= 10;
+        }
+        check_big_vec_eq(&v, &[10, 20, 30, 40]);
+    }
+
+    #[test]
+    fn entry_commit_and_discard() {
+        let mut data = [0u8; 4 + 8 * 3];
+        let mut v = from_slice(&mut data, &[1, 2, 3]);
+        {
+            let mut slice = v.deserialize_mut_slice::<TestStruct>(0, 3).unwrap();
+            let mut third = slice.pop().unwrap();
+            let mut second = slice.pop().unwrap();
+            second.value = 20;
+            second.commit();
+            third.value = 30;
+            third.discard();
+        }
+        check_big_vec_eq(&v, &[1, 20, 3]);
+    }
+
+    #[test]
+    fn staked_nft_packed_len() {
+        assert_eq!(get_packed_len::<StakedNFT>(), StakedNFT::LEN);
+    }
+
+    #[test]
+    fn staked_nft_queries() {
+        let owner = Pubkey::new_unique();
+        let other = Pubkey::new_unique();
+        let items = [(owner, 10), (other, 5), (owner, 50), (owner, 100)]
+            .iter()
+            .map(|(owner, stake_time)| StakedNFT {
+                owner: *owner,
+                token_mint: Pubkey::new_unique(),
+                holder: Pubkey::new_unique(),
+                stake_time: *stake_time,
+                multiplier_bps: DEFAULT_MULTIPLIER_BPS,
+                reward_debt: 0,
+            })
+            .collect::<Vec<_>>();
+        let mut data = vec![0u8; 4 + StakedNFT::LEN * items.len()];
+        let mut v = BigVec { data: &mut data };
+        for item in items.iter() {
+            v.push(*item).unwrap();
+        }
+
+        // all stakes by owner staked before 60
+        let older = v
+            .filter_unpacked::<StakedNFT, _>(|x| x.owner == owner && x.stake_time < 60)
+            .unwrap();
+        assert_eq!(older, vec![items[0], items[2]]);
+
+        // same query on the packed bytes
+        let mut count = 0;
+        v.retain_by::<StakedNFT, _>(|data| {
+            let matches = StakedNFT::is_owned_by(data, owner.as_ref())
+                && StakedNFT::stake_time_from_slice(data) < 60;
+            count += matches as usize;
+            !matches
+        })
+        .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(
+            v.iter::<StakedNFT>()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            vec![items[1], items[3]]
+        );
+        assert_eq!(
+            v.position::<StakedNFT, _>(|data| StakedNFT::is_owned_by(data, owner.as_ref())),
+            Some(1)
+        );
+    }
+
+    fn arb_staked_nft() -> impl Strategy<Value = StakedNFT> {
+        (
+            any::<[u8; 32]>(),
+            any::<[u8; 32]>(),
+            any::<[u8; 32]>(),
+            any::<i64>(),
+            any::<u16>(),
+            any::<u64>(),
+        )
+            .prop_map(
+                |(owner, token_mint, holder, stake_time, multiplier_bps, reward_debt)| StakedNFT {
+                    owner: Pubkey::new_from_array(owner),
+                    token_mint: Pubkey::new_from_array(token_mint),
+                    holder: Pubkey::new_from_array(holder),
+                    stake_time,
+                    multiplier_bps,
+                    reward_debt,
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn sort_by(values in vec(any::<u64>().prop_filter("corrupt", |x| *x != u64::MAX), 0..32)) {
+            let mut data = vec![0u8; 4 + 8 * values.len()];
+            let mut v = from_slice(&mut data, &values);
+            v.sort_by::<TestStruct, _>(|a, b| {
+                u64::try_from_slice(a).unwrap().cmp(&u64::try_from_slice(b).unwrap())
+            });
+            let mut sorted = values.clone();
+            sorted.sort_unstable();
+            check_big_vec_eq(&v, &sorted);
+        }
+
+        #[test]
+        fn staked_nft_round_trip(items in vec(arb_staked_nft(), 0..16)) {
+            let mut data = vec![0u8; 4 + StakedNFT::LEN * items.len()];
+            let mut v = BigVec { data: &mut data };
+            for item in items.iter() {
+                v.push(*item).unwrap();
+            }
+
+            // every element reads back exactly as `Pack` wrote it
+            for (index, item) in v.iter::<StakedNFT>().enumerate() {
+                let item = item.unwrap();
+                prop_assert_eq!(item, items[index]);
+                let mut packed = vec![0u8; StakedNFT::LEN];
+                items[index].pack_into_slice(&mut packed);
+                let start = 4 + index * StakedNFT::LEN;
+                prop_assert_eq!(&v.data[start..start + StakedNFT::LEN], &packed[..]);
+            }
+        }
+
+        #[test]
+        fn staked_nft_entry_write_back(
+            items in vec(arb_staked_nft(), 1..16),
+            replacement in arb_staked_nft(),
+            index in any::<prop::sample::Index>(),
+        ) {
+            let index = index.index(items.len());
+            let mut data = vec![0u8; 4 + StakedNFT::LEN * items.len()];
+            let mut v = BigVec { data: &mut data };
+            for item in items.iter() {
+                v.push(*item).unwrap();
+            }
+
+            {
+                let mut slice = v.deserialize_mut_slice::<StakedNFT>(index, 1).unwrap();
+                *slice[0] = replacement;
+            }
+
+            let mut expected = items.clone();
+            expected[index] = replacement;
+            let actual = v
+                .iter::<StakedNFT>()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            prop_assert_eq!(actual, expected);
+        }
+    }
 }
@@ -0,0 +1,43 @@
+//! Helpers for other programs to check staking status through a CPI.
+//!
+//! Depend on this crate with the `no-entrypoint` feature and invoke
+//! `verify_stake` before unlocking a feature; the call fails with
+//! `NFTStakingError::StakedNFTNotFound` unless the owner has the mint staked.
+//! A failed CPI aborts the whole transaction, so the check can't be used to
+//! branch on. No account is modified, every account may be passed read-only.
+
+use crate::instruction;
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, program::invoke};
+
+/// Accounts of a `VerifyStake` CPI
+pub struct VerifyStake<'a, 'info> {
+    /// The staking program
+    pub program: &'a AccountInfo<'info>,
+    /// Wallet expected to have the NFT staked, doesn't need to sign
+    pub owner: &'a AccountInfo<'info>,
+    pub mint: &'a AccountInfo<'info>,
+    pub stake_store: &'a AccountInfo<'info>,
+    pub stake_list: &'a AccountInfo<'info>,
+}
+
+/// Invokes `VerifyStake`, failing unless the owner has the mint staked in
+/// the stake store
+pub fn verify_stake(accounts: VerifyStake) -> ProgramResult {
+    invoke(
+        &instruction::verify_stake(
+            accounts.program.key,
+            accounts.owner.key,
+            accounts.mint.key,
+            accounts.stake_store.key,
+            accounts.stake_list.key,
+        )?,
+        &[
+            accounts.owner.clone(),
+            accounts.mint.clone(),
+            accounts.stake_store.clone(),
+            accounts.stake_list.clone(),
+            accounts.program.clone(),
+        ],
+    )
+}
+
@@ -30,6 +30,128 @@ pub enum NFTStakingError {
     /// The deserialization of the account returned something besides State::Account.
     #[error("Deserialized account is not an SPL Token account")]
     ExpectedAccount,
+    /// Points ledger does not belong to this owner and stake store.
+    #[error("InvalidStakerAccount")]
+    InvalidStakerAccount,
+    /// Account isn't the pool's manager, holding the admin role.
+    #[error("InvalidManager")]
+    InvalidManager,
+    /// Invalid rarity table account.
+    #[error("InvalidRarityTable")]
+    InvalidRarityTable,
+    /// Rarity multiplier must be greater than zero.
+    #[error("InvalidRarityMultiplier")]
+    InvalidRarityMultiplier,
+    /// The mint isn't part of the stake store's allow-list.
+    #[error("InvalidAllowListProof")]
+    InvalidAllowListProof,
+    /// The token account doesn't hold exactly one of the deposited mint for the depositor.
+    #[error("MintMismatch")]
+    MintMismatch,
+    /// Metadata account isn't the token-metadata PDA of the mint.
+    #[error("InvalidMetadataAccount")]
+    InvalidMetadataAccount,
+    /// The NFT doesn't belong to the required collection.
+    #[error("CollectionMismatch")]
+    CollectionMismatch,
+    /// The NFT's collection hasn't been verified by the collection authority.
+    #[error("UnverifiedCollection")]
+    UnverifiedCollection,
+    /// The required creator isn't listed in the NFT's creators.
+    #[error("CreatorMismatch")]
+    CreatorMismatch,
+    /// The required creator hasn't verified the NFT.
+    #[error("UnverifiedCreator")]
+    UnverifiedCreator,
+    /// Reward vault doesn't match the stake store's reward vault.
+    #[error("InvalidRewardVault")]
+    InvalidRewardVault,
+    /// Reward epochs must last at least a second.
+    #[error("InvalidRewardEpoch")]
+    InvalidRewardEpoch,
+    /// Withdrawal would take rewards owed to stakers.
+    #[error("InsufficientExcessRewards")]
+    InsufficientExcessRewards,
+    /// Reward accounts are required to settle rewards.
+    #[error("MissingRewardAccounts")]
+    MissingRewardAccounts,
+    /// Emission segments must start in the future, in order, before the end.
+    #[error("InvalidEmissionSchedule")]
+    InvalidEmissionSchedule,
+    /// The pool is being decommissioned and takes no new deposits.
+    #[error("PoolSunset")]
+    PoolSunset,
+    /// The pool can't be closed while NFTs are staked.
+    #[error("PoolNotEmpty")]
+    PoolNotEmpty,
+    /// The reward vault must be emptied before the pool is closed.
+    #[error("RewardVaultNotEmpty")]
+    RewardVaultNotEmpty,
+    /// The owner already has as many NFTs staked as a single owner may.
+    #[error("OwnerStakeLimitExceeded")]
+    OwnerStakeLimitExceeded,
+    /// The pool already holds as many NFTs as it's capped at.
+    #[error("PoolCapacityExceeded")]
+    PoolCapacityExceeded,
+    /// Stake tier thresholds must be positive and increasing.
+    #[error("InvalidStakeTiers")]
+    InvalidStakeTiers,
+    /// Tier record isn't the PDA of the stake store and mint.
+    #[error("InvalidTierRecord")]
+    InvalidTierRecord,
+    /// The account isn't of the expected kind.
+    #[error("InvalidAccountType")]
+    InvalidAccountType,
+    /// The account has an older layout and must be migrated first.
+    #[error("AccountNeedsMigration")]
+    AccountNeedsMigration,
+    /// The pool settings are out of range.
+    #[error("InvalidPoolConfig")]
+    InvalidPoolConfig,
+    /// The fee settings are incomplete or out of range.
+    #[error("InvalidFeeConfig")]
+    InvalidFeeConfig,
+    /// Deposits, withdrawals and claims are halted.
+    #[error("PoolPaused")]
+    PoolPaused,
+    /// The NFT can't be withdrawn before its lockup ends.
+    #[error("StakeLocked")]
+    StakeLocked,
+    /// The fee treasury doesn't match the pool's fee settings.
+    #[error("InvalidTreasury")]
+    InvalidTreasury,
+    /// The NFT hasn't been staked for the pool's reclaim timeout.
+    #[error("ReclaimTooEarly")]
+    ReclaimTooEarly,
+    /// The destination isn't the owner's associated token account.
+    #[error("InvalidAssociatedTokenAccount")]
+    InvalidAssociatedTokenAccount,
+    /// Account isn't the pool's reward operator.
+    #[error("InvalidRewardOperator")]
+    InvalidRewardOperator,
+    /// Account isn't the pool's pauser.
+    #[error("InvalidPauser")]
+    InvalidPauser,
+    /// No config change has been proposed.
+    #[error("NoPendingConfig")]
+    NoPendingConfig,
+    /// The pending config can't be applied before its delay has passed.
+    #[error("ConfigTimelocked")]
+    ConfigTimelocked,
+    /// The config can't be set directly while the pool has a config delay or
+    /// a change pending.
+    #[error("ConfigChangeNeedsProposal")]
+    ConfigChangeNeedsProposal,
+    /// The owner has no staker account to credit their rewards to.
+    #[error("MissingStakerAccount")]
+    MissingStakerAccount,
+    /// The reward rate can't change before the pool's config delay has
+    /// passed.
+    #[error("RewardRateTimelocked")]
+    RewardRateTimelocked,
+    /// The rent destination doesn't belong to the manager.
+    #[error("InvalidRentDestination")]
+    InvalidRentDestination,
 }
 
 impl From<NFTStakingError> for ProgramError {
@@ -1,37 +1,329 @@
-use crate::error::NFTStakingError::InvalidInstruction;
+use crate::{
+    error::NFTStakingError::InvalidInstruction, find_reward_authority_address,
+    find_reward_vault_address, find_staker_account_address, find_tier_record_address,
+    find_transient_stake_address,
+    merkle::HASH_BYTES,
+    state::{EmissionSegment, FeeConfig, PoolConfig, RarityEntry, Role},
+    utils::get_associated_token_address,
+};
+use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
+    clock::UnixTimestamp,
     instruction::{AccountMeta, Instruction},
     msg,
     program_error::ProgramError,
     pubkey::Pubkey,
+    system_program,
     sysvar::{clock, rent},
 };
 use std::convert::TryInto;
 use std::mem::size_of;
 
+/// Number of rarity entries that fit in a single transaction
+pub const MAX_RARITY_ENTRIES_PER_UPLOAD: usize = 25;
+
 #[repr(C)]
 #[derive(Debug, PartialEq)]
 pub struct DepositNFTData {
+    /// Kept for compatibility, a deposit always stakes the single NFT held by
+    /// the token account
     pub amount: u64,
+    /// Proof that the mint is in the allow-list, required once the stake
+    /// store has an allow-list root
+    pub proof: Option<AllowListProof>,
+}
+
+/// Merkle proof of an allow-list entry, see `merkle`
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct AllowListProof {
+    /// Rarity weight committed in the leaf, taking precedence over the
+    /// rarity table
+    pub multiplier_bps: Option<u16>,
+    pub proof: Vec<[u8; HASH_BYTES]>,
+}
+
+/// Accounts paying a deposit or withdrawal fee, required unless the pool
+/// charges none or the owner is exempt
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeePayment {
+    /// Lamports transferred from the owner's wallet
+    Lamports { treasury: Pubkey },
+    /// Tokens of the fee mint transferred from the owner's token account
+    Token { source: Pubkey, treasury: Pubkey },
+}
+
+impl FeePayment {
+    /// The treasury followed by the system program for lamport fees, or by
+    /// the owner's token account for token fees
+    fn account_metas(&self) -> [AccountMeta; 2] {
+        match self {
+            Self::Lamports { treasury } => [
+                AccountMeta::new(*treasury, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+            Self::Token { source, treasury } => [
+                AccountMeta::new(*treasury, false),
+                AccountMeta::new(*source, false),
+            ],
+        }
+    }
+}
+
+/// What a withdrawal does with the stake's rewards when the reward accounts
+/// aren't supplied
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnsettledRewards {
+    /// Credit them to the owner's points ledger, to be paid out by
+    /// `ClaimCreditedRewards`
+    Credit,
+    /// Abort the withdrawal
+    Abort,
+    /// Forfeit them to the reward vault
+    Forfeit,
+}
+
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub struct WithdrawNFTData {
+    pub unsettled_rewards: UnsettledRewards,
+}
+
+/// Return data of `GetPendingRewards`
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct PendingRewards {
+    pub owner: Pubkey,
+    pub token_mint: Pubkey,
+    /// Rewards the stake could claim at `as_of`
+    pub pending: u64,
+    pub as_of: UnixTimestamp,
+}
+
+/// Return data of `GetStakeInfo`
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct StakeInfo {
+    pub owner: Pubkey,
+    pub token_mint: Pubkey,
+    pub stake_time: UnixTimestamp,
+    /// Seconds staked until `as_of`
+    pub stake_age: u64,
+    pub multiplier_bps: u16,
+    pub tier: u8,
+    pub pending_rewards: u64,
+    pub as_of: UnixTimestamp,
 }
 
 #[repr(C)]
 #[derive(Debug, PartialEq)]
 pub enum NFTStakingInstruction {
-    Initialize,
+    /// Creates a pool with the given settings, a default config lets the
+    /// stake list hold as many NFTs as its account fits
+    Initialize { config: PoolConfig },
     DepositNFT(DepositNFTData),
-    WithdrawNFT,
+    /// Returns the NFT to its owner, paying out its rewards in the same
+    /// instruction when the reward accounts are supplied, see
+    /// `UnsettledRewards` otherwise
+    WithdrawNFT(WithdrawNFTData),
+    /// Accrues an owner's points ledger up to the current clock, recounting
+    /// their active stakes from the stake list. Anyone may crank it.
+    Checkpoint,
+    /// Attaches an empty rarity table to the stake store
+    InitializeRarityTable,
+    /// Inserts or updates rarity multipliers, uploaded in chunks
+    UploadRarities(Vec<RarityEntry>),
+    /// Sets the Merkle root of the allow-list, all zeroes to allow any mint
+    SetAllowListRoot([u8; HASH_BYTES]),
+    /// Sets the Metaplex collection and creator deposits must be verified
+    /// by, `Pubkey::default()` to lift either requirement. The collection is
+    /// part of the config, see `ProposeConfig`
+    SetMetadataRequirements {
+        required_collection: Pubkey,
+        required_creator: Pubkey,
+    },
+    /// Transfers a reward budget into the reward vault, emitted pro rata
+    /// to the active shares over the next `duration` seconds together with
+    /// anything left over from the current epoch. Rejected while the pool
+    /// has a config delay, see `AppendEmissionSegments`
+    FundRewards { amount: u64, duration: u64 },
+    /// Pays out the rewards a single stake has earned since its last claim,
+    /// recording the claim on the owner's staker account when it follows
+    ClaimRewards,
+    /// Tops up the reward vault without changing the current epoch,
+    /// creating the vault on first use
+    FundRewardVault { amount: u64 },
+    /// Withdraws vault tokens that aren't owed to stakers
+    WithdrawExcessRewards { amount: u64 },
+    /// Appends future rate changes to the emission schedule and moves its
+    /// end, leaving the rewards accrued so far untouched. Changes can't take
+    /// effect before the pool's config delay has passed
+    AppendEmissionSegments {
+        segments: Vec<EmissionSegment>,
+        end_ts: UnixTimestamp,
+    },
+    /// Stops new deposits for good, withdrawals and claims go on
+    Sunset,
+    /// Closes an empty pool with no rewards left to claim, returning the
+    /// rent of its accounts to the manager or one of its multisig members
+    ClosePool,
+    /// Caps the NFTs staked per owner and in the whole pool, 0 to lift
+    /// either cap. Rejected once config changes must be proposed
+    SetStakeLimits { max_per_owner: u32, max_total: u32 },
+    /// Replaces the stake durations, in seconds, unlocking each tier.
+    /// Rejected once config changes must be proposed
+    SetStakeTiers { thresholds: Vec<u64> },
+    /// Records the tier a staked NFT has reached, or resets the record once
+    /// the NFT has been withdrawn; anyone may crank it
+    RecordTier,
+    /// Succeeds only if the owner has the mint staked in the stake store,
+    /// without modifying any account; meant to be invoked by other programs
+    VerifyStake,
+    /// Publishes the rewards a staked NFT could claim as `PendingRewards`
+    /// return data, without modifying any account
+    GetPendingRewards,
+    /// Publishes the stake of an NFT as `StakeInfo` return data, without
+    /// modifying any account
+    GetStakeInfo,
+    /// Upgrades the stake store and stake list to the current layout, the
+    /// manager pays for any extra rent. A stake list growing by more than
+    /// an instruction allows is grown over several calls before it's
+    /// rewritten. Points ledgers, tier records and the rarity table of the
+    /// pool may be passed after them to be upgraded too
+    Migrate,
+    /// Halts or resumes deposits, withdrawals and claims
+    SetPaused { paused: bool },
+    /// Replaces the deposit and withdrawal fees, their treasury and the
+    /// wallets exempt from them. Rejected once config changes must be
+    /// proposed
+    SetFees { fees: FeeConfig },
+    /// Returns an NFT whose owner hasn't deposited, withdrawn or claimed for
+    /// the reclaim timeout to the owner's associated token account and drops its entry. Anyone may
+    /// crank it, earning a bounty out of the stake's rewards, in which case
+    /// the reward accounts are required. The rest of the rewards is credited
+    /// to the owner's staker account
+    Reclaim,
+    /// Hands a role to another key, signed by the manager.
+    /// `Pubkey::default()` returns the reward operator or pauser role to the
+    /// manager
+    SetAuthority { role: Role, authority: Pubkey },
+    /// Proposes a config taking effect once the current config delay has
+    /// passed, replacing any change still pending. The emission schedule,
+    /// pause state and roles are kept as they are when it's applied. While
+    /// the pool has a config delay or a change pending, proposals are the
+    /// only way to change the config
+    ProposeConfig { config: PoolConfig },
+    /// Applies the pending config once its delay has passed; anyone may
+    /// crank it
+    ApplyPendingConfig,
+    /// Drops the pending config
+    CancelPendingConfig,
+    /// Pays out the rewards withdrawals credited to the owner's points
+    /// ledger
+    ClaimCreditedRewards,
 }
 
 impl NFTStakingInstruction {
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
         let (tag, rest) = input.split_first().ok_or(InvalidInstruction)?;
         Ok(match tag {
-            0 => Self::Initialize,
-            1 => Self::DepositNFT(DepositNFTData {
+            0 => {
+                // clients predating the config send the tag alone
+                let config = match rest {
+                    [] => PoolConfig::default(),
+                    config => PoolConfig::try_from_slice(config)?,
+                };
+                Self::Initialize { config }
+            }
+            1 => {
+                let amount = Self::unpack_u64(rest)?;
+                let proof = match &rest[8..] {
+                    [] => None,
+                    proof => Some(AllowListProof::try_from_slice(proof)?),
+                };
+                Self::DepositNFT(DepositNFTData { amount, proof })
+            }
+            2 => {
+                let unsettled_rewards = match rest.first() {
+                    None | Some(0) => UnsettledRewards::Credit,
+                    Some(1) => UnsettledRewards::Abort,
+                    Some(2) => UnsettledRewards::Forfeit,
+                    Some(_) => return Err(InvalidInstruction.into()),
+                };
+                Self::WithdrawNFT(WithdrawNFTData { unsettled_rewards })
+            }
+            3 => Self::Checkpoint,
+            4 => Self::InitializeRarityTable,
+            5 => Self::UploadRarities(Vec::<RarityEntry>::try_from_slice(rest)?),
+            6 => Self::SetAllowListRoot(
+                rest.get(..HASH_BYTES)
+                    .and_then(|slice| slice.try_into().ok())
+                    .ok_or(InvalidInstruction)?,
+            ),
+            7 => {
+                let (required_collection, rest) = Self::unpack_pubkey(rest)?;
+                let (required_creator, _rest) = Self::unpack_pubkey(rest)?;
+                Self::SetMetadataRequirements {
+                    required_collection,
+                    required_creator,
+                }
+            }
+            8 => {
+                let amount = Self::unpack_u64(rest)?;
+                let duration = Self::unpack_u64(rest.get(8..).ok_or(InvalidInstruction)?)?;
+                Self::FundRewards { amount, duration }
+            }
+            9 => Self::ClaimRewards,
+            10 => Self::FundRewardVault {
                 amount: Self::unpack_u64(rest)?,
-            }),
-            2 => Self::WithdrawNFT,
+            },
+            11 => Self::WithdrawExcessRewards {
+                amount: Self::unpack_u64(rest)?,
+            },
+            12 => {
+                let (segments, end_ts) =
+                    <(Vec<EmissionSegment>, UnixTimestamp)>::try_from_slice(rest)?;
+                Self::AppendEmissionSegments { segments, end_ts }
+            }
+            13 => Self::Sunset,
+            14 => Self::ClosePool,
+            15 => {
+                let max_per_owner = Self::unpack_u32(rest)?;
+                let max_total = Self::unpack_u32(rest.get(4..).ok_or(InvalidInstruction)?)?;
+                Self::SetStakeLimits {
+                    max_per_owner,
+                    max_total,
+                }
+            }
+            16 => {
+                let thresholds = Vec::<u64>::try_from_slice(rest)?;
+                Self::SetStakeTiers { thresholds }
+            }
+            17 => Self::RecordTier,
+            18 => Self::VerifyStake,
+            19 => Self::GetPendingRewards,
+            20 => Self::GetStakeInfo,
+            21 => Self::Migrate,
+            22 => {
+                let paused = match rest.first() {
+                    Some(0) => false,
+                    Some(1) => true,
+                    _ => return Err(InvalidInstruction.into()),
+                };
+                Self::SetPaused { paused }
+            }
+            23 => Self::SetFees {
+                fees: FeeConfig::try_from_slice(rest)?,
+            },
+            24 => Self::Reclaim,
+            25 => {
+                let (role, authority) = <(Role, Pubkey)>::try_from_slice(rest)?;
+                Self::SetAuthority { role, authority }
+            }
+            26 => Self::ProposeConfig {
+                config: PoolConfig::try_from_slice(rest)?,
+            },
+            27 => Self::ApplyPendingConfig,
+            28 => Self::CancelPendingConfig,
+            29 => Self::ClaimCreditedRewards,
             _ => return Err(InvalidInstruction.into()),
         })
     }
@@ -45,6 +337,23 @@ impl NFTStakingInstruction {
         Ok(amount)
     }
 
+    fn unpack_u32(input: &[u8]) -> Result<u32, ProgramError> {
+        input
+            .get(..4)
+            .and_then(|slice| slice.try_into().ok())
+            .map(u32::from_le_bytes)
+            .ok_or_else(|| InvalidInstruction.into())
+    }
+
+    fn unpack_pubkey(input: &[u8]) -> Result<(Pubkey, &[u8]), ProgramError> {
+        if input.len() < 32 {
+            msg!("Pubkey cannot be unpacked");
+            return Err(InvalidInstruction.into());
+        }
+        let (key, rest) = input.split_at(32);
+        Ok((Pubkey::new(key), rest))
+    }
+
     // fn unpack_u16(input: &[u8]) -> Result<u16, ProgramError> {
     //     let max_items = input
     //         .get(..8)
@@ -56,13 +365,97 @@ impl NFTStakingInstruction {
 
     pub fn pack(&self) -> Vec<u8> {
         let mut buf = Vec::with_capacity(size_of::<Self>());
-        match *self {
-            Self::Initialize => buf.push(0),
-            Self::DepositNFT(DepositNFTData { amount }) => {
+        match self {
+            Self::Initialize { config } => {
+                buf.push(0);
+                buf.extend_from_slice(&config.try_to_vec().unwrap());
+            }
+            Self::DepositNFT(DepositNFTData { amount, proof }) => {
                 buf.push(1);
                 buf.extend_from_slice(&amount.to_le_bytes());
+                if let Some(proof) = proof {
+                    buf.extend_from_slice(&proof.try_to_vec().unwrap());
+                }
             }
-            Self::WithdrawNFT => buf.push(1),
+            Self::WithdrawNFT(WithdrawNFTData { unsettled_rewards }) => {
+                buf.push(2);
+                buf.push(*unsettled_rewards as u8);
+            }
+            Self::Checkpoint => buf.push(3),
+            Self::InitializeRarityTable => buf.push(4),
+            Self::UploadRarities(entries) => {
+                buf.push(5);
+                buf.extend_from_slice(&entries.try_to_vec().unwrap());
+            }
+            Self::SetAllowListRoot(root) => {
+                buf.push(6);
+                buf.extend_from_slice(root);
+            }
+            Self::SetMetadataRequirements {
+                required_collection,
+                required_creator,
+            } => {
+                buf.push(7);
+                buf.extend_from_slice(required_collection.as_ref());
+                buf.extend_from_slice(required_creator.as_ref());
+            }
+            Self::FundRewards { amount, duration } => {
+                buf.push(8);
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&duration.to_le_bytes());
+            }
+            Self::ClaimRewards => buf.push(9),
+            Self::FundRewardVault { amount } => {
+                buf.push(10);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::WithdrawExcessRewards { amount } => {
+                buf.push(11);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::AppendEmissionSegments { segments, end_ts } => {
+                buf.push(12);
+                buf.extend_from_slice(&(segments, end_ts).try_to_vec().unwrap());
+            }
+            Self::Sunset => buf.push(13),
+            Self::ClosePool => buf.push(14),
+            Self::SetStakeLimits {
+                max_per_owner,
+                max_total,
+            } => {
+                buf.push(15);
+                buf.extend_from_slice(&max_per_owner.to_le_bytes());
+                buf.extend_from_slice(&max_total.to_le_bytes());
+            }
+            Self::SetStakeTiers { thresholds } => {
+                buf.push(16);
+                buf.extend_from_slice(&thresholds.try_to_vec().unwrap());
+            }
+            Self::RecordTier => buf.push(17),
+            Self::VerifyStake => buf.push(18),
+            Self::GetPendingRewards => buf.push(19),
+            Self::GetStakeInfo => buf.push(20),
+            Self::Migrate => buf.push(21),
+            Self::SetPaused { paused } => {
+                buf.push(22);
+                buf.push(*paused as u8);
+            }
+            Self::SetFees { fees } => {
+                buf.push(23);
+                buf.extend_from_slice(&fees.try_to_vec().unwrap());
+            }
+            Self::Reclaim => buf.push(24),
+            Self::SetAuthority { role, authority } => {
+                buf.push(25);
+                buf.extend_from_slice(&(role, authority).try_to_vec().unwrap());
+            }
+            Self::ProposeConfig { config } => {
+                buf.push(26);
+                buf.extend_from_slice(&config.try_to_vec().unwrap());
+            }
+            Self::ApplyPendingConfig => buf.push(27),
+            Self::CancelPendingConfig => buf.push(28),
+            Self::ClaimCreditedRewards => buf.push(29),
         }
         buf
     }
@@ -74,8 +467,9 @@ pub fn initialize(
     stake_store_pubkey: &Pubkey,
     stake_list_pubkey: &Pubkey,
     owner_pubkey: &Pubkey,
+    config: PoolConfig,
 ) -> Result<Instruction, ProgramError> {
-    let data = NFTStakingInstruction::Initialize.pack();
+    let data = NFTStakingInstruction::Initialize { config }.pack();
     let accounts = vec![
         AccountMeta::new(*stake_store_pubkey, false),
         AccountMeta::new(*stake_list_pubkey, false),
@@ -90,6 +484,7 @@ pub fn initialize(
 }
 
 /// creates a 'deposit_nft' instruction
+#[allow(clippy::too_many_arguments)]
 pub fn deposit_nft(
     program_id: &Pubkey,
     depositor_pubkey: &Pubkey,
@@ -97,20 +492,788 @@ pub fn deposit_nft(
     stake_pubkey: &Pubkey,
     stake_store_pubkey: &Pubkey,
     stake_list_pubkey: &Pubkey,
+    metadata_pubkey: Option<&Pubkey>,
+    rarity_table_pubkey: Option<&Pubkey>,
     amount: u64,
+    proof: Option<AllowListProof>,
+    fee_payment: Option<&FeePayment>,
 ) -> Result<Instruction, ProgramError> {
-    let data = NFTStakingInstruction::DepositNFT(DepositNFTData { amount }).pack();
-    let accounts = vec![
+    let data = NFTStakingInstruction::DepositNFT(DepositNFTData { amount, proof }).pack();
+    let (staker_pubkey, _) =
+        find_staker_account_address(program_id, stake_store_pubkey, depositor_pubkey);
+    let mut accounts = vec![
         AccountMeta::new(*depositor_pubkey, true),
         AccountMeta::new(*mint_pubkey, false),
         AccountMeta::new(clock::id(), false),
         AccountMeta::new(*stake_pubkey, false),
         AccountMeta::new(*stake_store_pubkey, false),
         AccountMeta::new(*stake_list_pubkey, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new(staker_pubkey, false),
+        AccountMeta::new_readonly(system_program::id(), false),
     ];
+    if let Some(metadata_pubkey) = metadata_pubkey {
+        accounts.push(AccountMeta::new_readonly(*metadata_pubkey, false));
+    }
+    if let Some(rarity_table_pubkey) = rarity_table_pubkey {
+        accounts.push(AccountMeta::new_readonly(*rarity_table_pubkey, false));
+    }
+    if let Some(fee_payment) = fee_payment {
+        accounts.extend_from_slice(&fee_payment.account_metas());
+    }
     Ok(Instruction {
         program_id: *program_id,
         accounts,
         data,
     })
 }
+
+/// creates a 'withdraw_nft' instruction, settling the stake's rewards into
+/// `reward_destination_pubkey` when given
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_nft(
+    program_id: &Pubkey,
+    withdrawer_pubkey: &Pubkey,
+    mint_pubkey: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    stake_list_pubkey: &Pubkey,
+    stake_pubkey: &Pubkey,
+    reward_destination_pubkey: Option<&Pubkey>,
+    unsettled_rewards: UnsettledRewards,
+    fee_payment: Option<&FeePayment>,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::WithdrawNFT(WithdrawNFTData { unsettled_rewards }).pack();
+    let (pda, _) = find_transient_stake_address(program_id, withdrawer_pubkey, mint_pubkey);
+    let (staker_pubkey, _) =
+        find_staker_account_address(program_id, stake_store_pubkey, withdrawer_pubkey);
+    let mut accounts = vec![
+        AccountMeta::new(*withdrawer_pubkey, true),
+        AccountMeta::new_readonly(*mint_pubkey, false),
+        AccountMeta::new_readonly(clock::id(), false),
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new(*stake_list_pubkey, false),
+        AccountMeta::new(*stake_pubkey, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new(staker_pubkey, false),
+    ];
+    if let Some(fee_payment) = fee_payment {
+        accounts.extend_from_slice(&fee_payment.account_metas());
+    }
+    if let Some(reward_destination_pubkey) = reward_destination_pubkey {
+        let (reward_vault_pubkey, _) = find_reward_vault_address(program_id, stake_store_pubkey);
+        let (reward_authority_pubkey, _) =
+            find_reward_authority_address(program_id, stake_store_pubkey);
+        accounts.push(AccountMeta::new(reward_vault_pubkey, false));
+        accounts.push(AccountMeta::new(*reward_destination_pubkey, false));
+        accounts.push(AccountMeta::new_readonly(reward_authority_pubkey, false));
+    }
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'checkpoint' instruction
+pub fn checkpoint(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    stake_list_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::Checkpoint.pack();
+    let (staker_pubkey, _) =
+        find_staker_account_address(program_id, stake_store_pubkey, owner_pubkey);
+    let accounts = vec![
+        AccountMeta::new(staker_pubkey, false),
+        AccountMeta::new_readonly(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*stake_list_pubkey, false),
+        AccountMeta::new_readonly(clock::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'initialize_rarity_table' instruction
+pub fn initialize_rarity_table(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    rarity_table_pubkey: &Pubkey,
+    manager_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::InitializeRarityTable.pack();
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new(*rarity_table_pubkey, false),
+        AccountMeta::new_readonly(*manager_pubkey, true),
+        AccountMeta::new_readonly(rent::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'upload_rarities' instruction
+pub fn upload_rarities(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    rarity_table_pubkey: &Pubkey,
+    manager_pubkey: &Pubkey,
+    entries: Vec<RarityEntry>,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::UploadRarities(entries).pack();
+    let accounts = vec![
+        AccountMeta::new_readonly(*stake_store_pubkey, false),
+        AccountMeta::new(*rarity_table_pubkey, false),
+        AccountMeta::new_readonly(*manager_pubkey, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates the 'upload_rarities' instructions for a whole table, each
+/// carrying at most `MAX_RARITY_ENTRIES_PER_UPLOAD` entries. Entries are
+/// sent in mint order so each chunk lands at the end of the sorted table
+pub fn upload_rarities_in_chunks(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    rarity_table_pubkey: &Pubkey,
+    manager_pubkey: &Pubkey,
+    entries: &[RarityEntry],
+) -> Result<Vec<Instruction>, ProgramError> {
+    let mut entries = entries.to_vec();
+    entries.sort_unstable_by_key(|entry| entry.mint);
+    entries
+        .chunks(MAX_RARITY_ENTRIES_PER_UPLOAD)
+        .map(|chunk| {
+            upload_rarities(
+                program_id,
+                stake_store_pubkey,
+                rarity_table_pubkey,
+                manager_pubkey,
+                chunk.to_vec(),
+            )
+        })
+        .collect()
+}
+
+/// creates a 'set_allow_list_root' instruction
+pub fn set_allow_list_root(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    manager_pubkey: &Pubkey,
+    root: [u8; HASH_BYTES],
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::SetAllowListRoot(root).pack();
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*manager_pubkey, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'set_metadata_requirements' instruction
+pub fn set_metadata_requirements(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    manager_pubkey: &Pubkey,
+    required_collection: Option<&Pubkey>,
+    required_creator: Option<&Pubkey>,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::SetMetadataRequirements {
+        required_collection: required_collection.copied().unwrap_or_default(),
+        required_creator: required_creator.copied().unwrap_or_default(),
+    }
+    .pack();
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*manager_pubkey, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'fund_rewards' instruction, the reward vault must exist
+pub fn fund_rewards(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    reward_operator_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    amount: u64,
+    duration: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::FundRewards { amount, duration }.pack();
+    let (reward_vault_pubkey, _) = find_reward_vault_address(program_id, stake_store_pubkey);
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*reward_operator_pubkey, true),
+        AccountMeta::new_readonly(clock::id(), false),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(reward_vault_pubkey, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'claim_rewards' instruction
+pub fn claim_rewards(
+    program_id: &Pubkey,
+    owner_pubkey: &Pubkey,
+    mint_pubkey: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    stake_list_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::ClaimRewards.pack();
+    let (reward_vault_pubkey, _) = find_reward_vault_address(program_id, stake_store_pubkey);
+    let (reward_authority_pubkey, _) =
+        find_reward_authority_address(program_id, stake_store_pubkey);
+    let (staker_pubkey, _) =
+        find_staker_account_address(program_id, stake_store_pubkey, owner_pubkey);
+    let accounts = vec![
+        AccountMeta::new_readonly(*owner_pubkey, true),
+        AccountMeta::new_readonly(*mint_pubkey, false),
+        AccountMeta::new_readonly(clock::id(), false),
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new(*stake_list_pubkey, false),
+        AccountMeta::new(reward_vault_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(reward_authority_pubkey, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new(staker_pubkey, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'fund_reward_vault' instruction, the reward operator pays for
+/// the vault if it doesn't exist yet
+pub fn fund_reward_vault(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    reward_operator_pubkey: &Pubkey,
+    reward_mint_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    amount: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::FundRewardVault { amount }.pack();
+    let (reward_vault_pubkey, _) = find_reward_vault_address(program_id, stake_store_pubkey);
+    let (reward_authority_pubkey, _) =
+        find_reward_authority_address(program_id, stake_store_pubkey);
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new(*reward_operator_pubkey, true),
+        AccountMeta::new_readonly(*reward_mint_pubkey, false),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(reward_vault_pubkey, false),
+        AccountMeta::new_readonly(reward_authority_pubkey, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(rent::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'withdraw_excess_rewards' instruction
+pub fn withdraw_excess_rewards(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    reward_operator_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    amount: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::WithdrawExcessRewards { amount }.pack();
+    let (reward_vault_pubkey, _) = find_reward_vault_address(program_id, stake_store_pubkey);
+    let (reward_authority_pubkey, _) =
+        find_reward_authority_address(program_id, stake_store_pubkey);
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*reward_operator_pubkey, true),
+        AccountMeta::new_readonly(clock::id(), false),
+        AccountMeta::new(reward_vault_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(reward_authority_pubkey, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'append_emission_segments' instruction
+pub fn append_emission_segments(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    reward_operator_pubkey: &Pubkey,
+    segments: Vec<EmissionSegment>,
+    end_ts: UnixTimestamp,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::AppendEmissionSegments { segments, end_ts }.pack();
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*reward_operator_pubkey, true),
+        AccountMeta::new_readonly(clock::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'sunset' instruction
+pub fn sunset(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    manager_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::Sunset.pack();
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*manager_pubkey, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'close_pool' instruction, closing the rarity table and the
+/// reward vault along with the pool when they exist. The rent goes to the
+/// manager or, for a multisig manager, to one of its members
+pub fn close_pool(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    stake_list_pubkey: &Pubkey,
+    manager_pubkey: &Pubkey,
+    rent_destination_pubkey: &Pubkey,
+    rarity_table_pubkey: Option<&Pubkey>,
+    close_reward_vault: bool,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::ClosePool.pack();
+    let mut accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new(*stake_list_pubkey, false),
+        AccountMeta::new_readonly(*manager_pubkey, true),
+        AccountMeta::new(*rent_destination_pubkey, false),
+    ];
+    if let Some(rarity_table_pubkey) = rarity_table_pubkey {
+        accounts.push(AccountMeta::new(*rarity_table_pubkey, false));
+    }
+    if close_reward_vault {
+        let (reward_vault_pubkey, _) = find_reward_vault_address(program_id, stake_store_pubkey);
+        let (reward_authority_pubkey, _) =
+            find_reward_authority_address(program_id, stake_store_pubkey);
+        accounts.push(AccountMeta::new(reward_vault_pubkey, false));
+        accounts.push(AccountMeta::new_readonly(reward_authority_pubkey, false));
+        accounts.push(AccountMeta::new_readonly(spl_token::id(), false));
+    }
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'set_stake_limits' instruction
+pub fn set_stake_limits(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    manager_pubkey: &Pubkey,
+    max_per_owner: u32,
+    max_total: u32,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::SetStakeLimits {
+        max_per_owner,
+        max_total,
+    }
+    .pack();
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*manager_pubkey, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'set_stake_tiers' instruction
+pub fn set_stake_tiers(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    manager_pubkey: &Pubkey,
+    thresholds: Vec<u64>,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::SetStakeTiers { thresholds }.pack();
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*manager_pubkey, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'record_tier' instruction, the payer funds the tier record
+/// the first time it's written
+pub fn record_tier(
+    program_id: &Pubkey,
+    payer_pubkey: &Pubkey,
+    mint_pubkey: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    stake_list_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::RecordTier.pack();
+    let (tier_record, _) = find_tier_record_address(program_id, stake_store_pubkey, mint_pubkey);
+    let accounts = vec![
+        AccountMeta::new(*payer_pubkey, true),
+        AccountMeta::new_readonly(*mint_pubkey, false),
+        AccountMeta::new_readonly(clock::id(), false),
+        AccountMeta::new_readonly(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*stake_list_pubkey, false),
+        AccountMeta::new(tier_record, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'verify_stake' instruction
+pub fn verify_stake(
+    program_id: &Pubkey,
+    owner_pubkey: &Pubkey,
+    mint_pubkey: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    stake_list_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::VerifyStake.pack();
+    let accounts = vec![
+        AccountMeta::new_readonly(*owner_pubkey, false),
+        AccountMeta::new_readonly(*mint_pubkey, false),
+        AccountMeta::new_readonly(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*stake_list_pubkey, false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Accounts of the view instructions, none of them signs or is written so
+/// the instructions can be simulated
+fn stake_view_accounts(
+    mint_pubkey: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    stake_list_pubkey: &Pubkey,
+) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new_readonly(*mint_pubkey, false),
+        AccountMeta::new_readonly(clock::id(), false),
+        AccountMeta::new_readonly(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*stake_list_pubkey, false),
+    ]
+}
+
+/// creates a 'get_pending_rewards' instruction
+pub fn get_pending_rewards(
+    program_id: &Pubkey,
+    mint_pubkey: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    stake_list_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: stake_view_accounts(mint_pubkey, stake_store_pubkey, stake_list_pubkey),
+        data: NFTStakingInstruction::GetPendingRewards.pack(),
+    })
+}
+
+/// creates a 'get_stake_info' instruction
+pub fn get_stake_info(
+    program_id: &Pubkey,
+    mint_pubkey: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    stake_list_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts: stake_view_accounts(mint_pubkey, stake_store_pubkey, stake_list_pubkey),
+        data: NFTStakingInstruction::GetStakeInfo.pack(),
+    })
+}
+
+/// creates a 'migrate' instruction, `pool_accounts` are the points ledgers,
+/// tier records and rarity table to upgrade along with the pool
+pub fn migrate(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    stake_list_pubkey: &Pubkey,
+    manager_pubkey: &Pubkey,
+    pool_accounts: &[Pubkey],
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::Migrate.pack();
+    let mut accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new(*stake_list_pubkey, false),
+        AccountMeta::new(*manager_pubkey, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    accounts.extend(
+        pool_accounts
+            .iter()
+            .map(|pubkey| AccountMeta::new(*pubkey, false)),
+    );
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'set_paused' instruction
+pub fn set_paused(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    pauser_pubkey: &Pubkey,
+    paused: bool,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::SetPaused { paused }.pack();
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*pauser_pubkey, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'set_fees' instruction
+pub fn set_fees(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    manager_pubkey: &Pubkey,
+    fees: FeeConfig,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::SetFees { fees }.pack();
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*manager_pubkey, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'set_authority' instruction
+pub fn set_authority(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    manager_pubkey: &Pubkey,
+    role: Role,
+    authority_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::SetAuthority {
+        role,
+        authority: *authority_pubkey,
+    }
+    .pack();
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*manager_pubkey, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'propose_config' instruction
+pub fn propose_config(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    manager_pubkey: &Pubkey,
+    config: PoolConfig,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::ProposeConfig { config }.pack();
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*manager_pubkey, true),
+        AccountMeta::new_readonly(clock::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'apply_pending_config' instruction
+pub fn apply_pending_config(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::ApplyPendingConfig.pack();
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(clock::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'cancel_pending_config' instruction
+pub fn cancel_pending_config(
+    program_id: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    manager_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::CancelPendingConfig.pack();
+    let accounts = vec![
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new_readonly(*manager_pubkey, true),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'reclaim' instruction, paying the bounty into
+/// `bounty_destination_pubkey` when given
+pub fn reclaim(
+    program_id: &Pubkey,
+    owner_pubkey: &Pubkey,
+    mint_pubkey: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    stake_list_pubkey: &Pubkey,
+    stake_pubkey: &Pubkey,
+    bounty_destination_pubkey: Option<&Pubkey>,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::Reclaim.pack();
+    let (pda, _) = find_transient_stake_address(program_id, owner_pubkey, mint_pubkey);
+    let (staker_pubkey, _) =
+        find_staker_account_address(program_id, stake_store_pubkey, owner_pubkey);
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*owner_pubkey, false),
+        AccountMeta::new_readonly(*mint_pubkey, false),
+        AccountMeta::new_readonly(clock::id(), false),
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new(*stake_list_pubkey, false),
+        AccountMeta::new(*stake_pubkey, false),
+        AccountMeta::new_readonly(pda, false),
+        AccountMeta::new(get_associated_token_address(owner_pubkey, mint_pubkey), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new(staker_pubkey, false),
+    ];
+    if let Some(bounty_destination_pubkey) = bounty_destination_pubkey {
+        let (reward_vault_pubkey, _) = find_reward_vault_address(program_id, stake_store_pubkey);
+        let (reward_authority_pubkey, _) =
+            find_reward_authority_address(program_id, stake_store_pubkey);
+        accounts.push(AccountMeta::new(reward_vault_pubkey, false));
+        accounts.push(AccountMeta::new(*bounty_destination_pubkey, false));
+        accounts.push(AccountMeta::new_readonly(reward_authority_pubkey, false));
+    }
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// creates a 'claim_credited_rewards' instruction
+pub fn claim_credited_rewards(
+    program_id: &Pubkey,
+    owner_pubkey: &Pubkey,
+    stake_store_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = NFTStakingInstruction::ClaimCreditedRewards.pack();
+    let (staker_pubkey, _) =
+        find_staker_account_address(program_id, stake_store_pubkey, owner_pubkey);
+    let (reward_vault_pubkey, _) = find_reward_vault_address(program_id, stake_store_pubkey);
+    let (reward_authority_pubkey, _) =
+        find_reward_authority_address(program_id, stake_store_pubkey);
+    let accounts = vec![
+        AccountMeta::new_readonly(*owner_pubkey, true),
+        AccountMeta::new(*stake_store_pubkey, false),
+        AccountMeta::new(staker_pubkey, false),
+        AccountMeta::new(reward_vault_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(reward_authority_pubkey, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Turns the authority of an instruction built for a single key authority
+/// into a multisig: the authority stops signing and the signing members are
+/// appended last, the only place the program counts them. They're writable
+/// since the first one pays or receives rent for the authority
+pub fn with_authority_signers(
+    mut instruction: Instruction,
+    authority_pubkey: &Pubkey,
+    signer_pubkeys: &[&Pubkey],
+) -> Instruction {
+    for account in instruction.accounts.iter_mut() {
+        if account.pubkey == *authority_pubkey {
+            account.is_signer = false;
+        }
+    }
+    instruction.accounts.extend(
+        signer_pubkeys
+            .iter()
+            .map(|signer_pubkey| AccountMeta::new(**signer_pubkey, true)),
+    );
+    instruction
+}
@@ -1,5 +1,8 @@
+pub mod cpi;
 pub mod error;
 pub mod instruction;
+pub mod merkle;
+pub mod metadata;
 pub mod processor;
 pub mod state;
 pub mod big_vec;
@@ -9,5 +12,89 @@ pub mod utils;
 pub mod entrypoint;
 
 pub use solana_program;
+use solana_program::pubkey::Pubkey;
+
+/// Seed for the transient authority holding a staked NFT account
+const TRANSIENT_NFT_STAKE_SEED_PREFIX: &[u8] = b"transient";
+
+/// Seed for the per-owner points ledger
+const STAKER_SEED_PREFIX: &[u8] = b"staker";
+
+/// Seed for the authority of a stake store's reward vault
+const REWARD_AUTHORITY_SEED_PREFIX: &[u8] = b"rewards";
+
+/// Seed for a stake store's reward vault
+const REWARD_VAULT_SEED_PREFIX: &[u8] = b"reward_vault";
+
+/// Seed for the tier record of a staked mint
+const TIER_RECORD_SEED_PREFIX: &[u8] = b"tier";
+
+/// Generates the authority that holds a staked NFT account on behalf of its
+/// owner
+pub fn find_transient_stake_address(
+    program_id: &Pubkey,
+    owner_address: &Pubkey,
+    mint_address: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            TRANSIENT_NFT_STAKE_SEED_PREFIX,
+            &owner_address.to_bytes(),
+            &mint_address.to_bytes(),
+        ],
+        program_id,
+    )
+}
+
+/// Generates the address of an owner's points ledger in a stake store
+pub fn find_staker_account_address(
+    program_id: &Pubkey,
+    stake_store_address: &Pubkey,
+    owner_address: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            STAKER_SEED_PREFIX,
+            &stake_store_address.to_bytes(),
+            &owner_address.to_bytes(),
+        ],
+        program_id,
+    )
+}
+
+/// Generates the authority that owns a stake store's reward vault
+pub fn find_reward_authority_address(
+    program_id: &Pubkey,
+    stake_store_address: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[REWARD_AUTHORITY_SEED_PREFIX, &stake_store_address.to_bytes()],
+        program_id,
+    )
+}
+
+/// Generates the token account holding a stake store's rewards
+pub fn find_reward_vault_address(program_id: &Pubkey, stake_store_address: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[REWARD_VAULT_SEED_PREFIX, &stake_store_address.to_bytes()],
+        program_id,
+    )
+}
+
+/// Generates the account recording the stake tier reached by a mint
+pub fn find_tier_record_address(
+    program_id: &Pubkey,
+    stake_store_address: &Pubkey,
+    mint_address: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            TIER_RECORD_SEED_PREFIX,
+            &stake_store_address.to_bytes(),
+            &mint_address.to_bytes(),
+        ],
+        program_id,
+    )
+}
 
 solana_program::declare_id!("NFTStakin1111111111111111111111111111111111");
\ No newline at end of file
@@ -0,0 +1,277 @@
+//! Merkle allow-list of NFT mints, each optionally carrying a rarity weight.
+//!
+//! Leaves are `sha256(0x00 || mint [|| multiplier_bps])` and nodes are
+//! `sha256(0x01 || min(left, right) || max(left, right))`, so a proof is just
+//! the list of sibling hashes from the leaf up to the root.
+
+use solana_program::{hash::hashv, pubkey::Pubkey};
+
+/// Size of every hash in the tree
+pub const HASH_BYTES: usize = 32;
+
+const LEAF_PREFIX: &[u8] = &[0];
+const NODE_PREFIX: &[u8] = &[1];
+
+/// Hash of a single allow-list entry
+pub fn leaf_hash(mint: &Pubkey, multiplier_bps: Option<u16>) -> [u8; HASH_BYTES] {
+    match multiplier_bps {
+        Some(multiplier_bps) => hashv(&[LEAF_PREFIX, mint.as_ref(), &multiplier_bps.to_le_bytes()]),
+        None => hashv(&[LEAF_PREFIX, mint.as_ref()]),
+    }
+    .to_bytes()
+}
+
+/// Hash of two sibling nodes, independent of their order
+pub fn node_hash(a: &[u8; HASH_BYTES], b: &[u8; HASH_BYTES]) -> [u8; HASH_BYTES] {
+    if a <= b {
+        hashv(&[NODE_PREFIX, a, b]).to_bytes()
+    } else {
+        hashv(&[NODE_PREFIX, b, a]).to_bytes()
+    }
+}
+
+/// Checks the leaf is part of the tree with the given root
+pub fn verify(root: &[u8; HASH_BYTES], leaf: [u8; HASH_BYTES], proof: &[[u8; HASH_BYTES]]) -> bool {
+    let computed = proof
+        .iter()
+        .fold(leaf, |node, sibling| node_hash(&node, sibling));
+    computed == *root
+}
+
+#[cfg(not(target_arch = "bpf"))]
+pub use tree::*;
+
+/// Off-chain tree builder, producing the root stored in the stake store and
+/// the proofs handed to depositors
+#[cfg(not(target_arch = "bpf"))]
+mod tree {
+    use super::*;
+    use std::{fmt, str::FromStr};
+
+    /// A single row of the allow-list
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct AllowListEntry {
+        pub mint: Pubkey,
+        pub multiplier_bps: Option<u16>,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum MerkleTreeError {
+        /// The allow-list has no entries
+        Empty,
+        /// A CSV line couldn't be parsed, with its 1-based line number
+        InvalidLine(usize),
+    }
+
+    impl fmt::Display for MerkleTreeError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                MerkleTreeError::Empty => write!(f, "allow-list is empty"),
+                MerkleTreeError::InvalidLine(line) => write!(f, "invalid allow-list line {}", line),
+            }
+        }
+    }
+
+    impl std::error::Error for MerkleTreeError {}
+
+    #[derive(Clone, Debug)]
+    pub struct MerkleTree {
+        entries: Vec<AllowListEntry>,
+        /// Every level of the tree, leaves first and the root last
+        levels: Vec<Vec<[u8; HASH_BYTES]>>,
+    }
+
+    impl MerkleTree {
+        pub fn new(entries: Vec<AllowListEntry>) -> Result<Self, MerkleTreeError> {
+            if entries.is_empty() {
+                return Err(MerkleTreeError::Empty);
+            }
+            let leaves = entries
+                .iter()
+                .map(|entry| leaf_hash(&entry.mint, entry.multiplier_bps))
+                .collect::<Vec<_>>();
+            let mut levels = vec![leaves];
+            while levels.last().unwrap().len() > 1 {
+                // an odd node out is carried up to the next level unchanged
+                let next = levels
+                    .last()
+                    .unwrap()
+                    .chunks(2)
+                    .map(|pair| match pair {
+                        [left, right] => node_hash(left, right),
+                        [single] => *single,
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                levels.push(next);
+            }
+            Ok(Self { entries, levels })
+        }
+
+        /// Builds the tree from CSV lines of `mint[,multiplier_bps]`, skipping
+        /// blank lines, `#` comments and a leading `mint` header
+        pub fn from_csv(csv: &str) -> Result<Self, MerkleTreeError> {
+            let mut entries = vec![];
+            for (index, line) in csv.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let mut columns = line.split(',').map(str::trim);
+                let mint = columns.next().unwrap_or_default();
+                if index == 0 && mint.eq_ignore_ascii_case("mint") {
+                    continue;
+                }
+                let invalid = MerkleTreeError::InvalidLine(index + 1);
+                let mint = Pubkey::from_str(mint).map_err(|_| invalid.clone())?;
+                let multiplier_bps = match columns.next() {
+                    None | Some("") => None,
+                    Some(weight) => Some(weight.parse::<u16>().map_err(|_| invalid.clone())?),
+                };
+                if columns.next().is_some() {
+                    return Err(invalid);
+                }
+                entries.push(AllowListEntry {
+                    mint,
+                    multiplier_bps,
+                });
+            }
+            Self::new(entries)
+        }
+
+        pub fn root(&self) -> [u8; HASH_BYTES] {
+            self.levels.last().unwrap()[0]
+        }
+
+        pub fn entries(&self) -> &[AllowListEntry] {
+            &self.entries
+        }
+
+        /// Proof of the entry at `index` in the allow-list
+        pub fn proof(&self, index: usize) -> Option<Vec<[u8; HASH_BYTES]>> {
+            if index >= self.entries.len() {
+                return None;
+            }
+            let mut proof = vec![];
+            let mut index = index;
+            for level in &self.levels[..self.levels.len() - 1] {
+                let sibling = index ^ 1;
+                if sibling < level.len() {
+                    proof.push(level[sibling]);
+                }
+                index /= 2;
+            }
+            Some(proof)
+        }
+
+        /// Entry and proof of the first occurrence of the mint
+        pub fn find_proof(&self, mint: &Pubkey) -> Option<(AllowListEntry, Vec<[u8; HASH_BYTES]>)> {
+            let index = self.entries.iter().position(|entry| entry.mint == *mint)?;
+            Some((self.entries[index], self.proof(index)?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn csv_for(mints: &[(Pubkey, Option<u16>)]) -> String {
+        let mut csv = String::from("mint,multiplier_bps\n");
+        for (mint, multiplier_bps) in mints {
+            match multiplier_bps {
+                Some(multiplier_bps) => csv.push_str(&format!("{},{}\n", mint, multiplier_bps)),
+                None => csv.push_str(&format!("{}\n", mint)),
+            }
+        }
+        csv
+    }
+
+    #[test]
+    fn every_proof_verifies() {
+        for size in 1..=9 {
+            let mints = (0..size)
+                .map(|i| {
+                    (
+                        Pubkey::new_unique(),
+                        if i % 2 == 0 { Some(10_000 + i) } else { None },
+                    )
+                })
+                .collect::<Vec<_>>();
+            let tree = MerkleTree::from_csv(&csv_for(&mints)).unwrap();
+            assert_eq!(tree.entries().len(), size as usize);
+            for (index, (mint, multiplier_bps)) in mints.iter().enumerate() {
+                let proof = tree.proof(index).unwrap();
+                assert!(verify(
+                    &tree.root(),
+                    leaf_hash(mint, *multiplier_bps),
+                    &proof
+                ));
+                assert_eq!(tree.find_proof(mint).unwrap().1, proof);
+            }
+            assert!(tree.proof(size as usize).is_none());
+        }
+    }
+
+    #[test]
+    fn tampered_proofs_fail() {
+        let mints = (0..5)
+            .map(|_| (Pubkey::new_unique(), Some(15_000)))
+            .collect::<Vec<_>>();
+        let tree = MerkleTree::from_csv(&csv_for(&mints)).unwrap();
+        let (mint, multiplier_bps) = mints[3];
+        let proof = tree.proof(3).unwrap();
+
+        // a different weight, a missing weight or another mint
+        assert!(!verify(
+            &tree.root(),
+            leaf_hash(&mint, Some(30_000)),
+            &proof
+        ));
+        assert!(!verify(&tree.root(), leaf_hash(&mint, None), &proof));
+        assert!(!verify(
+            &tree.root(),
+            leaf_hash(&Pubkey::new_unique(), multiplier_bps),
+            &proof
+        ));
+
+        // a truncated or corrupted proof
+        assert!(!verify(
+            &tree.root(),
+            leaf_hash(&mint, multiplier_bps),
+            &proof[1..]
+        ));
+        let mut corrupted = proof.clone();
+        corrupted[0][0] ^= 1;
+        assert!(!verify(
+            &tree.root(),
+            leaf_hash(&mint, multiplier_bps),
+            &corrupted
+        ));
+    }
+
+    #[test]
+    fn csv_errors() {
+        assert_eq!(
+            MerkleTree::from_csv("").unwrap_err(),
+            MerkleTreeError::Empty
+        );
+        assert_eq!(
+            MerkleTree::from_csv("mint\n# nothing\n").unwrap_err(),
+            MerkleTreeError::Empty
+        );
+        let mint = Pubkey::new_unique();
+        assert_eq!(
+            MerkleTree::from_csv(&format!("{}\nnot-a-mint\n", mint)).unwrap_err(),
+            MerkleTreeError::InvalidLine(2)
+        );
+        assert_eq!(
+            MerkleTree::from_csv(&format!("{},70000\n", mint)).unwrap_err(),
+            MerkleTreeError::InvalidLine(1)
+        );
+        assert_eq!(
+            MerkleTree::from_csv(&format!("{},1,2\n", mint)).unwrap_err(),
+            MerkleTreeError::InvalidLine(1)
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! Minimal reader of Metaplex token-metadata accounts, covering the fields
+//! needed to check the collection and creators of a deposited NFT.
+//!
+//! Metadata accounts written before the collection field existed are shorter
+//! or zero padded, so the trailing optional fields read as `None` when the
+//! account data runs out.
+
+use crate::error::NFTStakingError;
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{account_info::AccountInfo, msg, program_error::ProgramError, pubkey::Pubkey};
+
+/// Metaplex token-metadata program
+pub mod metadata_program {
+    solana_program::declare_id!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+}
+
+/// Seed of the metadata PDA of a mint
+pub const METADATA_SEED_PREFIX: &[u8] = b"metadata";
+
+/// Account key of a `MetadataV1` account
+pub const METADATA_V1_KEY: u8 = 4;
+
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct Creator {
+    pub address: Pubkey,
+    pub verified: bool,
+    pub share: u8,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct Collection {
+    pub verified: bool,
+    pub key: Pubkey,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct Data {
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub seller_fee_basis_points: u16,
+    pub creators: Option<Vec<Creator>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, BorshSerialize)]
+pub struct Metadata {
+    pub key: u8,
+    pub update_authority: Pubkey,
+    pub mint: Pubkey,
+    pub data: Data,
+    pub primary_sale_happened: bool,
+    pub is_mutable: bool,
+    pub edition_nonce: Option<u8>,
+    /// `TokenStandard` discriminant
+    pub token_standard: Option<u8>,
+    pub collection: Option<Collection>,
+}
+
+impl Metadata {
+    /// Parses the metadata, ignoring any field after the collection
+    pub fn from_account_data(data: &[u8]) -> Result<Self, ProgramError> {
+        let mut data = data;
+        Ok(Self {
+            key: u8::deserialize(&mut data)?,
+            update_authority: Pubkey::deserialize(&mut data)?,
+            mint: Pubkey::deserialize(&mut data)?,
+            data: Data::deserialize(&mut data)?,
+            primary_sale_happened: bool::deserialize(&mut data)?,
+            is_mutable: bool::deserialize(&mut data)?,
+            edition_nonce: deserialize_trailing(&mut data)?,
+            token_standard: deserialize_trailing(&mut data)?,
+            collection: deserialize_trailing(&mut data)?,
+        })
+    }
+
+    /// The creator with the given address, if listed
+    pub fn creator(&self, address: &Pubkey) -> Option<&Creator> {
+        self.data
+            .creators
+            .as_ref()?
+            .iter()
+            .find(|creator| creator.address == *address)
+    }
+}
+
+/// Reads an optional field that older accounts don't have
+fn deserialize_trailing<T: BorshDeserialize>(data: &mut &[u8]) -> Result<Option<T>, ProgramError> {
+    if data.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Option::<T>::deserialize(data)?)
+    }
+}
+
+/// Generates the metadata address of a mint
+pub fn find_metadata_address(mint_address: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            METADATA_SEED_PREFIX,
+            &metadata_program::id().to_bytes(),
+            &mint_address.to_bytes(),
+        ],
+        &metadata_program::id(),
+    )
+}
+
+/// Loads the metadata of the mint, checking the account is its PDA and
+/// owned by the token-metadata program
+pub fn load_metadata(
+    metadata_info: &AccountInfo,
+    mint_address: &Pubkey,
+) -> Result<Metadata, ProgramError> {
+    let (metadata_address, _) = find_metadata_address(mint_address);
+    if metadata_address != *metadata_info.key {
+        msg!(
+            "Incorrect metadata address for mint {}, expected {}, received {}",
+            mint_address,
+            metadata_address,
+            metadata_info.key
+        );
+        return Err(NFTStakingError::InvalidMetadataAccount.into());
+    }
+    if *metadata_info.owner != metadata_program::id() {
+        msg!(
+            "Expected metadata to be owned by {}, received {}",
+            metadata_program::id(),
+            metadata_info.owner
+        );
+        return Err(NFTStakingError::InvalidMetadataAccount.into());
+    }
+    let metadata = Metadata::from_account_data(&metadata_info.data.borrow())
+        .map_err(|_| NFTStakingError::InvalidMetadataAccount)?;
+    if metadata.key != METADATA_V1_KEY || metadata.mint != *mint_address {
+        msg!("Metadata account doesn't describe mint {}", mint_address);
+        return Err(NFTStakingError::InvalidMetadataAccount.into());
+    }
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> Metadata {
+        Metadata {
+            key: METADATA_V1_KEY,
+            update_authority: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            data: Data {
+                name: "Staked #1".to_string(),
+                symbol: "STK".to_string(),
+                uri: "https://example.com/1.json".to_string(),
+                seller_fee_basis_points: 500,
+                creators: Some(vec![Creator {
+                    address: Pubkey::new_unique(),
+                    verified: true,
+                    share: 100,
+                }]),
+            },
+            primary_sale_happened: true,
+            is_mutable: true,
+            edition_nonce: Some(255),
+            token_standard: Some(0),
+            collection: Some(Collection {
+                verified: true,
+                key: Pubkey::new_unique(),
+            }),
+        }
+    }
+
+    #[test]
+    fn parse_padded_metadata() {
+        let metadata = metadata();
+        let mut data = metadata.try_to_vec().unwrap();
+        // fields added after the collection are skipped
+        data.extend_from_slice(&[1, 7, 0, 0]);
+        assert_eq!(Metadata::from_account_data(&data).unwrap(), metadata);
+
+        let creator = metadata.data.creators.as_ref().unwrap()[0].address;
+        assert!(metadata.creator(&creator).unwrap().verified);
+        assert!(metadata.creator(&Pubkey::new_unique()).is_none());
+    }
+
+    #[test]
+    fn parse_legacy_metadata() {
+        let metadata = Metadata {
+            edition_nonce: None,
+            token_standard: None,
+            collection: None,
+            ..metadata()
+        };
+        let mut data = metadata.try_to_vec().unwrap();
+        // written before the optional fields existed
+        data.truncate(data.len() - 3);
+        assert_eq!(Metadata::from_account_data(&data).unwrap(), metadata);
+        // zero padded by the token-metadata program
+        data.resize(data.len() + 64, 0);
+        assert_eq!(Metadata::from_account_data(&data).unwrap(), metadata);
+
+        data.truncate(40);
+        assert!(Metadata::from_account_data(&data).is_err());
+    }
+}
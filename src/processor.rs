@@ -1,51 +1,76 @@
-use borsh::BorshSerialize;
+use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     borsh::try_from_slice_unchecked,
-    clock::Clock,
+    clock::{Clock, UnixTimestamp},
     decode_error::DecodeError,
-    entrypoint::ProgramResult,
+    entrypoint::{ProgramResult, MAX_PERMITTED_DATA_INCREASE},
     msg,
-    program::{invoke, invoke_signed},
+    program::{invoke, invoke_signed, set_return_data},
     program_error::{PrintProgramError, ProgramError},
-    program_pack::Pack,
+    program_pack::{IsInitialized, Pack},
     pubkey::Pubkey,
     rent::Rent,
-    // system_instruction,
-    // system_program,
+    system_instruction,
+    system_program,
     sysvar::Sysvar,
 };
 
 use crate::{
+    big_vec::BigVec,
     error::NFTStakingError,
-    instruction::{DepositNFTData, NFTStakingInstruction},
+    instruction::{
+        AllowListProof, DepositNFTData, NFTStakingInstruction, PendingRewards, StakeInfo,
+        UnsettledRewards, WithdrawNFTData,
+    },
+    merkle::{self, HASH_BYTES},
+    metadata,
+    find_reward_authority_address, find_reward_vault_address, find_staker_account_address,
+    find_tier_record_address, find_transient_stake_address,
     state::{
-        StakeList,
+        AccountHeader,
+        EmissionSegment,
+        FeeConfig,
+        PendingConfig,
+        PoolConfig,
+        ProgramAccount,
+        RarityEntry,
+        RarityTableHeader,
         StakeListHeader,
+        Role,
         StakeStore,
         StakedNFT,
+        StakerAccount,
+        TierRecord,
+        CURRENT_VERSION,
+        LEGACY_VERSION,
+        V2_VERSION,
+    },
+    utils::{
+        check_authority_wallet, get_associated_token_address, unpack_token_account,
+        validate_authority, DEFAULT_MULTIPLIER_BPS, MAX_ITEMS,
     },
-    utils::{ unpack_token_account, MAX_ITEMS },
+    REWARD_AUTHORITY_SEED_PREFIX, REWARD_VAULT_SEED_PREFIX, STAKER_SEED_PREFIX,
+    TIER_RECORD_SEED_PREFIX, TRANSIENT_NFT_STAKE_SEED_PREFIX,
 };
 use num_traits::FromPrimitive;
 
 use spl_token::state::Account as TokenAccount;
+use std::convert::TryInto;
 
-const TRANSIENT_NFT_STAKE_SEED_PREFIX: &[u8] = b"transient";
-
-// /// Check system program address
-// fn check_system_program(program_id: &Pubkey) -> Result<(), ProgramError> {
-//     if *program_id != system_program::id() {
-//         msg!(
-//             "Expected system program {}, received {}",
-//             system_program::id(),
-//             program_id
-//         );
-//         Err(ProgramError::IncorrectProgramId)
-//     } else {
-//         Ok(())
-//     }
-// }
+/// Check system program address
+fn check_system_program(program_id: &Pubkey) -> Result<(), ProgramError> {
+    if *program_id != system_program::id() {
+        msg!(
+            "Expected system program {}, received {}",
+            system_program::id(),
+            program_id
+        );
+        Err(ProgramError::IncorrectProgramId)
+    } else {
+        Ok(())
+    }
+}
 
 // /// Check stake program address
 // fn check_stake_program(program_id: &Pubkey) -> Result<(), ProgramError> {
@@ -78,8 +103,417 @@ fn check_account_owner(
     }
 }
 
+/// Check the points ledger address matches the owner and stake store
+fn check_staker_address(
+    program_id: &Pubkey,
+    stake_store_address: &Pubkey,
+    owner_address: &Pubkey,
+    staker_info: &AccountInfo,
+) -> Result<u8, ProgramError> {
+    let (staker_address, bump_seed) =
+        find_staker_account_address(program_id, stake_store_address, owner_address);
+    if staker_address != *staker_info.key {
+        msg!(
+            "Incorrect points ledger address for owner {}, expected {}, received {}",
+            owner_address,
+            staker_address,
+            staker_info.key
+        );
+        Err(NFTStakingError::InvalidStakerAccount.into())
+    } else {
+        Ok(bump_seed)
+    }
+}
+
+/// Load an initialized points ledger belonging to the owner and stake store
+fn load_staker_account(
+    program_id: &Pubkey,
+    stake_store_address: &Pubkey,
+    owner_address: &Pubkey,
+    staker_info: &AccountInfo,
+) -> Result<StakerAccount, ProgramError> {
+    check_staker_address(program_id, stake_store_address, owner_address, staker_info)?;
+    let staker = load_program_account::<StakerAccount>(staker_info, program_id)?;
+    if !staker.is_initialized() {
+        return Err(NFTStakingError::InvalidStakerAccount.into());
+    }
+    Ok(staker)
+}
+
+/// Load a program account of the expected kind, checking it's owned by the
+/// program and carries the kind's discriminator
+fn load_program_account<T: ProgramAccount>(
+    account_info: &AccountInfo,
+    program_id: &Pubkey,
+) -> Result<T, ProgramError> {
+    check_account_owner(account_info, program_id)?;
+    T::load(&account_info.data.borrow())
+}
+
+/// Zero a program account and move its lamports to the destination, the
+/// runtime purges it at the end of the transaction
+fn close_program_account(
+    account_info: &AccountInfo,
+    destination_info: &AccountInfo,
+) -> ProgramResult {
+    let lamports = account_info.lamports();
+    **destination_info.lamports.borrow_mut() = destination_info
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(NFTStakingError::AmountOverflow)?;
+    **account_info.lamports.borrow_mut() = 0;
+    account_info
+        .data
+        .borrow_mut()
+        .iter_mut()
+        .for_each(|byte| *byte = 0);
+    Ok(())
+}
+
+/// Kind and used length of a headerless account of the pool, told apart by
+/// the address it was derived at
+fn headerless_pool_account(
+    program_id: &Pubkey,
+    stake_store_address: &Pubkey,
+    stake_store: &StakeStore,
+    account_info: &AccountInfo,
+) -> Result<([u8; 8], usize), ProgramError> {
+    let data = account_info.data.borrow();
+    if *account_info.key == stake_store.rarity_table {
+        let len = RarityTableHeader::headerless_len(&data)?;
+        return Ok((RarityTableHeader::DISCRIMINATOR, len));
+    }
+    // the v1 ledger ends before `credited_rewards` and `last_active_ts`,
+    // which start out at 0
+    let mut staker_data = data.get(..StakerAccount::V1_LEN).unwrap_or(&data).to_vec();
+    staker_data.resize(StakerAccount::LEN - AccountHeader::LEN, 0);
+    if let Ok(staker) = try_from_slice_unchecked::<StakerAccount>(&staker_data) {
+        let (staker_address, _) =
+            find_staker_account_address(program_id, stake_store_address, &staker.owner);
+        if staker.stake_store == *stake_store_address && staker_address == *account_info.key {
+            return Ok((StakerAccount::DISCRIMINATOR, StakerAccount::LEN - AccountHeader::LEN));
+        }
+    }
+    if let Ok(tier_record) = try_from_slice_unchecked::<TierRecord>(&data) {
+        let (tier_record_address, _) =
+            find_tier_record_address(program_id, stake_store_address, &tier_record.token_mint);
+        if tier_record.stake_store == *stake_store_address
+            && tier_record_address == *account_info.key
+        {
+            return Ok((TierRecord::DISCRIMINATOR, TierRecord::LEN - AccountHeader::LEN));
+        }
+    }
+    msg!("Account {} isn't part of pool {}", account_info.key, stake_store_address);
+    Err(NFTStakingError::InvalidAccountType.into())
+}
+
+/// Wallet paying or receiving rent on behalf of an authority, its first
+/// signing member when the authority is a multisig
+fn authority_wallet<'a, 'b>(
+    authority_info: &'b AccountInfo<'a>,
+    authority_signers: &[&'b AccountInfo<'a>],
+) -> &'b AccountInfo<'a> {
+    authority_signers.first().copied().unwrap_or(authority_info)
+}
+
+/// Grow a program account to `len`, topping up its rent from the payer
+fn resize_account<'a>(
+    account_info: &AccountInfo<'a>,
+    payer_info: &AccountInfo<'a>,
+    system_program_info: &AccountInfo<'a>,
+    len: usize,
+) -> ProgramResult {
+    if account_info.data_len() >= len {
+        return Ok(());
+    }
+    let rent = Rent::get()?;
+    let lamports = rent
+        .minimum_balance(len)
+        .saturating_sub(account_info.lamports());
+    if lamports > 0 {
+        check_system_program(system_program_info.key)?;
+        invoke(
+            &system_instruction::transfer(payer_info.key, account_info.key, lamports),
+            &[
+                payer_info.clone(),
+                account_info.clone(),
+                system_program_info.clone(),
+            ],
+        )?;
+    }
+    msg!("Resizing {} to {} bytes", account_info.key, len);
+    account_info.realloc(len, true)
+}
+
 pub struct Processor;
 impl Processor {
+    /// Load the owner's points ledger, creating its PDA on the first deposit
+    fn get_or_create_staker_account<'a>(
+        program_id: &Pubkey,
+        stake_store_info: &AccountInfo<'a>,
+        owner_info: &AccountInfo<'a>,
+        staker_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        stake_list: &BigVec,
+        now: UnixTimestamp,
+    ) -> Result<StakerAccount, ProgramError> {
+        let bump_seed = check_staker_address(
+            program_id,
+            stake_store_info.key,
+            owner_info.key,
+            staker_info,
+        )?;
+
+        if staker_info.data_is_empty() {
+            check_system_program(system_program_info.key)?;
+            let rent = Rent::get()?;
+            let staker_signer_seeds: &[&[u8]] = &[
+                STAKER_SEED_PREFIX,
+                &stake_store_info.key.to_bytes(),
+                &owner_info.key.to_bytes(),
+                &[bump_seed],
+            ];
+            msg!("Creating points ledger for {}", owner_info.key);
+            invoke_signed(
+                &system_instruction::create_account(
+                    owner_info.key,
+                    staker_info.key,
+                    rent.minimum_balance(StakerAccount::LEN),
+                    StakerAccount::LEN as u64,
+                    program_id,
+                ),
+                &[
+                    owner_info.clone(),
+                    staker_info.clone(),
+                    system_program_info.clone(),
+                ],
+                &[staker_signer_seeds],
+            )?;
+        }
+
+        let staker = load_program_account::<StakerAccount>(staker_info, program_id)?;
+        if staker.is_initialized() {
+            return Ok(staker);
+        }
+        let mut staker = StakerAccount {
+            is_initialized: true,
+            stake_store: *stake_store_info.key,
+            owner: *owner_info.key,
+            last_update_ts: now,
+            ..StakerAccount::default()
+        };
+        // stakes migrated from the first release predate the ledger
+        for staked_nft in stake_list.iter::<StakedNFT>() {
+            let staked_nft = staked_nft?;
+            if staked_nft.owner == *owner_info.key {
+                staker.add_stake(staked_nft.multiplier_bps)?;
+            }
+        }
+        Ok(staker)
+    }
+
+    /// Create the stake store's reward vault PDA, a token account of the
+    /// reward mint owned by the reward authority
+    #[allow(clippy::too_many_arguments)]
+    fn create_reward_vault<'a>(
+        program_id: &Pubkey,
+        stake_store_info: &AccountInfo<'a>,
+        payer_info: &AccountInfo<'a>,
+        reward_mint_info: &AccountInfo<'a>,
+        reward_vault_info: &AccountInfo<'a>,
+        reward_authority_info: &AccountInfo<'a>,
+        token_program: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        rent_info: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        let (reward_vault_address, bump_seed) =
+            find_reward_vault_address(program_id, stake_store_info.key);
+        let (reward_authority, _) =
+            find_reward_authority_address(program_id, stake_store_info.key);
+        if reward_vault_address != *reward_vault_info.key
+            || reward_authority != *reward_authority_info.key
+        {
+            msg!(
+                "Expected reward vault {} owned by {}, received {} owned by {}",
+                reward_vault_address,
+                reward_authority,
+                reward_vault_info.key,
+                reward_authority_info.key
+            );
+            return Err(NFTStakingError::InvalidRewardVault.into());
+        }
+
+        if reward_vault_info.data_is_empty() {
+            check_system_program(system_program_info.key)?;
+            let rent = Rent::from_account_info(rent_info)?;
+            let reward_vault_signer_seeds: &[&[u8]] = &[
+                REWARD_VAULT_SEED_PREFIX,
+                &stake_store_info.key.to_bytes(),
+                &[bump_seed],
+            ];
+            msg!("Creating reward vault {}", reward_vault_info.key);
+            invoke_signed(
+                &system_instruction::create_account(
+                    payer_info.key,
+                    reward_vault_info.key,
+                    rent.minimum_balance(TokenAccount::LEN),
+                    TokenAccount::LEN as u64,
+                    token_program.key,
+                ),
+                &[
+                    payer_info.clone(),
+                    reward_vault_info.clone(),
+                    system_program_info.clone(),
+                ],
+                &[reward_vault_signer_seeds],
+            )?;
+        }
+
+        invoke(
+            &spl_token::instruction::initialize_account(
+                token_program.key,
+                reward_vault_info.key,
+                reward_mint_info.key,
+                reward_authority_info.key,
+            )?,
+            &[
+                reward_vault_info.clone(),
+                reward_mint_info.clone(),
+                reward_authority_info.clone(),
+                rent_info.clone(),
+                token_program.clone(),
+            ],
+        )
+    }
+
+    /// Pay rewards out of the vault, signed by the reward authority
+    fn transfer_rewards<'a>(
+        program_id: &Pubkey,
+        stake_store_info: &AccountInfo<'a>,
+        reward_vault_info: &AccountInfo<'a>,
+        destination_info: &AccountInfo<'a>,
+        reward_authority_info: &AccountInfo<'a>,
+        token_program: &AccountInfo<'a>,
+        amount: u64,
+    ) -> ProgramResult {
+        let (reward_authority, bump_seed) =
+            find_reward_authority_address(program_id, stake_store_info.key);
+        let reward_authority_seeds: &[&[u8]] = &[
+            REWARD_AUTHORITY_SEED_PREFIX,
+            &stake_store_info.key.to_bytes(),
+            &[bump_seed],
+        ];
+        invoke_signed(
+            &spl_token::instruction::transfer(
+                token_program.key,
+                reward_vault_info.key,
+                destination_info.key,
+                &reward_authority,
+                &[],
+                amount,
+            )?,
+            &[
+                reward_vault_info.clone(),
+                destination_info.clone(),
+                reward_authority_info.clone(),
+                token_program.clone(),
+            ],
+            &[reward_authority_seeds],
+        )
+    }
+
+    /// Transfer tokens out of an account owned by an authority, signed by
+    /// the members of a multisig authority
+    fn transfer_from_authority<'a>(
+        source_info: &AccountInfo<'a>,
+        destination_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        authority_signers: &[&AccountInfo<'a>],
+        token_program: &AccountInfo<'a>,
+        amount: u64,
+    ) -> ProgramResult {
+        let signer_pubkeys = authority_signers
+            .iter()
+            .map(|signer| signer.key)
+            .collect::<Vec<_>>();
+        let mut account_infos = vec![
+            source_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            token_program.clone(),
+        ];
+        account_infos.extend(authority_signers.iter().map(|signer| (*signer).clone()));
+        invoke(
+            &spl_token::instruction::transfer(
+                token_program.key,
+                source_info.key,
+                destination_info.key,
+                authority_info.key,
+                &signer_pubkeys,
+                amount,
+            )?,
+            &account_infos,
+        )
+    }
+
+    /// Charge a deposit or withdrawal fee to the owner, in lamports through
+    /// the system program or in tokens of the fee mint
+    fn charge_fee<'a>(
+        fees: &FeeConfig,
+        amount: u64,
+        owner_info: &AccountInfo<'a>,
+        treasury_info: &AccountInfo<'a>,
+        payment_info: &AccountInfo<'a>,
+        token_program: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        if fees.treasury != *treasury_info.key {
+            msg!(
+                "Expected fee treasury {}, received {}",
+                fees.treasury,
+                treasury_info.key
+            );
+            return Err(NFTStakingError::InvalidTreasury.into());
+        }
+        msg!("Charging a fee of {} to {}", amount, owner_info.key);
+        if fees.fee_mint == Pubkey::default() {
+            check_system_program(payment_info.key)?;
+            invoke(
+                &system_instruction::transfer(owner_info.key, treasury_info.key, amount),
+                &[
+                    owner_info.clone(),
+                    treasury_info.clone(),
+                    payment_info.clone(),
+                ],
+            )
+        } else {
+            let treasury = unpack_token_account(&treasury_info.data.borrow())?;
+            if treasury.mint != fees.fee_mint {
+                msg!(
+                    "Fees are paid in {}, treasury holds mint {}",
+                    fees.fee_mint,
+                    treasury.mint
+                );
+                return Err(NFTStakingError::MintMismatch.into());
+            }
+            invoke(
+                &spl_token::instruction::transfer(
+                    token_program.key,
+                    payment_info.key,
+                    treasury_info.key,
+                    owner_info.key,
+                    &[],
+                    amount,
+                )?,
+                &[
+                    payment_info.clone(),
+                    treasury_info.clone(),
+                    owner_info.clone(),
+                    token_program.clone(),
+                ],
+            )
+        }
+    }
+
     pub fn process(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
@@ -88,22 +522,145 @@ impl Processor {
         let instruction = NFTStakingInstruction::unpack(instruction_data)?;
 
         match instruction {
-            NFTStakingInstruction::Initialize => {
+            NFTStakingInstruction::Initialize { config } => {
                 msg!("Instruction: Initialize");
-                Self::process_initialize(accounts, program_id)
+                Self::process_initialize(accounts, config, program_id)
             }
-            NFTStakingInstruction::DepositNFT(DepositNFTData { amount }) => {
+            NFTStakingInstruction::DepositNFT(DepositNFTData { proof, .. }) => {
                 msg!("Instruction: DepositNFT");
-                Self::process_deposit_nft(accounts, amount, program_id)
+                Self::process_deposit_nft(accounts, proof, program_id)
             }
-            NFTStakingInstruction::WithdrawNFT => {
+            NFTStakingInstruction::WithdrawNFT(WithdrawNFTData { unsettled_rewards }) => {
                 msg!("Instruction: WithdrawNFT");
-                Self::process_withdraw_nft(accounts, program_id)
+                Self::process_withdraw_nft(accounts, unsettled_rewards, program_id)
+            }
+            NFTStakingInstruction::Checkpoint => {
+                msg!("Instruction: Checkpoint");
+                Self::process_checkpoint(accounts, program_id)
+            }
+            NFTStakingInstruction::InitializeRarityTable => {
+                msg!("Instruction: InitializeRarityTable");
+                Self::process_initialize_rarity_table(accounts, program_id)
+            }
+            NFTStakingInstruction::UploadRarities(entries) => {
+                msg!("Instruction: UploadRarities");
+                Self::process_upload_rarities(accounts, entries, program_id)
+            }
+            NFTStakingInstruction::SetAllowListRoot(root) => {
+                msg!("Instruction: SetAllowListRoot");
+                Self::process_set_allow_list_root(accounts, root, program_id)
+            }
+            NFTStakingInstruction::SetMetadataRequirements {
+                required_collection,
+                required_creator,
+            } => {
+                msg!("Instruction: SetMetadataRequirements");
+                Self::process_set_metadata_requirements(
+                    accounts,
+                    required_collection,
+                    required_creator,
+                    program_id,
+                )
+            }
+            NFTStakingInstruction::FundRewards { amount, duration } => {
+                msg!("Instruction: FundRewards");
+                Self::process_fund_rewards(accounts, amount, duration, program_id)
+            }
+            NFTStakingInstruction::ClaimRewards => {
+                msg!("Instruction: ClaimRewards");
+                Self::process_claim_rewards(accounts, program_id)
+            }
+            NFTStakingInstruction::FundRewardVault { amount } => {
+                msg!("Instruction: FundRewardVault");
+                Self::process_fund_reward_vault(accounts, amount, program_id)
+            }
+            NFTStakingInstruction::WithdrawExcessRewards { amount } => {
+                msg!("Instruction: WithdrawExcessRewards");
+                Self::process_withdraw_excess_rewards(accounts, amount, program_id)
+            }
+            NFTStakingInstruction::AppendEmissionSegments { segments, end_ts } => {
+                msg!("Instruction: AppendEmissionSegments");
+                Self::process_append_emission_segments(accounts, segments, end_ts, program_id)
+            }
+            NFTStakingInstruction::Sunset => {
+                msg!("Instruction: Sunset");
+                Self::process_sunset(accounts, program_id)
+            }
+            NFTStakingInstruction::ClosePool => {
+                msg!("Instruction: ClosePool");
+                Self::process_close_pool(accounts, program_id)
+            }
+            NFTStakingInstruction::SetStakeLimits {
+                max_per_owner,
+                max_total,
+            } => {
+                msg!("Instruction: SetStakeLimits");
+                Self::process_set_stake_limits(accounts, max_per_owner, max_total, program_id)
+            }
+            NFTStakingInstruction::SetStakeTiers { thresholds } => {
+                msg!("Instruction: SetStakeTiers");
+                Self::process_set_stake_tiers(accounts, thresholds, program_id)
+            }
+            NFTStakingInstruction::RecordTier => {
+                msg!("Instruction: RecordTier");
+                Self::process_record_tier(accounts, program_id)
+            }
+            NFTStakingInstruction::VerifyStake => {
+                msg!("Instruction: VerifyStake");
+                Self::process_verify_stake(accounts, program_id)
+            }
+            NFTStakingInstruction::GetPendingRewards => {
+                msg!("Instruction: GetPendingRewards");
+                Self::process_get_pending_rewards(accounts, program_id)
+            }
+            NFTStakingInstruction::GetStakeInfo => {
+                msg!("Instruction: GetStakeInfo");
+                Self::process_get_stake_info(accounts, program_id)
+            }
+            NFTStakingInstruction::Migrate => {
+                msg!("Instruction: Migrate");
+                Self::process_migrate(accounts, program_id)
+            }
+            NFTStakingInstruction::SetPaused { paused } => {
+                msg!("Instruction: SetPaused");
+                Self::process_set_paused(accounts, paused, program_id)
+            }
+            NFTStakingInstruction::SetFees { fees } => {
+                msg!("Instruction: SetFees");
+                Self::process_set_fees(accounts, fees, program_id)
+            }
+            NFTStakingInstruction::Reclaim => {
+                msg!("Instruction: Reclaim");
+                Self::process_reclaim(accounts, program_id)
+            }
+            NFTStakingInstruction::SetAuthority { role, authority } => {
+                msg!("Instruction: SetAuthority");
+                Self::process_set_authority(accounts, role, authority, program_id)
+            }
+            NFTStakingInstruction::ProposeConfig { config } => {
+                msg!("Instruction: ProposeConfig");
+                Self::process_propose_config(accounts, config, program_id)
+            }
+            NFTStakingInstruction::ApplyPendingConfig => {
+                msg!("Instruction: ApplyPendingConfig");
+                Self::process_apply_pending_config(accounts, program_id)
+            }
+            NFTStakingInstruction::CancelPendingConfig => {
+                msg!("Instruction: CancelPendingConfig");
+                Self::process_cancel_pending_config(accounts, program_id)
+            }
+            NFTStakingInstruction::ClaimCreditedRewards => {
+                msg!("Instruction: ClaimCreditedRewards");
+                Self::process_claim_credited_rewards(accounts, program_id)
             }
         }
     }
 
-    fn process_initialize(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    fn process_initialize(
+        accounts: &[AccountInfo],
+        mut config: PoolConfig,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
         let stake_store_info = next_account_info(account_info_iter)?;
         let stake_list_info = next_account_info(account_info_iter)?;
@@ -111,9 +668,10 @@ impl Processor {
         let rent_info = next_account_info(account_info_iter)?;
 
         let rent = &Rent::from_account_info(rent_info)?;
-        if !manager_info.is_signer {
+        // the members of a multisig manager follow the instruction's accounts
+        if let Err(error) = validate_authority(manager_info, account_info_iter.as_slice()) {
             msg!("Manager did not sign to initialize");
-            return Err(NFTStakingError::SignatureMissing.into());
+            return Err(error);
         }
 
         if stake_store_info.key == stake_list_info.key {
@@ -121,18 +679,14 @@ impl Processor {
             return Err(NFTStakingError::AlreadyInUse.into());
         }
 
-        check_account_owner(stake_store_info, program_id)?;
-        let mut stake_store =
-            try_from_slice_unchecked::<StakeStore>(&stake_store_info.data.borrow())?;
-
-        check_account_owner(stake_list_info, program_id)?;
-        let mut stake_list = try_from_slice_unchecked::<StakeList>(&stake_list_info.data.borrow())?;
-
-        // should not exceed (10M - header'size) / StakedNFT's size. 100K items will be possible as ideal.
-        stake_list.header.is_initialized = true;
-        stake_list.header.max_items = MAX_ITEMS;
-        stake_list.header.count = 0;
-        stake_list.items.clear();
+        if load_program_account::<StakeStore>(stake_store_info, program_id)?.is_initialized() {
+            msg!("Stake store {} is already initialized", stake_store_info.key);
+            return Err(NFTStakingError::AlreadyInUse.into());
+        }
+        if load_program_account::<StakeListHeader>(stake_list_info, program_id)?.is_initialized() {
+            msg!("Stake list {} is already initialized", stake_list_info.key);
+            return Err(NFTStakingError::AlreadyInUse.into());
+        }
 
         if !rent.is_exempt(stake_store_info.lamports(), stake_store_info.data_len()) {
             msg!("Stake store not rent-exempt");
@@ -143,23 +697,64 @@ impl Processor {
             msg!("Stake list not rent-exempt");
             return Err(ProgramError::AccountNotRentExempt);
         }
-        stake_list.serialize(&mut *stake_list_info.data.borrow_mut())?;
+
+        config.check()?;
+        let capacity = stake_list_info
+            .data_len()
+            .saturating_sub(StakeListHeader::packed_len() + 4)
+            / StakedNFT::LEN;
+        // should not exceed (10M - header'size) / StakedNFT's size. 100K items will be possible as ideal.
+        if config.max_items == 0 {
+            config.max_items = capacity.min(MAX_ITEMS as usize) as u16;
+        }
+        if config.max_items == 0 || config.max_items as usize > capacity {
+            msg!(
+                "Stake list has room for {} NFTs, {} requested",
+                capacity,
+                config.max_items
+            );
+            return Err(NFTStakingError::InvalidPoolConfig.into());
+        }
+        {
+            // only the headers and the empty BigVec's length are written, the
+            // list may be several megabytes
+            let mut stake_list_data = stake_list_info.data.borrow_mut();
+            let header = StakeListHeader {
+                is_initialized: true,
+                max_items: config.max_items,
+                count: 0,
+            };
+            header.save(&mut stake_list_data)?;
+            let (_header, mut stake_list) = StakeListHeader::deserialize_vec(&mut stake_list_data)?;
+            stake_list.clear()?;
+        }
 
         let stake_store = StakeStore {
             is_initialized: true,
             manager: *manager_info.key,
             stake_list: *stake_list_info.key,
             staked_count: 0,
+            rarity_table: Pubkey::default(),
+            allow_list_root: [0; HASH_BYTES],
+            required_creator: Pubkey::default(),
+            reward_vault: Pubkey::default(),
+            total_shares: 0,
+            reward_per_share: 0,
+            reward_rate: 0,
+            reward_epoch_end: 0,
+            last_reward_ts: 0,
+            unclaimed_rewards: 0,
+            config,
+            sunset: false,
+            pending_config: PendingConfig::default(),
         };
 
-        stake_store
-            .serialize(&mut *stake_store_info.data.borrow_mut())
-        .map_err(|e| e.into())
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
     }
 
     fn process_deposit_nft(
         accounts: &[AccountInfo],
-        amount: u64,
+        proof: Option<AllowListProof>,
         program_id: &Pubkey,
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
@@ -173,13 +768,24 @@ impl Processor {
         }
 
         let stake_account_info = next_account_info(account_info_iter)?;
-        // check to balance of stake_account
-        // ...
+        // the proof and rarity lookups below trust `mint_info`, so the token
+        // account must actually hold that single NFT for the depositor
+        let stake_account = unpack_token_account(&stake_account_info.data.borrow())?;
+        if stake_account.mint != *mint_info.key
+            || stake_account.amount != 1
+            || stake_account.owner != *depositor_info.key
+        {
+            msg!(
+                "Token account {} doesn't hold mint {} for {}",
+                stake_account_info.key,
+                mint_info.key,
+                depositor_info.key
+            );
+            return Err(NFTStakingError::MintMismatch.into());
+        }
 
         let stake_store_info = next_account_info(account_info_iter)?;
-        check_account_owner(stake_store_info, program_id)?;
-        let mut stake_store =
-            try_from_slice_unchecked::<StakeStore>(&stake_store_info.data.borrow())?;
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
         msg!("=========================");
         let stake_list_info = next_account_info(account_info_iter)?;
         check_account_owner(stake_list_info, program_id)?;
@@ -192,14 +798,18 @@ impl Processor {
             return Err(ProgramError::AccountDataTooSmall);
         }
 
-        let (pda, _nonce) = Pubkey::find_program_address(
-            &[
-                TRANSIENT_NFT_STAKE_SEED_PREFIX,
-                &depositor_info.key.to_bytes(),
-                &mint_info.key.to_bytes(),
-            ],
-            program_id,
-        );
+        if stake_store.sunset {
+            msg!("Pool {} is being decommissioned", stake_store_info.key);
+            return Err(NFTStakingError::PoolSunset.into());
+        }
+        stake_store.config.check_not_paused()?;
+        // every deposit adds exactly one entry, whatever its `amount` says
+        stake_store
+            .config
+            .check_pool_capacity(stake_list.len() as u16)?;
+
+        let (pda, _nonce) =
+            find_transient_stake_address(program_id, depositor_info.key, mint_info.key);
 
         let token_program = next_account_info(account_info_iter)?;
         let owner_change_ix = spl_token::instruction::set_authority(
@@ -220,56 +830,132 @@ impl Processor {
             ],
         )?;
 
+        let staker_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+
+        if stake_store.has_metadata_requirements() {
+            let metadata_info = next_account_info(account_info_iter)?;
+            let metadata = metadata::load_metadata(metadata_info, mint_info.key)?;
+            stake_store.check_metadata(&metadata)?;
+        }
+
+        let proven_multiplier_bps = match (stake_store.has_allow_list(), proof) {
+            (false, None) => None,
+            (true, Some(proof)) if merkle::verify(
+                &stake_store.allow_list_root,
+                merkle::leaf_hash(mint_info.key, proof.multiplier_bps),
+                &proof.proof,
+            ) => proof.multiplier_bps,
+            _ => {
+                msg!("Mint {} is not proven to be in the allow-list", mint_info.key);
+                return Err(NFTStakingError::InvalidAllowListProof.into());
+            }
+        };
+        if proven_multiplier_bps == Some(0) {
+            return Err(NFTStakingError::InvalidRarityMultiplier.into());
+        }
+
+        let multiplier_bps = if let Some(multiplier_bps) = proven_multiplier_bps {
+            multiplier_bps
+        } else if stake_store.has_rarity_table() {
+            let rarity_table_info = next_account_info(account_info_iter)?;
+            stake_store.check_rarity_table(rarity_table_info)?;
+            check_account_owner(rarity_table_info, program_id)?;
+            let mut rarity_table_data = rarity_table_info.data.borrow_mut();
+            let (_header, rarity_table) =
+                RarityTableHeader::deserialize_vec(&mut rarity_table_data)?;
+            RarityTableHeader::multiplier_bps(&rarity_table, mint_info.key)?
+        } else {
+            DEFAULT_MULTIPLIER_BPS
+        };
+
+        let mut staker = Self::get_or_create_staker_account(
+            program_id,
+            stake_store_info,
+            depositor_info,
+            staker_info,
+            system_program_info,
+            &stake_list,
+            clock.unix_timestamp,
+        )?;
+        stake_store.config.check_owner_limit(staker.active_stakes)?;
+
+        stake_store.update_rewards(clock.unix_timestamp)?;
         stake_list.push(StakedNFT {
             owner: *depositor_info.key,
             token_mint: *mint_info.key,
             holder: *stake_account_info.key,
             stake_time: clock.unix_timestamp,
+            multiplier_bps,
+            reward_debt: stake_store.reward_debt(multiplier_bps)?,
         })?;
+        header.count = stake_list.len() as u16;
+        header.save(&mut stake_list_data)?;
+        stake_store.add_shares(multiplier_bps)?;
 
         // increase the stake_store's staked_count
-        stake_store.staked_count += amount as u16;
-        header.count += amount as u16;
+        stake_store.staked_count = stake_store.staked_count.saturating_add(1);
 
-        stake_store
-            .serialize(&mut *stake_store_info.data.borrow_mut())
-            .map_err(|e| e.into())
+        staker.accrue(clock.unix_timestamp)?;
+        staker.record_activity(clock.unix_timestamp);
+        staker.add_stake(multiplier_bps)?;
+        staker.save(&mut staker_info.data.borrow_mut())?;
+
+        let fees = &stake_store.config.fees;
+        let fee = fees.fee_for(fees.deposit_fee, depositor_info.key);
+        if fee > 0 {
+            let treasury_info = next_account_info(account_info_iter)?;
+            let payment_info = next_account_info(account_info_iter)?;
+            Self::charge_fee(
+                fees,
+                fee,
+                depositor_info,
+                treasury_info,
+                payment_info,
+                token_program,
+            )?;
+        }
+
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
     }
 
-    fn process_withdraw_nft(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    fn process_withdraw_nft(
+        accounts: &[AccountInfo],
+        unsettled_rewards: UnsettledRewards,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
         let withdrawer_info = next_account_info(account_info_iter)?;
         let mint_info = next_account_info(account_info_iter)?;
         let clock_info = next_account_info(account_info_iter)?;
-        let _clock = &Clock::from_account_info(clock_info)?;
+        let clock = &Clock::from_account_info(clock_info)?;
 
         if !withdrawer_info.is_signer {
             return Err(ProgramError::MissingRequiredSignature);
         }
 
         let stake_store_info = next_account_info(account_info_iter)?;
-        check_account_owner(stake_store_info, program_id)?;
-        let stake_store = try_from_slice_unchecked::<StakeStore>(&stake_store_info.data.borrow())?;
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.config.check_not_paused()?;
         let stake_list_info = next_account_info(account_info_iter)?;
         check_account_owner(stake_list_info, program_id)?;
         stake_store.check_stake_list(&stake_list_info)?;
 
         let mut stake_list_data = stake_list_info.data.borrow_mut();
-        let (_header, mut stake_list) = StakeListHeader::deserialize_vec(&mut stake_list_data)?;
+        let (mut header, mut stake_list) = StakeListHeader::deserialize_vec(&mut stake_list_data)?;
         if 0 == stake_list.len() as u16 {
             return Err(ProgramError::AccountDataTooSmall);
         }
 
         let stake_account_info = next_account_info(account_info_iter)?;
 
-        let staked_nft_info: StakedNFT;
-        {
-            let maybe_staked_nft = stake_list.find_double::<StakedNFT>(
-                withdrawer_info.key.as_ref(),
-                mint_info.key.as_ref(),
-                StakedNFT::memcmp_pubkey,
-            );
-            if maybe_staked_nft.is_none() {
+        let staked_nft_info = match stake_list.find_double::<StakedNFT>(
+            withdrawer_info.key.as_ref(),
+            mint_info.key.as_ref(),
+            StakedNFT::memcmp_pubkey,
+        )? {
+            Some(staked_nft) => staked_nft,
+            None => {
                 msg!(
                     "owner account {}, token mint {} not found in stake list",
                     withdrawer_info.key,
@@ -277,9 +963,7 @@ impl Processor {
                 );
                 return Err(NFTStakingError::StakedNFTNotFound.into());
             }
-
-            staked_nft_info = *maybe_staked_nft.unwrap();
-        }
+        };
         if staked_nft_info.holder != *stake_account_info.key {
             msg!(
                 "owner {} or token mint {} mismatch for staked NFT",
@@ -288,25 +972,21 @@ impl Processor {
             );
             return Err(NFTStakingError::StakedNFTNotFound.into());
         }
+        stake_store
+            .config
+            .check_lockup(&staked_nft_info, clock.unix_timestamp)?;
 
-        let stake_account = TokenAccount::unpack(&stake_account_info.data.borrow())?;
-        let (pda, nonce) = Pubkey::find_program_address(
-            &[
-                TRANSIENT_NFT_STAKE_SEED_PREFIX,
-                &withdrawer_info.key.to_bytes(),
-                &mint_info.key.to_bytes(),
-            ],
-            program_id,
-        );
+        let (pda, nonce) =
+            find_transient_stake_address(program_id, withdrawer_info.key, mint_info.key);
         let pda_account = next_account_info(account_info_iter)?;
         let token_program = next_account_info(account_info_iter)?;
-        let transfer_to_withrawer_ix = spl_token::instruction::transfer(
+        let owner_change_ix = spl_token::instruction::set_authority(
             token_program.key,
             stake_account_info.key,
-            withdrawer_info.key,
+            Some(withdrawer_info.key),
+            spl_token::instruction::AuthorityType::AccountOwner,
             &pda,
             &[&pda],
-            stake_account.amount,
         )?;
 
         let authority_signature_seeds: &[&[u8]] = &[
@@ -316,12 +996,11 @@ impl Processor {
             &[nonce],
         ];
 
-        msg!("Calling the token program to transfer token to the withdrawer...");
+        msg!("Calling the token program to return the stake account to the withdrawer...");
         invoke_signed(
-            &transfer_to_withrawer_ix,
+            &owner_change_ix,
             &[
                 stake_account_info.clone(),
-                withdrawer_info.clone(),
                 pda_account.clone(),
                 token_program.clone(),
             ],
@@ -330,291 +1009,5092 @@ impl Processor {
 
         stake_list
             .retain::<StakedNFT>(StakedNFT::is_not_withdrawn, staked_nft_info.holder.as_ref())?;
+        header.count = stake_list.len() as u16;
+        header.save(&mut stake_list_data)?;
 
         // decrease the stake_store's staked_count
-        // ...
+        stake_store.staked_count = stake_store.staked_count.saturating_sub(1);
 
-        let close_pdas_stake_acc_ix = spl_token::instruction::close_account(
-            token_program.key,
-            stake_account_info.key,
+        // stakes migrated from the first release have no points ledger
+        let staker_info = next_account_info(account_info_iter)?;
+        check_staker_address(
+            program_id,
+            stake_store_info.key,
             withdrawer_info.key,
-            &pda,
-            &[&pda],
-        )?;
-        msg!("Calling the token program to close stake account which has been withdrawn...");
-        invoke_signed(
-            &close_pdas_stake_acc_ix,
-            &[
-                stake_account_info.clone(),
-                withdrawer_info.clone(),
-                pda_account.clone(),
-                token_program.clone(),
-            ],
-            &[authority_signature_seeds],
+            staker_info,
         )?;
+        let mut staker = if staker_info.data_is_empty() {
+            msg!("No points ledger for {}", withdrawer_info.key);
+            None
+        } else {
+            let mut staker = load_staker_account(
+                program_id,
+                stake_store_info.key,
+                withdrawer_info.key,
+                staker_info,
+            )?;
+            staker.accrue(clock.unix_timestamp)?;
+            staker.record_activity(clock.unix_timestamp);
+            staker.remove_stake(staked_nft_info.multiplier_bps);
+            Some(staker)
+        };
 
-        msg!("Closing the stake account...");
-        Ok(())
-    }
-}
+        // the fee accounts come before the optional reward accounts
+        let fees = &stake_store.config.fees;
+        let fee = fees.fee_for(fees.withdraw_fee, withdrawer_info.key);
+        if fee > 0 {
+            let treasury_info = next_account_info(account_info_iter)?;
+            let payment_info = next_account_info(account_info_iter)?;
+            Self::charge_fee(
+                fees,
+                fee,
+                withdrawer_info,
+                treasury_info,
+                payment_info,
+                token_program,
+            )?;
+        }
 
-impl PrintProgramError for NFTStakingError {
-    fn print<E>(&self)
-    where
-        E: 'static + std::error::Error + DecodeError<E> + PrintProgramError + FromPrimitive,
-    {
-        match self {
-            NFTStakingError::AlreadyInUse => {
-                msg!("Error: The account cannot be initialized because it is already being used")
+        // pay out the stake's rewards when the reward accounts follow,
+        // otherwise they stay owed to the owner through its ledger unless
+        // they're explicitly forfeited to the reward vault
+        stake_store.update_rewards(clock.unix_timestamp)?;
+        let pending = stake_store.pending_rewards(&staked_nft_info)?;
+        stake_store.remove_shares(staked_nft_info.multiplier_bps);
+        match (next_account_info(account_info_iter), unsettled_rewards) {
+            (Ok(reward_vault_info), _) => {
+                let destination_info = next_account_info(account_info_iter)?;
+                let reward_authority_info = next_account_info(account_info_iter)?;
+                stake_store.check_reward_vault(reward_vault_info)?;
+                stake_store.release_rewards(pending);
+                if pending > 0 {
+                    msg!("Claiming {} rewards for {}", pending, mint_info.key);
+                    Self::transfer_rewards(
+                        program_id,
+                        stake_store_info,
+                        reward_vault_info,
+                        destination_info,
+                        reward_authority_info,
+                        token_program,
+                        pending,
+                    )?;
+                }
             }
-            NFTStakingError::AmountOverflow => {
-                msg!("Error: Staking has been reached maximum count")
+            (Err(_), UnsettledRewards::Forfeit) => {
+                stake_store.release_rewards(pending);
+                if pending > 0 {
+                    msg!("Forfeiting {} rewards of {}", pending, mint_info.key);
+                }
             }
-            NFTStakingError::ExpectedAmountMismatch => msg!("Error: Expected Amount Mismatch"),
-            NFTStakingError::InvalidInstruction => msg!("Error: Invalid Instruction"),
-            NFTStakingError::InvalidStakeList => msg!("Error: Detect mismatching of Stake List"),
-            NFTStakingError::NotRentExempt => msg!("Error: Not Rent Exempt"),
-            NFTStakingError::SignatureMissing => msg!("Error: Required signature is missing"),
-            NFTStakingError::StakedNFTNotFound => {
-                msg!("Error: Stake account for this nft not found in the list")
+            (Err(_), UnsettledRewards::Credit) => match staker.as_mut() {
+                Some(staker) => {
+                    if pending > 0 {
+                        msg!("Crediting {} rewards of {}", pending, mint_info.key);
+                        staker.credit_rewards(pending)?;
+                    }
+                }
+                None if pending == 0 => {}
+                None => {
+                    msg!("Reward accounts are required without a ledger to credit");
+                    return Err(NFTStakingError::MissingRewardAccounts.into());
+                }
+            },
+            (Err(_), UnsettledRewards::Abort) => {
+                msg!("Reward accounts are required to settle the stake's rewards");
+                return Err(NFTStakingError::MissingRewardAccounts.into());
             }
-            NFTStakingError::ExpectedAccount => {
+        }
+        if let Some(staker) = staker {
+            staker.save(&mut staker_info.data.borrow_mut())?;
+        }
+        stake_store.save(&mut stake_store_info.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn process_reclaim(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let owner_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let stake_list_info = next_account_info(account_info_iter)?;
+        let stake_account_info = next_account_info(account_info_iter)?;
+        let pda_account = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        let staker_info = next_account_info(account_info_iter)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.config.check_not_paused()?;
+        check_account_owner(stake_list_info, program_id)?;
+        stake_store.check_stake_list(stake_list_info)?;
+
+        let mut stake_list_data = stake_list_info.data.borrow_mut();
+        let (mut header, mut stake_list) = StakeListHeader::deserialize_vec(&mut stake_list_data)?;
+        let staked_nft = stake_list
+            .find_double::<StakedNFT>(
+                owner_info.key.as_ref(),
+                mint_info.key.as_ref(),
+                StakedNFT::memcmp_pubkey,
+            )?
+            .filter(|staked_nft| staked_nft.holder == *stake_account_info.key)
+            .ok_or_else(|| {
+                msg!(
+                    "owner account {}, token mint {} not found in stake list",
+                    owner_info.key,
+                    mint_info.key
+                );
+                NFTStakingError::StakedNFTNotFound
+            })?;
+        stake_store
+            .config
+            .check_lockup(&staked_nft, clock.unix_timestamp)?;
+
+        // stakes migrated from the first release have no points ledger, the
+        // deposit is the last thing their owner is known to have signed
+        check_staker_address(program_id, stake_store_info.key, owner_info.key, staker_info)?;
+        let mut staker = if staker_info.data_is_empty() {
+            None
+        } else {
+            Some(load_staker_account(
+                program_id,
+                stake_store_info.key,
+                owner_info.key,
+                staker_info,
+            )?)
+        };
+        let last_active_ts = staker
+            .as_ref()
+            .map_or(staked_nft.stake_time, |staker| staker.last_active_ts);
+        stake_store
+            .config
+            .check_reclaimable(&staked_nft, last_active_ts, clock.unix_timestamp)?;
+
+        let associated_token_address = get_associated_token_address(owner_info.key, mint_info.key);
+        if associated_token_address != *destination_info.key {
+            msg!(
+                "Expected associated token account {}, received {}",
+                associated_token_address,
+                destination_info.key
+            );
+            return Err(NFTStakingError::InvalidAssociatedTokenAccount.into());
+        }
+
+        let (pda, nonce) = find_transient_stake_address(program_id, owner_info.key, mint_info.key);
+        let authority_signature_seeds: &[&[u8]] = &[
+            TRANSIENT_NFT_STAKE_SEED_PREFIX,
+            &owner_info.key.to_bytes(),
+            &mint_info.key.to_bytes(),
+            &[nonce],
+        ];
+        // an NFT staked from another account is moved to the associated
+        // token account, the emptied account is handed back as well
+        if destination_info.key != stake_account_info.key {
+            let stake_account = unpack_token_account(&stake_account_info.data.borrow())?;
+            msg!("Returning mint {} to {}", mint_info.key, destination_info.key);
+            invoke_signed(
+                &spl_token::instruction::transfer(
+                    token_program.key,
+                    stake_account_info.key,
+                    destination_info.key,
+                    &pda,
+                    &[],
+                    stake_account.amount,
+                )?,
+                &[
+                    stake_account_info.clone(),
+                    destination_info.clone(),
+                    pda_account.clone(),
+                    token_program.clone(),
+                ],
+                &[authority_signature_seeds],
+            )?;
+        }
+        invoke_signed(
+            &spl_token::instruction::set_authority(
+                token_program.key,
+                stake_account_info.key,
+                Some(owner_info.key),
+                spl_token::instruction::AuthorityType::AccountOwner,
+                &pda,
+                &[&pda],
+            )?,
+            &[
+                stake_account_info.clone(),
+                pda_account.clone(),
+                token_program.clone(),
+            ],
+            &[authority_signature_seeds],
+        )?;
+
+        stake_list.retain::<StakedNFT>(StakedNFT::is_not_withdrawn, staked_nft.holder.as_ref())?;
+        header.count = stake_list.len() as u16;
+        header.save(&mut stake_list_data)?;
+        stake_store.staked_count = stake_store.staked_count.saturating_sub(1);
+
+        if let Some(staker) = staker.as_mut() {
+            staker.accrue(clock.unix_timestamp)?;
+            staker.remove_stake(staked_nft.multiplier_bps);
+        }
+
+        // the cranker's bounty comes out of the stake's rewards, the owner
+        // keeps the rest on their ledger
+        stake_store.update_rewards(clock.unix_timestamp)?;
+        let pending = stake_store.pending_rewards(&staked_nft)?;
+        stake_store.remove_shares(staked_nft.multiplier_bps);
+        let bounty = pending.min(stake_store.config.reclaim_bounty);
+        let owed = pending - bounty;
+        if owed > 0 {
+            match staker.as_mut() {
+                Some(staker) => staker.credit_rewards(owed)?,
+                None => {
+                    msg!("No points ledger to credit {} rewards to", owed);
+                    return Err(NFTStakingError::MissingStakerAccount.into());
+                }
+            }
+        }
+        match next_account_info(account_info_iter) {
+            Ok(reward_vault_info) => {
+                let bounty_destination_info = next_account_info(account_info_iter)?;
+                let reward_authority_info = next_account_info(account_info_iter)?;
+                stake_store.check_reward_vault(reward_vault_info)?;
+                if bounty > 0 {
+                    msg!("Paying a reclaim bounty of {}", bounty);
+                    stake_store.release_rewards(bounty);
+                    Self::transfer_rewards(
+                        program_id,
+                        stake_store_info,
+                        reward_vault_info,
+                        bounty_destination_info,
+                        reward_authority_info,
+                        token_program,
+                        bounty,
+                    )?;
+                }
+            }
+            Err(_) if bounty > 0 => {
+                msg!("Reward accounts are required to pay the reclaim bounty");
+                return Err(NFTStakingError::MissingRewardAccounts.into());
+            }
+            Err(_) => {}
+        }
+        if let Some(staker) = staker {
+            staker.save(&mut staker_info.data.borrow_mut())?;
+        }
+        msg!("Reclaimed mint {} of {}", mint_info.key, owner_info.key);
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_checkpoint(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let staker_info = next_account_info(account_info_iter)?;
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let stake_list_info = next_account_info(account_info_iter)?;
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        check_account_owner(stake_list_info, program_id)?;
+        stake_store.check_stake_list(stake_list_info)?;
+
+        let owner = load_program_account::<StakerAccount>(staker_info, program_id)?.owner;
+        let mut staker =
+            load_staker_account(program_id, stake_store_info.key, &owner, staker_info)?;
+
+        // walk the owner's entries, each accruing from its stake time or the
+        // last checkpoint, whichever is later
+        let mut stake_list_data = stake_list_info.data.borrow_mut();
+        let (_header, stake_list) = StakeListHeader::deserialize_vec(&mut stake_list_data)?;
+        let mut active_stakes = 0u32;
+        let mut active_weight_bps = 0u64;
+        let mut stake_seconds = 0u64;
+        let mut weighted_stake_seconds = 0u64;
+        for staked_nft in stake_list.iter::<StakedNFT>() {
+            let staked_nft = staked_nft?;
+            if staked_nft.owner != owner {
+                continue;
+            }
+            let since = staked_nft.stake_time.max(staker.last_update_ts);
+            let elapsed = clock.unix_timestamp.saturating_sub(since).max(0) as u64;
+            active_stakes += 1;
+            active_weight_bps += staked_nft.multiplier_bps as u64;
+            stake_seconds = stake_seconds
+                .checked_add(elapsed)
+                .ok_or(NFTStakingError::AmountOverflow)?;
+            weighted_stake_seconds = elapsed
+                .checked_mul(staked_nft.multiplier_bps as u64)
+                .and_then(|weighted| weighted_stake_seconds.checked_add(weighted))
+                .ok_or(NFTStakingError::AmountOverflow)?;
+        }
+
+        staker.add_stake_seconds(stake_seconds, weighted_stake_seconds)?;
+        staker.active_stakes = active_stakes;
+        staker.active_weight_bps = active_weight_bps;
+        staker.last_update_ts = staker.last_update_ts.max(clock.unix_timestamp);
+        msg!(
+            "Checkpointed {}: {} active stakes, {} points",
+            owner,
+            staker.active_stakes,
+            staker.points
+        );
+        staker.save(&mut staker_info.data.borrow_mut())
+    }
+
+    fn process_initialize_rarity_table(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let rarity_table_info = next_account_info(account_info_iter)?;
+        let manager_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+        let rent = &Rent::from_account_info(rent_info)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.check_authority(Role::Admin, manager_info, accounts)?;
+
+        if stake_store.has_rarity_table()
+            || rarity_table_info.key == stake_store_info.key
+            || *rarity_table_info.key == stake_store.stake_list
+        {
+            msg!("Rarity table {} can't be attached", rarity_table_info.key);
+            return Err(NFTStakingError::AlreadyInUse.into());
+        }
+
+        check_account_owner(rarity_table_info, program_id)?;
+        if !rent.is_exempt(rarity_table_info.lamports(), rarity_table_info.data_len()) {
+            msg!("Rarity table not rent-exempt");
+            return Err(ProgramError::AccountNotRentExempt);
+        }
+
+        {
+            let mut rarity_table_data = rarity_table_info.data.borrow_mut();
+            let (header, rarity_table) =
+                RarityTableHeader::deserialize_vec(&mut rarity_table_data)?;
+            if header.is_initialized() {
+                return Err(NFTStakingError::AlreadyInUse.into());
+            }
+            rarity_table.data.iter_mut().for_each(|byte| *byte = 0);
+        }
+        let header = RarityTableHeader {
+            is_initialized: true,
+            stake_store: *stake_store_info.key,
+        };
+        header.save(&mut rarity_table_info.data.borrow_mut())?;
+
+        stake_store.rarity_table = *rarity_table_info.key;
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_upload_rarities(
+        accounts: &[AccountInfo],
+        entries: Vec<RarityEntry>,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let rarity_table_info = next_account_info(account_info_iter)?;
+        let manager_info = next_account_info(account_info_iter)?;
+
+        let stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.check_authority(Role::Admin, manager_info, accounts)?;
+        stake_store.check_rarity_table(rarity_table_info)?;
+        check_account_owner(rarity_table_info, program_id)?;
+
+        let mut rarity_table_data = rarity_table_info.data.borrow_mut();
+        let (header, mut rarity_table) =
+            RarityTableHeader::deserialize_vec(&mut rarity_table_data)?;
+        if !header.is_initialized() {
+            return Err(NFTStakingError::InvalidRarityTable.into());
+        }
+
+        for entry in entries {
+            if entry.multiplier_bps == 0 {
+                msg!("Rarity multiplier of mint {} must not be zero", entry.mint);
+                return Err(NFTStakingError::InvalidRarityMultiplier.into());
+            }
+            // the table is kept sorted by mint, entries uploaded in mint
+            // order are appended without shifting any other
+            let position = rarity_table.binary_search_by::<RarityEntry, _>(|data| {
+                RarityEntry::cmp_mint(data, entry.mint.as_ref())
+            });
+            match position {
+                Ok(index) => {
+                    let mut existing = rarity_table.deserialize_mut_slice::<RarityEntry>(index, 1)?;
+                    existing[0].multiplier_bps = entry.multiplier_bps;
+                }
+                Err(index) => rarity_table.insert(index, entry)?,
+            }
+        }
+        msg!("Rarity table holds {} entries", rarity_table.len());
+        Ok(())
+    }
+
+    fn process_set_allow_list_root(
+        accounts: &[AccountInfo],
+        root: [u8; HASH_BYTES],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let manager_info = next_account_info(account_info_iter)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.check_authority(Role::Admin, manager_info, accounts)?;
+
+        stake_store.allow_list_root = root;
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_fund_rewards(
+        accounts: &[AccountInfo],
+        amount: u64,
+        duration: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let reward_operator_info = next_account_info(account_info_iter)?;
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let reward_vault_info = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        let reward_operator_signers =
+            stake_store.check_authority(Role::RewardOperator, reward_operator_info, accounts)?;
+
+        stake_store.check_reward_vault(reward_vault_info)?;
+        // a funded epoch changes the rate at once, under a config delay
+        // rewards are scheduled with emission segments instead
+        stake_store.check_rate_change_notice(clock.unix_timestamp, clock.unix_timestamp)?;
+
+        stake_store.fund_rewards(amount, duration, clock.unix_timestamp)?;
+        msg!(
+            "Emitting {} per second until {}",
+            stake_store.reward_rate,
+            stake_store.reward_epoch_end
+        );
+
+        Self::transfer_from_authority(
+            source_info,
+            reward_vault_info,
+            reward_operator_info,
+            &reward_operator_signers,
+            token_program,
+            amount,
+        )?;
+
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_claim_rewards(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let owner_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let stake_list_info = next_account_info(account_info_iter)?;
+        let reward_vault_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let reward_authority_info = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.config.check_not_paused()?;
+        check_account_owner(stake_list_info, program_id)?;
+        stake_store.check_stake_list(stake_list_info)?;
+        stake_store.check_reward_vault(reward_vault_info)?;
+        stake_store.update_rewards(clock.unix_timestamp)?;
+
+        let mut stake_list_data = stake_list_info.data.borrow_mut();
+        let (_header, mut stake_list) = StakeListHeader::deserialize_vec(&mut stake_list_data)?;
+        let mut staked_nft = stake_list
+            .find_mut_by::<StakedNFT, _>(|data| {
+                StakedNFT::memcmp_pubkey(data, owner_info.key.as_ref(), mint_info.key.as_ref())
+            })?
+            .ok_or(NFTStakingError::StakedNFTNotFound)?;
+        let pending = stake_store.pending_rewards(&staked_nft)?;
+        staked_nft.reward_debt = stake_store.reward_debt(staked_nft.multiplier_bps)?;
+        staked_nft.commit();
+
+        stake_store.release_rewards(pending);
+
+        // the claim counts as activity on the owner's ledger, when given
+        if let Ok(staker_info) = next_account_info(account_info_iter) {
+            check_staker_address(program_id, stake_store_info.key, owner_info.key, staker_info)?;
+            if !staker_info.data_is_empty() {
+                let mut staker = load_staker_account(
+                    program_id,
+                    stake_store_info.key,
+                    owner_info.key,
+                    staker_info,
+                )?;
+                staker.record_activity(clock.unix_timestamp);
+                staker.save(&mut staker_info.data.borrow_mut())?;
+            }
+        }
+
+        if pending > 0 {
+            msg!("Claiming {} rewards for {}", pending, mint_info.key);
+            Self::transfer_rewards(
+                program_id,
+                stake_store_info,
+                reward_vault_info,
+                destination_info,
+                reward_authority_info,
+                token_program,
+                pending,
+            )?;
+        }
+
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_claim_credited_rewards(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let owner_info = next_account_info(account_info_iter)?;
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let staker_info = next_account_info(account_info_iter)?;
+        let reward_vault_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let reward_authority_info = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+
+        if !owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.config.check_not_paused()?;
+        stake_store.check_reward_vault(reward_vault_info)?;
+        let mut staker =
+            load_staker_account(program_id, stake_store_info.key, owner_info.key, staker_info)?;
+
+        let credited = staker.credited_rewards;
+        staker.credited_rewards = 0;
+        stake_store.release_rewards(credited);
+
+        if credited > 0 {
+            msg!("Claiming {} credited rewards for {}", credited, owner_info.key);
+            Self::transfer_rewards(
+                program_id,
+                stake_store_info,
+                reward_vault_info,
+                destination_info,
+                reward_authority_info,
+                token_program,
+                credited,
+            )?;
+        }
+
+        staker.save(&mut staker_info.data.borrow_mut())?;
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_fund_reward_vault(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let reward_operator_info = next_account_info(account_info_iter)?;
+        let reward_mint_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let reward_vault_info = next_account_info(account_info_iter)?;
+        let reward_authority_info = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+        let rent_info = next_account_info(account_info_iter)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        let reward_operator_signers =
+            stake_store.check_authority(Role::RewardOperator, reward_operator_info, accounts)?;
+
+        if stake_store.reward_vault == Pubkey::default() {
+            stake_store.config.check_reward_mint(reward_mint_info.key)?;
+            Self::create_reward_vault(
+                program_id,
+                stake_store_info,
+                authority_wallet(reward_operator_info, &reward_operator_signers),
+                reward_mint_info,
+                reward_vault_info,
+                reward_authority_info,
+                token_program,
+                system_program_info,
+                rent_info,
+            )?;
+            stake_store.reward_vault = *reward_vault_info.key;
+            stake_store.config.reward_mint = *reward_mint_info.key;
+        }
+        stake_store.check_reward_vault(reward_vault_info)?;
+
+        if amount > 0 {
+            Self::transfer_from_authority(
+                source_info,
+                reward_vault_info,
+                reward_operator_info,
+                &reward_operator_signers,
+                token_program,
+                amount,
+            )?;
+        }
+
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_withdraw_excess_rewards(
+        accounts: &[AccountInfo],
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let reward_operator_info = next_account_info(account_info_iter)?;
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+        let reward_vault_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let reward_authority_info = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.check_authority(Role::RewardOperator, reward_operator_info, accounts)?;
+        stake_store.check_reward_vault(reward_vault_info)?;
+        stake_store.update_rewards(clock.unix_timestamp)?;
+
+        let balance = unpack_token_account(&reward_vault_info.data.borrow())?.amount;
+        let status = stake_store.reward_vault_status(balance)?;
+        if amount > status.excess {
+            msg!(
+                "Can't withdraw {}, only {} of the vault's {} isn't owed",
+                amount,
+                status.excess,
+                status.balance
+            );
+            return Err(NFTStakingError::InsufficientExcessRewards.into());
+        }
+
+        Self::transfer_rewards(
+            program_id,
+            stake_store_info,
+            reward_vault_info,
+            destination_info,
+            reward_authority_info,
+            token_program,
+            amount,
+        )?;
+
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_append_emission_segments(
+        accounts: &[AccountInfo],
+        segments: Vec<EmissionSegment>,
+        end_ts: UnixTimestamp,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let reward_operator_info = next_account_info(account_info_iter)?;
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.check_authority(Role::RewardOperator, reward_operator_info, accounts)?;
+
+        let change_ts = stake_store
+            .config
+            .emission_schedule
+            .first_change_ts(&segments, end_ts);
+        // settle the old schedule before changing what follows
+        stake_store.update_rewards(clock.unix_timestamp)?;
+        stake_store.config.emission_schedule.append(
+            &segments,
+            end_ts,
+            clock.unix_timestamp,
+        )?;
+        // rate changes leave stakers the config delay as notice
+        if let Some(change_ts) = change_ts {
+            stake_store.check_rate_change_notice(change_ts, clock.unix_timestamp)?;
+        }
+        msg!(
+            "Emission schedule holds {} segments until {}",
+            stake_store.config.emission_schedule.segment_count,
+            end_ts
+        );
+
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_sunset(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let manager_info = next_account_info(account_info_iter)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.check_authority(Role::Admin, manager_info, accounts)?;
+
+        stake_store.sunset = true;
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_close_pool(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let stake_list_info = next_account_info(account_info_iter)?;
+        let manager_info = next_account_info(account_info_iter)?;
+        let rent_destination_info = next_account_info(account_info_iter)?;
+
+        let stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.check_authority(Role::Admin, manager_info, accounts)?;
+        check_account_owner(stake_list_info, program_id)?;
+        stake_store.check_stake_list(stake_list_info)?;
+        // rent left with a multisig couldn't be withdrawn again
+        check_authority_wallet(manager_info, rent_destination_info)?;
+
+        {
+            let mut stake_list_data = stake_list_info.data.borrow_mut();
+            let (_header, stake_list) = StakeListHeader::deserialize_vec(&mut stake_list_data)?;
+            if !stake_list.is_empty() || !stake_store.is_empty() || stake_store.staked_count > 0 {
+                msg!(
+                    "Pool still holds {} shares of {} NFTs, {} in the stake list",
+                    stake_store.total_shares,
+                    stake_store.staked_count,
+                    stake_list.len()
+                );
+                return Err(NFTStakingError::PoolNotEmpty.into());
+            }
+        }
+        if stake_store.unclaimed_rewards > 0 {
+            msg!("Stakers are still owed {} rewards", stake_store.unclaimed_rewards);
+            return Err(NFTStakingError::PoolNotEmpty.into());
+        }
+
+        if stake_store.has_rarity_table() {
+            let rarity_table_info = next_account_info(account_info_iter)?;
+            stake_store.check_rarity_table(rarity_table_info)?;
+            check_account_owner(rarity_table_info, program_id)?;
+            close_program_account(rarity_table_info, rent_destination_info)?;
+        }
+
+        if stake_store.reward_vault != Pubkey::default() {
+            let reward_vault_info = next_account_info(account_info_iter)?;
+            let reward_authority_info = next_account_info(account_info_iter)?;
+            let token_program = next_account_info(account_info_iter)?;
+            stake_store.check_reward_vault(reward_vault_info)?;
+            let balance = unpack_token_account(&reward_vault_info.data.borrow())?.amount;
+            if balance > 0 {
+                msg!("Withdraw the {} tokens left in the reward vault first", balance);
+                return Err(NFTStakingError::RewardVaultNotEmpty.into());
+            }
+
+            let (reward_authority, bump_seed) =
+                find_reward_authority_address(program_id, stake_store_info.key);
+            let reward_authority_seeds: &[&[u8]] = &[
+                REWARD_AUTHORITY_SEED_PREFIX,
+                &stake_store_info.key.to_bytes(),
+                &[bump_seed],
+            ];
+            invoke_signed(
+                &spl_token::instruction::close_account(
+                    token_program.key,
+                    reward_vault_info.key,
+                    rent_destination_info.key,
+                    &reward_authority,
+                    &[],
+                )?,
+                &[
+                    reward_vault_info.clone(),
+                    rent_destination_info.clone(),
+                    reward_authority_info.clone(),
+                    token_program.clone(),
+                ],
+                &[reward_authority_seeds],
+            )?;
+        }
+
+        msg!("Closing pool {}", stake_store_info.key);
+        close_program_account(stake_list_info, rent_destination_info)?;
+        close_program_account(stake_store_info, rent_destination_info)
+    }
+
+    fn process_set_stake_limits(
+        accounts: &[AccountInfo],
+        max_per_owner: u32,
+        max_total: u32,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let manager_info = next_account_info(account_info_iter)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.check_authority(Role::Admin, manager_info, accounts)?;
+        stake_store.check_config_unlocked()?;
+
+        // lowering a cap below the current stakes only blocks new deposits
+        stake_store.config.max_per_owner = max_per_owner;
+        stake_store.config.max_total = max_total;
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_set_paused(
+        accounts: &[AccountInfo],
+        paused: bool,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let pauser_info = next_account_info(account_info_iter)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.check_authority(Role::Pauser, pauser_info, accounts)?;
+
+        msg!("Pool {} paused: {}", stake_store_info.key, paused);
+        stake_store.config.paused = paused;
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_set_fees(
+        accounts: &[AccountInfo],
+        fees: FeeConfig,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let manager_info = next_account_info(account_info_iter)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.check_authority(Role::Admin, manager_info, accounts)?;
+        stake_store.check_config_unlocked()?;
+
+        fees.check()?;
+        stake_store.config.fees = fees;
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_set_authority(
+        accounts: &[AccountInfo],
+        role: Role,
+        authority: Pubkey,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let manager_info = next_account_info(account_info_iter)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.check_authority(Role::Admin, manager_info, accounts)?;
+
+        stake_store.set_authority(role, authority)?;
+        msg!("{:?} of pool {} is now {}", role, stake_store_info.key, stake_store.authority(role));
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_propose_config(
+        accounts: &[AccountInfo],
+        config: PoolConfig,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let manager_info = next_account_info(account_info_iter)?;
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.check_authority(Role::Admin, manager_info, accounts)?;
+
+        stake_store.propose_config(config, clock.unix_timestamp)?;
+        msg!(
+            "Config of pool {} changes at {}",
+            stake_store_info.key,
+            stake_store.pending_config.effective_at
+        );
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_apply_pending_config(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.apply_pending_config(clock.unix_timestamp)?;
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_cancel_pending_config(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let manager_info = next_account_info(account_info_iter)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.check_authority(Role::Admin, manager_info, accounts)?;
+
+        stake_store.cancel_pending_config()?;
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_set_stake_tiers(
+        accounts: &[AccountInfo],
+        thresholds: Vec<u64>,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let manager_info = next_account_info(account_info_iter)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.check_authority(Role::Admin, manager_info, accounts)?;
+        stake_store.check_config_unlocked()?;
+
+        // existing tier records follow the new thresholds when next recorded
+        stake_store.config.stake_tiers.set(&thresholds)?;
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+
+    fn process_record_tier(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let payer_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let stake_list_info = next_account_info(account_info_iter)?;
+        let tier_record_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+
+        if !payer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        check_account_owner(stake_list_info, program_id)?;
+        stake_store.check_stake_list(stake_list_info)?;
+
+        let (tier_record_address, bump_seed) =
+            find_tier_record_address(program_id, stake_store_info.key, mint_info.key);
+        if tier_record_address != *tier_record_info.key {
+            msg!(
+                "Incorrect tier record address for mint {}, expected {}, received {}",
+                mint_info.key,
+                tier_record_address,
+                tier_record_info.key
+            );
+            return Err(NFTStakingError::InvalidTierRecord.into());
+        }
+
+        let staked_nft = {
+            let mut stake_list_data = stake_list_info.data.borrow_mut();
+            let (_header, stake_list) = StakeListHeader::deserialize_vec(&mut stake_list_data)?;
+            stake_list
+                .find_unpacked::<StakedNFT, _>(|staked_nft| staked_nft.token_mint == *mint_info.key)?
+        };
+
+        let tier_record = match staked_nft {
+            Some(staked_nft) => {
+                if tier_record_info.data_is_empty() {
+                    check_system_program(system_program_info.key)?;
+                    let rent = Rent::get()?;
+                    let tier_record_signer_seeds: &[&[u8]] = &[
+                        TIER_RECORD_SEED_PREFIX,
+                        &stake_store_info.key.to_bytes(),
+                        &mint_info.key.to_bytes(),
+                        &[bump_seed],
+                    ];
+                    msg!("Creating tier record for {}", mint_info.key);
+                    invoke_signed(
+                        &system_instruction::create_account(
+                            payer_info.key,
+                            tier_record_info.key,
+                            rent.minimum_balance(TierRecord::LEN),
+                            TierRecord::LEN as u64,
+                            program_id,
+                        ),
+                        &[
+                            payer_info.clone(),
+                            tier_record_info.clone(),
+                            system_program_info.clone(),
+                        ],
+                        &[tier_record_signer_seeds],
+                    )?;
+                }
+                // an existing record must be of the current layout
+                load_program_account::<TierRecord>(tier_record_info, program_id)?;
+                TierRecord {
+                    is_initialized: true,
+                    stake_store: *stake_store_info.key,
+                    token_mint: *mint_info.key,
+                    owner: staked_nft.owner,
+                    stake_time: staked_nft.stake_time,
+                    tier: staked_nft.tier(&stake_store.config.stake_tiers, clock.unix_timestamp),
+                    recorded_at: clock.unix_timestamp,
+                }
+            }
+            None => {
+                // a withdrawn NFT loses its tier, readers shouldn't trust a
+                // stale record
+                let tier_record = load_program_account::<TierRecord>(tier_record_info, program_id)?;
+                if !tier_record.is_initialized() {
+                    return Err(NFTStakingError::StakedNFTNotFound.into());
+                }
+                TierRecord {
+                    tier: 0,
+                    recorded_at: clock.unix_timestamp,
+                    ..tier_record
+                }
+            }
+        };
+        msg!("Mint {} is at tier {}", mint_info.key, tier_record.tier);
+        tier_record.save(&mut tier_record_info.data.borrow_mut())
+    }
+
+    fn process_verify_stake(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let owner_info = next_account_info(account_info_iter)?;
+        let mint_info = next_account_info(account_info_iter)?;
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let stake_list_info = next_account_info(account_info_iter)?;
+
+        let stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        if !stake_store.is_initialized() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        check_account_owner(stake_list_info, program_id)?;
+        stake_store.check_stake_list(stake_list_info)?;
+
+        let mut stake_list_data = stake_list_info.data.borrow_mut();
+        let (_header, stake_list) = StakeListHeader::deserialize_vec(&mut stake_list_data)?;
+        let staked = stake_list
+            .position::<StakedNFT, _>(|data| {
+                StakedNFT::memcmp_pubkey(data, owner_info.key.as_ref(), mint_info.key.as_ref())
+            })
+            .is_some();
+        if !staked {
+            msg!("{} doesn't have {} staked", owner_info.key, mint_info.key);
+            return Err(NFTStakingError::StakedNFTNotFound.into());
+        }
+        Ok(())
+    }
+
+    /// Load the stake of a mint along with the stake store, its rewards
+    /// brought up to the clock in memory only
+    fn load_stake_view(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> Result<(StakeStore, StakedNFT, UnixTimestamp), ProgramError> {
+        let account_info_iter = &mut accounts.iter();
+        let mint_info = next_account_info(account_info_iter)?;
+        let clock_info = next_account_info(account_info_iter)?;
+        let clock = &Clock::from_account_info(clock_info)?;
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let stake_list_info = next_account_info(account_info_iter)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        if !stake_store.is_initialized() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        check_account_owner(stake_list_info, program_id)?;
+        stake_store.check_stake_list(stake_list_info)?;
+        stake_store.update_rewards(clock.unix_timestamp)?;
+
+        let mut stake_list_data = stake_list_info.data.borrow_mut();
+        let (_header, stake_list) = StakeListHeader::deserialize_vec(&mut stake_list_data)?;
+        let staked_nft = stake_list
+            .find_unpacked::<StakedNFT, _>(|staked_nft| staked_nft.token_mint == *mint_info.key)?
+            .ok_or(NFTStakingError::StakedNFTNotFound)?;
+        Ok((stake_store, staked_nft, clock.unix_timestamp))
+    }
+
+    fn process_get_pending_rewards(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let (stake_store, staked_nft, now) = Self::load_stake_view(accounts, program_id)?;
+        let pending_rewards = PendingRewards {
+            owner: staked_nft.owner,
+            token_mint: staked_nft.token_mint,
+            pending: stake_store.pending_rewards(&staked_nft)?,
+            as_of: now,
+        };
+        set_return_data(&pending_rewards.try_to_vec()?);
+        Ok(())
+    }
+
+    fn process_get_stake_info(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let (stake_store, staked_nft, now) = Self::load_stake_view(accounts, program_id)?;
+        let stake_info = StakeInfo {
+            owner: staked_nft.owner,
+            token_mint: staked_nft.token_mint,
+            stake_time: staked_nft.stake_time,
+            stake_age: now.saturating_sub(staked_nft.stake_time).max(0) as u64,
+            multiplier_bps: staked_nft.multiplier_bps,
+            tier: staked_nft.tier(&stake_store.config.stake_tiers, now),
+            pending_rewards: stake_store.pending_rewards(&staked_nft)?,
+            as_of: now,
+        };
+        set_return_data(&stake_info.try_to_vec()?);
+        Ok(())
+    }
+
+    fn process_migrate(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let stake_list_info = next_account_info(account_info_iter)?;
+        let manager_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+
+        check_account_owner(stake_store_info, program_id)?;
+        let (version, mut stake_store) = StakeStore::decode(&stake_store_info.data.borrow())?;
+        if !stake_store.is_initialized() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        let manager_signers =
+            stake_store.check_authority(Role::Admin, manager_info, accounts)?;
+        let payer_info = authority_wallet(manager_info, &manager_signers);
+        check_account_owner(stake_list_info, program_id)?;
+        stake_store.check_stake_list(stake_list_info)?;
+        if version == CURRENT_VERSION {
+            msg!("Pool {} is already at version {}", stake_store_info.key, version);
+        } else if version == V2_VERSION {
+            // only staker accounts changed layout since, below
+            AccountHeader::write(
+                &mut stake_list_info.data.borrow_mut(),
+                &StakeListHeader::DISCRIMINATOR,
+            )?;
+            stake_store.save(&mut stake_store_info.data.borrow_mut())?;
+            msg!(
+                "Migrated pool {} from version {} to {}",
+                stake_store_info.key,
+                version,
+                CURRENT_VERSION
+            );
+        } else {
+            // the list was written alongside its stake store, so it has the
+            // same headerless layout. An instruction can only grow it by
+            // MAX_PERMITTED_DATA_INCREASE, so a large list takes several calls
+            let migrated_len = StakeListHeader::migrated_len(&stake_list_info.data.borrow())?;
+            let grown_len =
+                migrated_len.min(stake_list_info.data_len() + MAX_PERMITTED_DATA_INCREASE);
+            resize_account(stake_list_info, payer_info, system_program_info, grown_len)?;
+            if grown_len < migrated_len {
+                msg!(
+                    "Stake list grown to {} of {} bytes, call Migrate again",
+                    grown_len,
+                    migrated_len
+                );
+                return Ok(());
+            }
+            let count = StakeListHeader::migrate(&mut stake_list_info.data.borrow_mut(), version)?;
+            let mut stake_list_data = stake_list_info.data.borrow_mut();
+            let (mut header, _) = StakeListHeader::deserialize_vec(&mut stake_list_data)?;
+            // older programs didn't keep the count in step with the entries
+            header.count = count
+                .try_into()
+                .map_err(|_| NFTStakingError::AmountOverflow)?;
+            header.save(&mut stake_list_data)?;
+            drop(stake_list_data);
+            stake_store.config.max_items = header.max_items;
+            if version == LEGACY_VERSION {
+                stake_store.staked_count = count
+                    .try_into()
+                    .map_err(|_| NFTStakingError::AmountOverflow)?;
+                stake_store.total_shares = count as u64 * DEFAULT_MULTIPLIER_BPS as u64;
+            }
+
+            resize_account(
+                stake_store_info,
+                payer_info,
+                system_program_info,
+                StakeStore::packed_len(),
+            )?;
+            stake_store.save(&mut stake_store_info.data.borrow_mut())?;
+            msg!(
+                "Migrated pool {} from version {} to {}",
+                stake_store_info.key,
+                version,
+                CURRENT_VERSION
+            );
+        }
+
+        // ledgers can outnumber what fits in a transaction, so they may be
+        // migrated over several calls once the pool is current. Program
+        // accounts never sign, signers are members of a multisig manager
+        for account_info in account_info_iter.filter(|account_info| !account_info.is_signer) {
+            check_account_owner(account_info, program_id)?;
+            if !matches!(account_info.data.borrow().first(), Some(0) | Some(1)) {
+                Self::migrate_headed_account(account_info, payer_info, system_program_info)?;
+                continue;
+            }
+            let (discriminator, len) = headerless_pool_account(
+                program_id,
+                stake_store_info.key,
+                &stake_store,
+                account_info,
+            )?;
+            resize_account(
+                account_info,
+                payer_info,
+                system_program_info,
+                AccountHeader::LEN + len,
+            )?;
+            AccountHeader::migrate(&mut account_info.data.borrow_mut(), &discriminator, len)?;
+            if discriminator == RarityTableHeader::DISCRIMINATOR {
+                // older programs appended entries in upload order
+                let mut rarity_table_data = account_info.data.borrow_mut();
+                let (_header, mut rarity_table) =
+                    RarityTableHeader::deserialize_vec(&mut rarity_table_data)?;
+                rarity_table.sort_by::<RarityEntry, _>(RarityEntry::cmp_mint);
+            }
+            msg!("Migrated {} to version {}", account_info.key, CURRENT_VERSION);
+        }
+        Ok(())
+    }
+
+    /// Brings an account that already has a header to the current version
+    fn migrate_headed_account<'a>(
+        account_info: &AccountInfo<'a>,
+        payer_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        let header = account_info
+            .data
+            .borrow()
+            .get(..AccountHeader::LEN)
+            .map(AccountHeader::try_from_slice)
+            .ok_or(NFTStakingError::InvalidAccountType)??;
+        if header.version == CURRENT_VERSION {
+            msg!("Account {} is already at version {}", account_info.key, header.version);
+            return Ok(());
+        }
+        match (header.discriminator, header.version) {
+            // `credited_rewards` and `last_active_ts` start out at 0
+            (StakerAccount::DISCRIMINATOR, V2_VERSION) => resize_account(
+                account_info,
+                payer_info,
+                system_program_info,
+                StakerAccount::LEN,
+            )?,
+            (TierRecord::DISCRIMINATOR, V2_VERSION)
+            | (RarityTableHeader::DISCRIMINATOR, V2_VERSION) => {}
+            _ => return Err(NFTStakingError::InvalidAccountType.into()),
+        }
+        AccountHeader::write(&mut account_info.data.borrow_mut(), &header.discriminator)?;
+        msg!("Migrated {} to version {}", account_info.key, CURRENT_VERSION);
+        Ok(())
+    }
+
+    fn process_set_metadata_requirements(
+        accounts: &[AccountInfo],
+        required_collection: Pubkey,
+        required_creator: Pubkey,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let stake_store_info = next_account_info(account_info_iter)?;
+        let manager_info = next_account_info(account_info_iter)?;
+
+        let mut stake_store = load_program_account::<StakeStore>(stake_store_info, program_id)?;
+        stake_store.check_authority(Role::Admin, manager_info, accounts)?;
+        // the creator isn't part of the config, the collection is
+        if required_collection != stake_store.config.required_collection {
+            stake_store.check_config_unlocked()?;
+        }
+
+        stake_store.config.required_collection = required_collection;
+        stake_store.required_creator = required_creator;
+        stake_store.save(&mut stake_store_info.data.borrow_mut())
+    }
+}
+
+impl PrintProgramError for NFTStakingError {
+    fn print<E>(&self)
+    where
+        E: 'static + std::error::Error + DecodeError<E> + PrintProgramError + FromPrimitive,
+    {
+        match self {
+            NFTStakingError::AlreadyInUse => {
+                msg!("Error: The account cannot be initialized because it is already being used")
+            }
+            NFTStakingError::AmountOverflow => {
+                msg!("Error: Staking has been reached maximum count")
+            }
+            NFTStakingError::ExpectedAmountMismatch => msg!("Error: Expected Amount Mismatch"),
+            NFTStakingError::InvalidInstruction => msg!("Error: Invalid Instruction"),
+            NFTStakingError::InvalidStakeList => msg!("Error: Detect mismatching of Stake List"),
+            NFTStakingError::NotRentExempt => msg!("Error: Not Rent Exempt"),
+            NFTStakingError::SignatureMissing => msg!("Error: Required signature is missing"),
+            NFTStakingError::StakedNFTNotFound => {
+                msg!("Error: Stake account for this nft not found in the list")
+            }
+            NFTStakingError::ExpectedAccount => {
                 msg!("The deserialization of the account returned something besides State::Account")
             }
+            NFTStakingError::InvalidStakerAccount => {
+                msg!("Error: Points ledger does not belong to this owner and stake store")
+            }
+            NFTStakingError::InvalidManager => {
+                msg!("Error: Manager doesn't match the stake store's manager")
+            }
+            NFTStakingError::InvalidRarityTable => msg!("Error: Detect mismatching of Rarity Table"),
+            NFTStakingError::InvalidRarityMultiplier => {
+                msg!("Error: Rarity multiplier must be greater than zero")
+            }
+            NFTStakingError::InvalidAllowListProof => {
+                msg!("Error: The mint isn't part of the stake store's allow-list")
+            }
+            NFTStakingError::MintMismatch => {
+                msg!("Error: The token account doesn't hold the deposited mint")
+            }
+            NFTStakingError::InvalidMetadataAccount => {
+                msg!("Error: Metadata account isn't the token-metadata PDA of the mint")
+            }
+            NFTStakingError::CollectionMismatch => {
+                msg!("Error: The NFT doesn't belong to the required collection")
+            }
+            NFTStakingError::UnverifiedCollection => {
+                msg!("Error: The NFT's collection hasn't been verified")
+            }
+            NFTStakingError::CreatorMismatch => {
+                msg!("Error: The required creator isn't listed in the NFT's creators")
+            }
+            NFTStakingError::UnverifiedCreator => {
+                msg!("Error: The required creator hasn't verified the NFT")
+            }
+            NFTStakingError::InvalidRewardVault => {
+                msg!("Error: Detect mismatching of Reward Vault")
+            }
+            NFTStakingError::InvalidRewardEpoch => {
+                msg!("Error: Reward epochs must last at least a second")
+            }
+            NFTStakingError::InsufficientExcessRewards => {
+                msg!("Error: Withdrawal would take rewards owed to stakers")
+            }
+            NFTStakingError::MissingRewardAccounts => {
+                msg!("Error: Reward accounts are required to settle rewards")
+            }
+            NFTStakingError::InvalidEmissionSchedule => {
+                msg!("Error: Emission segments must start in the future, in order, before the end")
+            }
+            NFTStakingError::PoolSunset => {
+                msg!("Error: The pool is being decommissioned and takes no new deposits")
+            }
+            NFTStakingError::PoolNotEmpty => {
+                msg!("Error: The pool can't be closed while NFTs are staked")
+            }
+            NFTStakingError::RewardVaultNotEmpty => {
+                msg!("Error: The reward vault must be emptied before the pool is closed")
+            }
+            NFTStakingError::OwnerStakeLimitExceeded => {
+                msg!("Error: The owner already has as many NFTs staked as allowed")
+            }
+            NFTStakingError::PoolCapacityExceeded => {
+                msg!("Error: The pool already holds as many NFTs as it's capped at")
+            }
+            NFTStakingError::InvalidStakeTiers => {
+                msg!("Error: Stake tier thresholds must be positive and increasing")
+            }
+            NFTStakingError::InvalidTierRecord => {
+                msg!("Error: Tier record isn't the PDA of the stake store and mint")
+            }
+            NFTStakingError::InvalidAccountType => {
+                msg!("Error: The account isn't of the expected kind")
+            }
+            NFTStakingError::AccountNeedsMigration => {
+                msg!("Error: The account has an older layout and must be migrated first")
+            }
+            NFTStakingError::InvalidPoolConfig => msg!("Error: The pool settings are out of range"),
+            NFTStakingError::InvalidFeeConfig => {
+                msg!("Error: The fee settings are incomplete or out of range")
+            }
+            NFTStakingError::PoolPaused => {
+                msg!("Error: Deposits, withdrawals and claims are halted")
+            }
+            NFTStakingError::StakeLocked => {
+                msg!("Error: The NFT can't be withdrawn before its lockup ends")
+            }
+            NFTStakingError::InvalidTreasury => {
+                msg!("Error: The fee treasury doesn't match the pool's fee settings")
+            }
+            NFTStakingError::ReclaimTooEarly => {
+                msg!("Error: The NFT hasn't been staked for the pool's reclaim timeout")
+            }
+            NFTStakingError::InvalidAssociatedTokenAccount => {
+                msg!("Error: The destination isn't the owner's associated token account")
+            }
+            NFTStakingError::InvalidRewardOperator => {
+                msg!("Error: Account isn't the pool's reward operator")
+            }
+            NFTStakingError::InvalidPauser => msg!("Error: Account isn't the pool's pauser"),
+            NFTStakingError::NoPendingConfig => msg!("Error: No config change is pending"),
+            NFTStakingError::ConfigTimelocked => {
+                msg!("Error: The pending config can't be applied before its delay has passed")
+            }
+            NFTStakingError::ConfigChangeNeedsProposal => {
+                msg!("Error: The config can only change through a proposal")
+            }
+            NFTStakingError::MissingStakerAccount => {
+                msg!("Error: The owner has no staker account to credit rewards to")
+            }
+            NFTStakingError::RewardRateTimelocked => {
+                msg!("Error: The reward rate can't change before the config delay")
+            }
+            NFTStakingError::InvalidRentDestination => {
+                msg!("Error: The rent destination doesn't belong to the manager")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+
+mod tests {
+    use super::*;
+    use crate::{
+        instruction::{
+            append_emission_segments, apply_pending_config, cancel_pending_config, checkpoint,
+            claim_credited_rewards, claim_rewards, close_pool, deposit_nft, fund_reward_vault,
+            fund_rewards, get_pending_rewards, get_stake_info, initialize,
+            initialize_rarity_table, migrate, propose_config, reclaim, record_tier,
+            set_allow_list_root, set_authority, set_fees, set_metadata_requirements, set_paused,
+            set_stake_limits, set_stake_tiers, sunset, upload_rarities, upload_rarities_in_chunks,
+            verify_stake, with_authority_signers, withdraw_excess_rewards, withdraw_nft,
+            FeePayment, PendingRewards, StakeInfo,
+        },
+        merkle::MerkleTree,
+        metadata::{
+            find_metadata_address, metadata_program, Collection, Creator, Data, Metadata,
+            METADATA_V1_KEY,
+        },
+        state::{
+            FeeConfig, FeeExemptions, RewardVaultStatus, StakeList, StakeListHeader, StakeStore,
+            StakedNFT, TierRecord, V1PoolConfig, V1StakeStore, V1_VERSION,
+        },
+        utils::{
+            get_associated_token_address, test_utils::*, unpack_token_account, BASIS_POINTS,
+            MAX_ITEMS, SECONDS_PER_POINT,
+        },
+    };
+    use borsh::{BorshDeserialize, BorshSerialize};
+    use solana_program::{
+        borsh::{get_instance_packed_len, get_packed_len},
+        clock::Epoch,
+        instruction::{AccountMeta, Instruction},
+        msg,
+        program_pack::Pack,
+        rent::Rent,
+        sysvar,
+    };
+    use solana_sdk::account::{create_account_for_test, Account, WritableAccount};
+    use spl_token::{
+        instruction::{initialize_account, initialize_mint, mint_to, transfer, MAX_SIGNERS},
+        state::{Account as SplAccount, Mint as SplMint, Multisig},
+    };
+
+    struct TestPool {
+        manager_key: Pubkey,
+        stake_store_key: Pubkey,
+        stake_store_account: Account,
+        stake_list_key: Pubkey,
+        stake_list_account: Account,
+    }
+
+    impl TestPool {
+        fn new(max_items: usize) -> Self {
+            Self::with_config(max_items, PoolConfig::default()).unwrap()
+        }
+
+        /// Initializes a pool whose stake list fits `max_items` NFTs
+        fn with_config(max_items: usize, config: PoolConfig) -> Result<Self, ProgramError> {
+            Self::with_manager(
+                max_items,
+                config,
+                (pubkey_rand(), &mut Account::default()),
+                &[],
+            )
+        }
+
+        /// Initializes a pool managed by `manager`, a multisig when
+        /// `signer_keys` are its signing members
+        fn with_manager(
+            max_items: usize,
+            config: PoolConfig,
+            manager: (Pubkey, &mut Account),
+            signer_keys: &[&Pubkey],
+        ) -> Result<Self, ProgramError> {
+            let store_size = StakeStore::packed_len();
+            let list_size = StakeListHeader::packed_len() + 4 + StakedNFT::LEN * max_items;
+            let (manager_key, manager_account) = manager;
+            let mut pool = Self {
+                manager_key,
+                stake_store_key: pubkey_rand(),
+                stake_store_account: Account::new(
+                    Rent::default().minimum_balance(store_size),
+                    store_size,
+                    &STAKE_PROGRAM_ID,
+                ),
+                stake_list_key: pubkey_rand(),
+                stake_list_account: Account::new(
+                    Rent::default().minimum_balance(list_size),
+                    list_size,
+                    &STAKE_PROGRAM_ID,
+                ),
+            };
+            let mut instruction = initialize(
+                &STAKE_PROGRAM_ID,
+                &pool.stake_store_key,
+                &pool.stake_list_key,
+                &pool.manager_key,
+                config,
+            )
+            .unwrap();
+            if !signer_keys.is_empty() {
+                instruction = with_authority_signers(instruction, &manager_key, signer_keys);
+            }
+            let mut rent_account = create_account_for_test(&Rent::default());
+            let mut signer_accounts = vec![Account::default(); signer_keys.len()];
+            let mut accounts = vec![
+                &mut pool.stake_store_account,
+                &mut pool.stake_list_account,
+                manager_account,
+                &mut rent_account,
+            ];
+            accounts.extend(signer_accounts.iter_mut());
+            do_process_instruction(instruction, accounts)?;
+            Ok(pool)
+        }
+
+        fn stake_store(&self) -> StakeStore {
+            StakeStore::load(&self.stake_store_account.data).unwrap()
+        }
+
+        fn staked_nfts(&mut self) -> Vec<StakedNFT> {
+            let (_header, stake_list) =
+                StakeListHeader::deserialize_vec(&mut self.stake_list_account.data).unwrap();
+            stake_list
+                .iter::<StakedNFT>()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        }
+
+        fn deposit(
+            &mut self,
+            nft: &mut TestNFT,
+            staker_account: &mut Account,
+            ts: i64,
+            rarity_table: Option<(&Pubkey, &mut Account)>,
+            proof: Option<AllowListProof>,
+        ) -> ProgramResult {
+            self.deposit_paying(nft, staker_account, ts, rarity_table, proof, None)
+        }
+
+        /// Deposits the NFT, with any optional trailing accounts the
+        /// instruction needs
+        fn deposit_paying(
+            &mut self,
+            nft: &mut TestNFT,
+            staker_account: &mut Account,
+            ts: i64,
+            rarity_table: Option<(&Pubkey, &mut Account)>,
+            proof: Option<AllowListProof>,
+            fee: Option<&mut TestFeePayment>,
+        ) -> ProgramResult {
+            let has_metadata_requirements = self.stake_store().has_metadata_requirements();
+            let mut accounts = vec![
+                &mut nft.owner_account,
+                &mut nft.mint_account,
+            ];
+            let mut clock = clock_account(ts);
+            let mut token_program_account = Account::default();
+            let mut system_program_account = Account::default();
+            accounts.push(&mut clock);
+            accounts.push(&mut nft.stake_account);
+            accounts.push(&mut self.stake_store_account);
+            accounts.push(&mut self.stake_list_account);
+            accounts.push(&mut token_program_account);
+            accounts.push(staker_account);
+            accounts.push(&mut system_program_account);
+            let metadata_key = if has_metadata_requirements {
+                accounts.push(&mut nft.metadata_account);
+                Some(nft.metadata_key)
+            } else {
+                None
+            };
+            let rarity_table_key = rarity_table.as_ref().map(|(key, _)| **key);
+            if let Some((_, rarity_table_account)) = rarity_table {
+                accounts.push(rarity_table_account);
+            }
+            let fee_payment = fee.as_ref().map(|fee| fee.payment);
+            if let Some(fee) = fee {
+                accounts.push(&mut fee.treasury_account);
+                accounts.push(&mut fee.payment_account);
+            }
+            do_process_instruction(
+                deposit_nft(
+                    &STAKE_PROGRAM_ID,
+                    &nft.owner_key,
+                    &nft.mint_key,
+                    &nft.stake_key,
+                    &self.stake_store_key,
+                    &self.stake_list_key,
+                    metadata_key.as_ref(),
+                    rarity_table_key.as_ref(),
+                    1,
+                    proof,
+                    fee_payment.as_ref(),
+                )
+                .unwrap(),
+                accounts,
+            )
+        }
+    }
+
+    /// Accounts of a staker paying a deposit or withdrawal fee
+    struct TestFeePayment {
+        payment: FeePayment,
+        treasury_account: Account,
+        /// System program for lamport fees, the owner's token account for
+        /// token fees
+        payment_account: Account,
+    }
+
+    /// An NFT sitting in a token account of its owner, ready to be deposited
+    struct TestNFT {
+        owner_key: Pubkey,
+        owner_account: Account,
+        mint_key: Pubkey,
+        mint_account: Account,
+        stake_key: Pubkey,
+        stake_account: Account,
+        metadata_key: Pubkey,
+        metadata_account: Account,
+    }
+
+    impl TestNFT {
+        fn new(owner_key: &Pubkey) -> Self {
+            let mint_authority_key = pubkey_rand();
+            let (mint_key, mut mint_account) = create_mint(
+                &spl_token::id(),
+                &mint_authority_key,
+                DEFAULT_TOKEN_DECIMALS,
+                None,
+            );
+            let (stake_key, stake_account) = mint_token(
+                &spl_token::id(),
+                &mint_key,
+                &mut mint_account,
+                &mint_authority_key,
+                owner_key,
+                1,
+            );
+            Self {
+                owner_key: *owner_key,
+                owner_account: Account::default(),
+                mint_key,
+                mint_account,
+                stake_key,
+                stake_account,
+                metadata_key: find_metadata_address(&mint_key).0,
+                metadata_account: Account::default(),
+            }
+        }
+
+        /// Writes Metaplex metadata for the NFT with the given collection
+        /// and creators
+        fn set_metadata(&mut self, collection: Option<Collection>, creators: Vec<Creator>) {
+            let metadata = Metadata {
+                key: METADATA_V1_KEY,
+                mint: self.mint_key,
+                data: Data {
+                    creators: Some(creators),
+                    ..Data::default()
+                },
+                collection,
+                ..Metadata::default()
+            };
+            let data = metadata.try_to_vec().unwrap();
+            self.metadata_account = Account::new(1, data.len(), &metadata_program::id());
+            self.metadata_account.data = data;
+        }
+    }
+
+    /// A reward mint with the manager's funding account and a vault owned by
+    /// the pool's reward authority
+    struct TestRewards {
+        mint_key: Pubkey,
+        mint_account: Account,
+        source_key: Pubkey,
+        source_account: Account,
+        vault_key: Pubkey,
+        vault_account: Account,
+    }
+
+    impl TestRewards {
+        fn new(pool: &TestPool, budget: u64) -> Self {
+            let mint_authority_key = pubkey_rand();
+            let (mint_key, mut mint_account) =
+                create_mint(&spl_token::id(), &mint_authority_key, 6, None);
+            let (source_key, source_account) = mint_token(
+                &spl_token::id(),
+                &mint_key,
+                &mut mint_account,
+                &mint_authority_key,
+                &pool.manager_key,
+                budget,
+            );
+            // the vault PDA is allocated up front, the test runtime doesn't
+            // process system program instructions
+            let (vault_key, _) =
+                find_reward_vault_address(&STAKE_PROGRAM_ID, &pool.stake_store_key);
+            let vault_account = Account::new(
+                account_minimum_balance(),
+                SplAccount::get_packed_len(),
+                &spl_token::id(),
+            );
+            Self {
+                mint_key,
+                mint_account,
+                source_key,
+                source_account,
+                vault_key,
+                vault_account,
+            }
+        }
+
+        /// An empty reward token account of the owner
+        fn destination(&mut self, owner_key: &Pubkey) -> (Pubkey, Account) {
+            let mint_authority_key = pubkey_rand();
+            let mut mint_account = self.mint_account.clone();
+            mint_token(
+                &spl_token::id(),
+                &self.mint_key,
+                &mut mint_account,
+                &mint_authority_key,
+                owner_key,
+                0,
+            )
+        }
+    }
+
+    impl TestPool {
+        fn sunset(&mut self) -> ProgramResult {
+            do_process_instruction(
+                sunset(&STAKE_PROGRAM_ID, &self.stake_store_key, &self.manager_key).unwrap(),
+                vec![&mut self.stake_store_account, &mut Account::default()],
+            )
+        }
+
+        /// Closes the pool along with its reward vault, crediting the rent
+        /// to the manager account
+        /// Closes the pool, paying its rent to `rent_destination`
+        fn close(
+            &mut self,
+            rewards: &mut TestRewards,
+            rent_destination: (&Pubkey, &mut Account),
+        ) -> ProgramResult {
+            let (rent_destination_key, rent_destination_account) = rent_destination;
+            let mut reward_authority_account = Account::default();
+            let mut token_program_account = Account::default();
+            do_process_instruction(
+                close_pool(
+                    &STAKE_PROGRAM_ID,
+                    &self.stake_store_key,
+                    &self.stake_list_key,
+                    &self.manager_key,
+                    rent_destination_key,
+                    None,
+                    true,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.stake_store_account,
+                    &mut self.stake_list_account,
+                    &mut Account::default(),
+                    rent_destination_account,
+                    &mut rewards.vault_account,
+                    &mut reward_authority_account,
+                    &mut token_program_account,
+                ],
+            )
+        }
+
+        fn append_emission_segments(
+            &mut self,
+            segments: &[(i64, u64)],
+            end_ts: i64,
+            ts: i64,
+        ) -> ProgramResult {
+            let segments = segments
+                .iter()
+                .map(|(start_ts, rate)| EmissionSegment {
+                    start_ts: *start_ts,
+                    rate: *rate,
+                })
+                .collect();
+            do_process_instruction(
+                append_emission_segments(
+                    &STAKE_PROGRAM_ID,
+                    &self.stake_store_key,
+                    &self.manager_key,
+                    segments,
+                    end_ts,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.stake_store_account,
+                    &mut Account::default(),
+                    &mut clock_account(ts),
+                ],
+            )
+        }
+
+        fn withdraw(
+            &mut self,
+            nft: &mut TestNFT,
+            staker_account: &mut Account,
+            ts: i64,
+            rewards: Option<(&mut TestRewards, &mut (Pubkey, Account))>,
+            unsettled_rewards: UnsettledRewards,
+        ) -> ProgramResult {
+            self.withdraw_paying(nft, staker_account, ts, rewards, unsettled_rewards, None)
+        }
+
+        /// Withdraws the NFT, settling its rewards when the reward accounts
+        /// are given
+        fn withdraw_paying(
+            &mut self,
+            nft: &mut TestNFT,
+            staker_account: &mut Account,
+            ts: i64,
+            rewards: Option<(&mut TestRewards, &mut (Pubkey, Account))>,
+            unsettled_rewards: UnsettledRewards,
+            fee: Option<&mut TestFeePayment>,
+        ) -> ProgramResult {
+            let mut clock = clock_account(ts);
+            let mut pda_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut reward_authority_account = Account::default();
+            let mut accounts = vec![
+                &mut nft.owner_account,
+                &mut nft.mint_account,
+                &mut clock,
+                &mut self.stake_store_account,
+                &mut self.stake_list_account,
+                &mut nft.stake_account,
+                &mut pda_account,
+                &mut token_program_account,
+                staker_account,
+            ];
+            let fee_payment = fee.as_ref().map(|fee| fee.payment);
+            if let Some(fee) = fee {
+                accounts.push(&mut fee.treasury_account);
+                accounts.push(&mut fee.payment_account);
+            }
+            let reward_destination_key = rewards.as_ref().map(|(_, destination)| destination.0);
+            if let Some((rewards, destination)) = rewards {
+                accounts.push(&mut rewards.vault_account);
+                accounts.push(&mut destination.1);
+                accounts.push(&mut reward_authority_account);
+            }
+            do_process_instruction(
+                withdraw_nft(
+                    &STAKE_PROGRAM_ID,
+                    &nft.owner_key,
+                    &nft.mint_key,
+                    &self.stake_store_key,
+                    &self.stake_list_key,
+                    &nft.stake_key,
+                    reward_destination_key.as_ref(),
+                    unsettled_rewards,
+                    fee_payment.as_ref(),
+                )
+                .unwrap(),
+                accounts,
+            )
+        }
+
+        /// Reclaims the NFT into its owner's associated token account,
+        /// paying the bounty when the reward accounts are given
+        fn reclaim(
+            &mut self,
+            nft: &mut TestNFT,
+            staker_account: &mut Account,
+            associated_token_account: &mut Account,
+            ts: i64,
+            rewards: Option<(&mut TestRewards, &mut (Pubkey, Account))>,
+        ) -> ProgramResult {
+            let mut clock = clock_account(ts);
+            let mut pda_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut reward_authority_account = Account::default();
+            let mut accounts = vec![
+                &mut nft.owner_account,
+                &mut nft.mint_account,
+                &mut clock,
+                &mut self.stake_store_account,
+                &mut self.stake_list_account,
+                &mut nft.stake_account,
+                &mut pda_account,
+                associated_token_account,
+                &mut token_program_account,
+                staker_account,
+            ];
+            let bounty_destination_key = rewards.as_ref().map(|(_, destination)| destination.0);
+            if let Some((rewards, destination)) = rewards {
+                accounts.push(&mut rewards.vault_account);
+                accounts.push(&mut destination.1);
+                accounts.push(&mut reward_authority_account);
+            }
+            do_process_instruction(
+                reclaim(
+                    &STAKE_PROGRAM_ID,
+                    &nft.owner_key,
+                    &nft.mint_key,
+                    &self.stake_store_key,
+                    &self.stake_list_key,
+                    &nft.stake_key,
+                    bounty_destination_key.as_ref(),
+                )
+                .unwrap(),
+                accounts,
+            )
+        }
+
+        fn fund_reward_vault(&mut self, rewards: &mut TestRewards, amount: u64) -> ProgramResult {
+            do_process_instruction(
+                fund_reward_vault(
+                    &STAKE_PROGRAM_ID,
+                    &self.stake_store_key,
+                    &self.manager_key,
+                    &rewards.mint_key,
+                    &rewards.source_key,
+                    amount,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.stake_store_account,
+                    &mut Account::default(),
+                    &mut rewards.mint_account,
+                    &mut rewards.source_account,
+                    &mut rewards.vault_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut create_account_for_test(&Rent::default()),
+                ],
+            )
+        }
+
+        fn withdraw_excess_rewards(
+            &mut self,
+            rewards: &mut TestRewards,
+            destination: &mut (Pubkey, Account),
+            amount: u64,
+            ts: i64,
+        ) -> ProgramResult {
+            do_process_instruction(
+                withdraw_excess_rewards(
+                    &STAKE_PROGRAM_ID,
+                    &self.stake_store_key,
+                    &self.manager_key,
+                    &destination.0,
+                    amount,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.stake_store_account,
+                    &mut Account::default(),
+                    &mut clock_account(ts),
+                    &mut rewards.vault_account,
+                    &mut destination.1,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        fn fund_rewards(
+            &mut self,
+            rewards: &mut TestRewards,
+            amount: u64,
+            duration: u64,
+            ts: i64,
+        ) -> ProgramResult {
+            do_process_instruction(
+                fund_rewards(
+                    &STAKE_PROGRAM_ID,
+                    &self.stake_store_key,
+                    &self.manager_key,
+                    &rewards.source_key,
+                    amount,
+                    duration,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.stake_store_account,
+                    &mut Account::default(),
+                    &mut clock_account(ts),
+                    &mut rewards.source_account,
+                    &mut rewards.vault_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        fn claim_rewards(
+            &mut self,
+            rewards: &mut TestRewards,
+            nft: &mut TestNFT,
+            destination: &mut (Pubkey, Account),
+            ts: i64,
+        ) -> ProgramResult {
+            do_process_instruction(
+                claim_rewards(
+                    &STAKE_PROGRAM_ID,
+                    &nft.owner_key,
+                    &nft.mint_key,
+                    &self.stake_store_key,
+                    &self.stake_list_key,
+                    &destination.0,
+                )
+                .unwrap(),
+                vec![
+                    &mut nft.owner_account,
+                    &mut nft.mint_account,
+                    &mut clock_account(ts),
+                    &mut self.stake_store_account,
+                    &mut self.stake_list_account,
+                    &mut rewards.vault_account,
+                    &mut destination.1,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+        }
+    }
+
+    fn token_balance(account: &Account) -> u64 {
+        unpack_token_account(&account.data).unwrap().amount
+    }
+
+    fn staker_account() -> Account {
+        Account::new(
+            Rent::default().minimum_balance(StakerAccount::LEN),
+            StakerAccount::LEN,
+            &STAKE_PROGRAM_ID,
+        )
+    }
+
+    fn tier_record_account() -> Account {
+        Account::new(
+            Rent::default().minimum_balance(TierRecord::LEN),
+            TierRecord::LEN,
+            &STAKE_PROGRAM_ID,
+        )
+    }
+
+    #[test]
+    fn test_deposit_nft() {
+        msg!("starting test_deposit_nft {}", STAKE_PROGRAM_ID);
+        let owner_key = pubkey_rand();
+        let depositor_key = pubkey_rand();
+        let stake_store_key = pubkey_rand();
+        let stake_list_key = pubkey_rand();
+
+        // setup accounts
+        let mut depositor_account = Account::default();
+        let mut stake_store_account = Account::new(
+            Rent::default().minimum_balance(StakeStore::packed_len()),
+            StakeStore::packed_len(),
+            &STAKE_PROGRAM_ID,
+        );
+
+        let mut stake_store =
+            StakeStore::load(&stake_store_account.data).unwrap();
+        let mut test_data = vec![1; stake_store_account.data.len()];
+        let mut fee = Rent::default().minimum_balance(StakeStore::packed_len());
+
+        let stake_store_account_info = AccountInfo::new(
+            &stake_store_key,
+            false,
+            true,
+            &mut fee,
+            test_data.as_mut_slice(),
+            &depositor_key,
+            false,
+            Epoch::default(),
+        );
+
+        let list_size = get_packed_len::<StakedNFT>() * MAX_ITEMS as usize
+            + StakeListHeader::packed_len(); //get_packed_len::<StakeList>();
+                                                   // msg!("list_size: {}", list_size);
+        let mut stake_list_account = Account::new(
+            Rent::default().minimum_balance(list_size),
+            list_size,
+            &STAKE_PROGRAM_ID,
+        );
+        let mut staker_account = Account::new(
+            Rent::default().minimum_balance(StakerAccount::LEN),
+            StakerAccount::LEN,
+            &STAKE_PROGRAM_ID,
+        );
+        let (nft1_mint_key, mut nft1_mint_account) =
+            create_mint(&spl_token::id(), &owner_key, DEFAULT_TOKEN_DECIMALS, None);
+        let (nft2_mint_key, mut nft2_mint_account) =
+            create_mint(&spl_token::id(), &owner_key, DEFAULT_TOKEN_DECIMALS, None);
+
+        let (user_nft1_key, mut user_nft1_account) = mint_token(
+            &spl_token::id(),
+            &nft1_mint_key,
+            &mut nft1_mint_account,
+            &owner_key,
+            &depositor_key,
+            1,
+        );
+
+        let (user_nft2_key, mut user_nft2_account) = mint_token(
+            &spl_token::id(),
+            &nft2_mint_key,
+            &mut nft2_mint_account,
+            &owner_key,
+            &depositor_key,
+            1,
+        );
+
+        let (stake_nft1_key, mut stake_nft1_account) = mint_token(
+            &spl_token::id(),
+            &nft1_mint_key,
+            &mut nft1_mint_account,
+            &owner_key,
+            &depositor_key,
+            0,
+        );
+
+        let (stake_nft2_key, mut stake_nft2_account) = mint_token(
+            &spl_token::id(),
+            &nft2_mint_key,
+            &mut nft2_mint_account,
+            &owner_key,
+            &depositor_key,
+            0,
+        );
+
+        {
+            let user_nft1 = unpack_token_account(&user_nft1_account.data).unwrap();
+            assert_eq!(user_nft1.amount, 1);
+            let user_nft2 = unpack_token_account(&user_nft2_account.data).unwrap();
+            assert_eq!(user_nft1.amount, 1);
+            let stake_nft1 = unpack_token_account(&stake_nft1_account.data).unwrap();
+            assert_eq!(stake_nft1.amount, 0);
+            let stake_nft2 = unpack_token_account(&stake_nft2_account.data).unwrap();
+            assert_eq!(stake_nft2.amount, 0);
+        }
+
+        do_process_instruction(
+            transfer(
+                &spl_token::id(),
+                &user_nft1_key,
+                &stake_nft1_key,
+                &depositor_key,
+                &[],
+                1,
+            )
+            .unwrap(),
+            vec![
+                &mut user_nft1_account,
+                &mut stake_nft1_account,
+                &mut depositor_account,
+            ],
+        )
+        .unwrap();
+
+        do_process_instruction(
+            transfer(
+                &spl_token::id(),
+                &user_nft2_key,
+                &stake_nft2_key,
+                &depositor_key,
+                &[],
+                1,
+            )
+            .unwrap(),
+            vec![
+                &mut user_nft2_account,
+                &mut stake_nft2_account,
+                &mut depositor_account,
+            ],
+        )
+        .unwrap();
+
+        {
+            let user_nft1 = unpack_token_account(&user_nft1_account.data).unwrap();
+            assert_eq!(user_nft1.amount, 0);
+            let user_nft2 = unpack_token_account(&user_nft2_account.data).unwrap();
+            assert_eq!(user_nft1.amount, 0);
+            let stake_nft1 = unpack_token_account(&stake_nft1_account.data).unwrap();
+            assert_eq!(stake_nft1.amount, 1);
+            let stake_nft2 = unpack_token_account(&stake_nft2_account.data).unwrap();
+            assert_eq!(stake_nft2.amount, 1);
+        }
+
+        msg!("*** before init: {}", stake_store_account.data.len());
+        msg!("{} {}", owner_key, stake_list_key);
+        do_process_instruction(
+            initialize(
+                &STAKE_PROGRAM_ID,
+                &stake_store_key,
+                &stake_list_key,
+                &owner_key,
+                PoolConfig::default(),
+            )
+            .unwrap(),
+            vec![
+                &mut stake_store_account,
+                &mut stake_list_account,
+                &mut Account::default(),
+                &mut create_account_for_test(&Rent::default()),
+            ],
+        )
+        .unwrap();
+
+        msg!("*** after init: {}", stake_store_account.data.len());
+        msg!("=================================");
+
+        do_process_instruction(
+            deposit_nft(
+                &STAKE_PROGRAM_ID,
+                &depositor_key,
+                &nft1_mint_key,
+                &stake_nft1_key,
+                &stake_store_key,
+                &stake_list_key,
+                None,
+                None,
+                1,
+                None,
+                None,
+            )
+            .unwrap(),
+            vec![
+                &mut depositor_account,
+                &mut nft1_mint_account,
+                &mut clock_account(ZERO_TS),
+                &mut stake_nft1_account,
+                &mut stake_store_account,
+                &mut stake_list_account,
+                &mut Account::default(),
+                &mut staker_account,
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+
+        do_process_instruction(
+            deposit_nft(
+                &STAKE_PROGRAM_ID,
+                &depositor_key,
+                &nft2_mint_key,
+                &stake_nft2_key,
+                &stake_store_key,
+                &stake_list_key,
+                None,
+                None,
+                1,
+                None,
+                None,
+            )
+            .unwrap(),
+            vec![
+                &mut depositor_account,
+                &mut nft2_mint_account,
+                &mut clock_account(ZERO_TS),
+                &mut stake_nft2_account,
+                &mut stake_store_account,
+                &mut stake_list_account,
+                &mut Account::default(),
+                &mut staker_account,
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+
+        // check stake store and stake list
+        let stake_store = StakeStore::load(&stake_store_account.data).unwrap();
+        assert_eq!(stake_store.staked_count, 2);
+        let (_header, stake_list) =
+            StakeListHeader::deserialize_vec(&mut stake_list_account.data).unwrap();
+        let staked = stake_list
+            .filter_unpacked::<StakedNFT, _>(|x| x.owner == depositor_key)
+            .unwrap();
+        assert_eq!(staked.len(), 2);
+        assert_eq!(staked[0].token_mint, nft1_mint_key);
+        assert_eq!(staked[1].token_mint, nft2_mint_key);
+
+        // both deposits are recorded in the depositor's points ledger
+        let staker = StakerAccount::load(&staker_account.data).unwrap();
+        assert_eq!(staker.owner, depositor_key);
+        assert_eq!(staker.stake_store, stake_store_key);
+        assert_eq!(staker.active_stakes, 2);
+        assert_eq!(staker.stake_seconds, 0);
+
+        // two NFTs staked for two hours earn four points
+        let two_hours = 2 * SECONDS_PER_POINT as i64;
+        do_process_instruction(
+            checkpoint(
+                &STAKE_PROGRAM_ID,
+                &stake_store_key,
+                &stake_list_key,
+                &depositor_key,
+            )
+            .unwrap(),
+            vec![
+                &mut staker_account,
+                &mut stake_store_account,
+                &mut stake_list_account,
+                &mut clock_account(two_hours),
+            ],
+        )
+        .unwrap();
+        let staker = StakerAccount::load(&staker_account.data).unwrap();
+        assert_eq!(staker.active_stakes, 2);
+        assert_eq!(staker.stake_seconds, 2 * two_hours as u64);
+        assert_eq!(staker.points, 4);
+        assert_eq!(staker.last_update_ts, two_hours);
+
+        // cranking again at the same time accrues nothing more
+        do_process_instruction(
+            checkpoint(
+                &STAKE_PROGRAM_ID,
+                &stake_store_key,
+                &stake_list_key,
+                &depositor_key,
+            )
+            .unwrap(),
+            vec![
+                &mut staker_account,
+                &mut stake_store_account,
+                &mut stake_list_account,
+                &mut clock_account(two_hours),
+            ],
+        )
+        .unwrap();
+        let checkpointed = StakerAccount::load(&staker_account.data).unwrap();
+        assert_eq!(checkpointed, staker);
+
+        // the ledger of another owner is rejected
+        let (other_staker_key, _) =
+            find_staker_account_address(&STAKE_PROGRAM_ID, &stake_store_key, &owner_key);
+        let mut instruction =
+            checkpoint(&STAKE_PROGRAM_ID, &stake_store_key, &stake_list_key, &owner_key).unwrap();
+        assert_eq!(instruction.accounts[0].pubkey, other_staker_key);
+        instruction.accounts[0].pubkey = pubkey_rand();
+        assert_eq!(
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut staker_account,
+                    &mut stake_store_account,
+                    &mut stake_list_account,
+                    &mut clock_account(two_hours),
+                ],
+            ),
+            Err(NFTStakingError::InvalidStakerAccount.into())
+        );
+    }
+
+    #[test]
+    fn test_reinitialize() {
+        let mut pool = TestPool::new(4);
+        let owner_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut nft = TestNFT::new(&owner_key);
+        pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+
+        let initialize_with = |stake_store: (&Pubkey, &mut Account),
+                               stake_list: (&Pubkey, &mut Account),
+                               manager_key: &Pubkey| {
+            do_process_instruction(
+                initialize(
+                    &STAKE_PROGRAM_ID,
+                    stake_store.0,
+                    stake_list.0,
+                    manager_key,
+                    PoolConfig::default(),
+                )
+                .unwrap(),
+                vec![
+                    stake_store.1,
+                    stake_list.1,
+                    &mut Account::default(),
+                    &mut create_account_for_test(&Rent::default()),
+                ],
+            )
+        };
+        let already_in_use = Err(NFTStakingError::AlreadyInUse.into());
+        let stake_store = pool.stake_store();
+        let staked_nfts = pool.staked_nfts();
+
+        // not even the manager may wipe the pool
+        let manager_key = pool.manager_key;
+        assert_eq!(
+            initialize_with(
+                (&pool.stake_store_key, &mut pool.stake_store_account),
+                (&pool.stake_list_key, &mut pool.stake_list_account),
+                &manager_key,
+            ),
+            already_in_use
+        );
+
+        // nor pair a live stake list with a fresh stake store, or the other
+        // way around
+        let fresh_pool = TestPool::new(4);
+        let mut fresh_store_account = fresh_pool.stake_store_account.clone();
+        fresh_store_account.data.iter_mut().for_each(|byte| *byte = 0);
+        let mut fresh_list_account = fresh_pool.stake_list_account.clone();
+        fresh_list_account.data.iter_mut().for_each(|byte| *byte = 0);
+        assert_eq!(
+            initialize_with(
+                (&pubkey_rand(), &mut fresh_store_account),
+                (&pool.stake_list_key, &mut pool.stake_list_account),
+                &pubkey_rand(),
+            ),
+            already_in_use
+        );
+        assert_eq!(
+            initialize_with(
+                (&pool.stake_store_key, &mut pool.stake_store_account),
+                (&pubkey_rand(), &mut fresh_list_account),
+                &pubkey_rand(),
+            ),
+            already_in_use
+        );
+        assert_eq!(pool.stake_store(), stake_store);
+        assert_eq!(pool.staked_nfts(), staked_nfts);
+
+        // fresh accounts are still accepted
+        initialize_with(
+            (&pubkey_rand(), &mut fresh_store_account),
+            (&pubkey_rand(), &mut fresh_list_account),
+            &pubkey_rand(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_rarity_multipliers() {
+        let mut pool = TestPool::new(4);
+        let rarity_table_key = pubkey_rand();
+        let table_size = RarityTableHeader::packed_len() + 4 + RarityEntry::LEN * 4;
+        let mut rarity_table_account = Account::new(
+            Rent::default().minimum_balance(table_size),
+            table_size,
+            &STAKE_PROGRAM_ID,
+        );
+
+        // only the manager can attach a table
+        assert_eq!(
+            do_process_instruction(
+                initialize_rarity_table(
+                    &STAKE_PROGRAM_ID,
+                    &pool.stake_store_key,
+                    &rarity_table_key,
+                    &pubkey_rand(),
+                )
+                .unwrap(),
+                vec![
+                    &mut pool.stake_store_account,
+                    &mut rarity_table_account,
+                    &mut Account::default(),
+                    &mut create_account_for_test(&Rent::default()),
+                ],
+            ),
+            Err(NFTStakingError::InvalidManager.into())
+        );
+        do_process_instruction(
+            initialize_rarity_table(
+                &STAKE_PROGRAM_ID,
+                &pool.stake_store_key,
+                &rarity_table_key,
+                &pool.manager_key,
+            )
+            .unwrap(),
+            vec![
+                &mut pool.stake_store_account,
+                &mut rarity_table_account,
+                &mut Account::default(),
+                &mut create_account_for_test(&Rent::default()),
+            ],
+        )
+        .unwrap();
+        assert_eq!(pool.stake_store().rarity_table, rarity_table_key);
+
+        let depositor_key = pubkey_rand();
+        let mut rare_nft = TestNFT::new(&depositor_key);
+        let mut common_nft = TestNFT::new(&depositor_key);
+        let entries = vec![
+            RarityEntry {
+                mint: rare_nft.mint_key,
+                multiplier_bps: 15_000,
+            },
+            RarityEntry {
+                mint: pubkey_rand(),
+                multiplier_bps: 20_000,
+            },
+        ];
+        let mut uploads = upload_rarities_in_chunks(
+            &STAKE_PROGRAM_ID,
+            &pool.stake_store_key,
+            &rarity_table_key,
+            &pool.manager_key,
+            &entries,
+        )
+        .unwrap();
+        assert_eq!(uploads.len(), 1);
+        do_process_instruction(
+            uploads.remove(0),
+            vec![
+                &mut pool.stake_store_account,
+                &mut rarity_table_account,
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+
+        // a later chunk updates an existing entry in place
+        do_process_instruction(
+            upload_rarities(
+                &STAKE_PROGRAM_ID,
+                &pool.stake_store_key,
+                &rarity_table_key,
+                &pool.manager_key,
+                vec![RarityEntry {
+                    mint: rare_nft.mint_key,
+                    multiplier_bps: 30_000,
+                }],
+            )
+            .unwrap(),
+            vec![
+                &mut pool.stake_store_account,
+                &mut rarity_table_account,
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+        {
+            let (_header, rarity_table) =
+                RarityTableHeader::deserialize_vec(&mut rarity_table_account.data).unwrap();
+            assert_eq!(rarity_table.len(), 2);
+            assert_eq!(
+                RarityTableHeader::multiplier_bps(&rarity_table, &rare_nft.mint_key).unwrap(),
+                30_000
+            );
+        }
+
+        // zero multipliers are rejected
+        assert_eq!(
+            do_process_instruction(
+                upload_rarities(
+                    &STAKE_PROGRAM_ID,
+                    &pool.stake_store_key,
+                    &rarity_table_key,
+                    &pool.manager_key,
+                    vec![RarityEntry {
+                        mint: common_nft.mint_key,
+                        multiplier_bps: 0,
+                    }],
+                )
+                .unwrap(),
+                vec![
+                    &mut pool.stake_store_account,
+                    &mut rarity_table_account,
+                    &mut Account::default(),
+                ],
+            ),
+            Err(NFTStakingError::InvalidRarityMultiplier.into())
+        );
+
+        // entries uploaded out of order are inserted in mint order
+        let first_mint = Pubkey::new_from_array([0; 32]);
+        let last_mint = Pubkey::new_from_array([u8::MAX; 32]);
+        do_process_instruction(
+            upload_rarities(
+                &STAKE_PROGRAM_ID,
+                &pool.stake_store_key,
+                &rarity_table_key,
+                &pool.manager_key,
+                vec![
+                    RarityEntry {
+                        mint: last_mint,
+                        multiplier_bps: 40_000,
+                    },
+                    RarityEntry {
+                        mint: first_mint,
+                        multiplier_bps: 50_000,
+                    },
+                ],
+            )
+            .unwrap(),
+            vec![
+                &mut pool.stake_store_account,
+                &mut rarity_table_account,
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+        {
+            let (_header, rarity_table) =
+                RarityTableHeader::deserialize_vec(&mut rarity_table_account.data).unwrap();
+            let mints = rarity_table
+                .iter::<RarityEntry>()
+                .map(|entry| entry.unwrap().mint)
+                .collect::<Vec<_>>();
+            assert_eq!(mints.len(), 4);
+            assert_eq!((mints[0], mints[3]), (first_mint, last_mint));
+            assert!(mints.windows(2).all(|pair| pair[0] < pair[1]));
+            for (mint, multiplier_bps) in [
+                (first_mint, 50_000),
+                (rare_nft.mint_key, 30_000),
+                (last_mint, 40_000),
+                (common_nft.mint_key, DEFAULT_MULTIPLIER_BPS),
+            ] {
+                assert_eq!(
+                    RarityTableHeader::multiplier_bps(&rarity_table, &mint),
+                    Ok(multiplier_bps)
+                );
+            }
+        }
+
+        // deposits must now supply the table
+        let mut staker_account = staker_account();
+        assert_eq!(
+            pool.deposit(&mut rare_nft, &mut staker_account, ZERO_TS, None, None),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        pool.deposit(
+            &mut rare_nft,
+            &mut staker_account,
+            ZERO_TS,
+            Some((&rarity_table_key, &mut rarity_table_account)),
+            None,
+        )
+        .unwrap();
+        pool.deposit(
+            &mut common_nft,
+            &mut staker_account,
+            ZERO_TS,
+            Some((&rarity_table_key, &mut rarity_table_account)),
+            None,
+        )
+        .unwrap();
+
+        let staked = pool.staked_nfts();
+        assert_eq!(staked[0].multiplier_bps, 30_000);
+        assert_eq!(staked[1].multiplier_bps, DEFAULT_MULTIPLIER_BPS);
+        let staker = StakerAccount::load(&staker_account.data).unwrap();
+        assert_eq!(staker.active_stakes, 2);
+        assert_eq!(staker.active_weight_bps, 40_000);
+
+        // the 3x NFT earns three times the points of the common one
+        do_process_instruction(
+            checkpoint(
+                &STAKE_PROGRAM_ID,
+                &pool.stake_store_key,
+                &pool.stake_list_key,
+                &depositor_key,
+            )
+            .unwrap(),
+            vec![
+                &mut staker_account,
+                &mut pool.stake_store_account,
+                &mut pool.stake_list_account,
+                &mut clock_account(SECONDS_PER_POINT as i64),
+            ],
+        )
+        .unwrap();
+        let staker = StakerAccount::load(&staker_account.data).unwrap();
+        assert_eq!(staker.stake_seconds, 2 * SECONDS_PER_POINT);
+        assert_eq!(staker.points, 4);
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let mut pool = TestPool::new(4);
+        let depositor_key = pubkey_rand();
+        let mut listed_nft = TestNFT::new(&depositor_key);
+        let mut weighted_nft = TestNFT::new(&depositor_key);
+        let mut unlisted_nft = TestNFT::new(&depositor_key);
+        let tree = MerkleTree::from_csv(&format!(
+            "mint,multiplier_bps\n{}\n{},25000\n{}\n",
+            listed_nft.mint_key,
+            weighted_nft.mint_key,
+            pubkey_rand()
+        ))
+        .unwrap();
+        let proof_for = |mint: &Pubkey| {
+            let (entry, proof) = tree.find_proof(mint).unwrap();
+            AllowListProof {
+                multiplier_bps: entry.multiplier_bps,
+                proof,
+            }
+        };
+
+        assert_eq!(
+            do_process_instruction(
+                set_allow_list_root(
+                    &STAKE_PROGRAM_ID,
+                    &pool.stake_store_key,
+                    &pubkey_rand(),
+                    tree.root()
+                )
+                .unwrap(),
+                vec![&mut pool.stake_store_account, &mut Account::default()],
+            ),
+            Err(NFTStakingError::InvalidManager.into())
+        );
+        do_process_instruction(
+            set_allow_list_root(
+                &STAKE_PROGRAM_ID,
+                &pool.stake_store_key,
+                &pool.manager_key,
+                tree.root(),
+            )
+            .unwrap(),
+            vec![&mut pool.stake_store_account, &mut Account::default()],
+        )
+        .unwrap();
+        assert_eq!(pool.stake_store().allow_list_root, tree.root());
+
+        let mut staker_account = staker_account();
+        // a proof is required, and must match the mint and its weight
+        assert_eq!(
+            pool.deposit(&mut listed_nft, &mut staker_account, ZERO_TS, None, None),
+            Err(NFTStakingError::InvalidAllowListProof.into())
+        );
+        let mut overweight = proof_for(&weighted_nft.mint_key);
+        overweight.multiplier_bps = Some(50_000);
+        assert_eq!(
+            pool.deposit(
+                &mut weighted_nft,
+                &mut staker_account,
+                ZERO_TS,
+                None,
+                Some(overweight)
+            ),
+            Err(NFTStakingError::InvalidAllowListProof.into())
+        );
+        let stolen_proof = proof_for(&listed_nft.mint_key);
+        assert_eq!(
+            pool.deposit(
+                &mut unlisted_nft,
+                &mut staker_account,
+                ZERO_TS,
+                None,
+                Some(stolen_proof)
+            ),
+            Err(NFTStakingError::InvalidAllowListProof.into())
+        );
+
+        // a valid proof can't vouch for a token account of another mint...
+        std::mem::swap(&mut unlisted_nft.stake_account, &mut listed_nft.stake_account);
+        let proof = proof_for(&listed_nft.mint_key);
+        assert_eq!(
+            pool.deposit(&mut listed_nft, &mut staker_account, ZERO_TS, None, Some(proof)),
+            Err(NFTStakingError::MintMismatch.into())
+        );
+        std::mem::swap(&mut unlisted_nft.stake_account, &mut listed_nft.stake_account);
+        // ...nor for an empty one
+        let holding = listed_nft.stake_account.data.clone();
+        let mut token_account = SplAccount::unpack(&holding).unwrap();
+        token_account.amount = 0;
+        SplAccount::pack(token_account, &mut listed_nft.stake_account.data).unwrap();
+        let proof = proof_for(&listed_nft.mint_key);
+        assert_eq!(
+            pool.deposit(&mut listed_nft, &mut staker_account, ZERO_TS, None, Some(proof)),
+            Err(NFTStakingError::MintMismatch.into())
+        );
+        listed_nft.stake_account.data = holding;
+
+        let proof = proof_for(&listed_nft.mint_key);
+        pool.deposit(&mut listed_nft, &mut staker_account, ZERO_TS, None, Some(proof))
+            .unwrap();
+        let proof = proof_for(&weighted_nft.mint_key);
+        pool.deposit(&mut weighted_nft, &mut staker_account, ZERO_TS, None, Some(proof))
+            .unwrap();
+
+        let staked = pool.staked_nfts();
+        assert_eq!(staked.len(), 2);
+        assert_eq!(staked[0].multiplier_bps, DEFAULT_MULTIPLIER_BPS);
+        assert_eq!(staked[1].multiplier_bps, 25_000);
+
+        // clearing the root opens the pool to any mint again
+        do_process_instruction(
+            set_allow_list_root(
+                &STAKE_PROGRAM_ID,
+                &pool.stake_store_key,
+                &pool.manager_key,
+                [0; HASH_BYTES],
+            )
+            .unwrap(),
+            vec![&mut pool.stake_store_account, &mut Account::default()],
+        )
+        .unwrap();
+        pool.deposit(&mut unlisted_nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_metadata_requirements() {
+        let mut pool = TestPool::new(4);
+        let depositor_key = pubkey_rand();
+        let collection_key = pubkey_rand();
+        let creator_key = pubkey_rand();
+        let verified_creator = Creator {
+            address: creator_key,
+            verified: true,
+            share: 100,
+        };
+        let verified_collection = Collection {
+            verified: true,
+            key: collection_key,
+        };
+
+        assert_eq!(
+            do_process_instruction(
+                set_metadata_requirements(
+                    &STAKE_PROGRAM_ID,
+                    &pool.stake_store_key,
+                    &pubkey_rand(),
+                    Some(&collection_key),
+                    None,
+                )
+                .unwrap(),
+                vec![&mut pool.stake_store_account, &mut Account::default()],
+            ),
+            Err(NFTStakingError::InvalidManager.into())
+        );
+        do_process_instruction(
+            set_metadata_requirements(
+                &STAKE_PROGRAM_ID,
+                &pool.stake_store_key,
+                &pool.manager_key,
+                Some(&collection_key),
+                Some(&creator_key),
+            )
+            .unwrap(),
+            vec![&mut pool.stake_store_account, &mut Account::default()],
+        )
+        .unwrap();
+        assert_eq!(pool.stake_store().config.required_collection, collection_key);
+        assert_eq!(pool.stake_store().required_creator, creator_key);
+
+        let mut staker_account = staker_account();
+        let mut nft = TestNFT::new(&depositor_key);
+
+        // missing, foreign or spoofed metadata accounts
+        assert_eq!(
+            pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None),
+            Err(NFTStakingError::InvalidMetadataAccount.into())
+        );
+        nft.set_metadata(Some(verified_collection.clone()), vec![verified_creator.clone()]);
+        nft.metadata_account.owner = STAKE_PROGRAM_ID;
+        assert_eq!(
+            pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None),
+            Err(NFTStakingError::InvalidMetadataAccount.into())
+        );
+        let mut other_nft = TestNFT::new(&depositor_key);
+        other_nft.set_metadata(Some(verified_collection.clone()), vec![verified_creator.clone()]);
+        nft.metadata_account = other_nft.metadata_account.clone();
+        assert_eq!(
+            pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None),
+            Err(NFTStakingError::InvalidMetadataAccount.into())
+        );
+
+        // the collection must match and be verified
+        nft.set_metadata(None, vec![verified_creator.clone()]);
+        assert_eq!(
+            pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None),
+            Err(NFTStakingError::CollectionMismatch.into())
+        );
+        nft.set_metadata(
+            Some(Collection {
+                verified: true,
+                key: pubkey_rand(),
+            }),
+            vec![verified_creator.clone()],
+        );
+        assert_eq!(
+            pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None),
+            Err(NFTStakingError::CollectionMismatch.into())
+        );
+        nft.set_metadata(
+            Some(Collection {
+                verified: false,
+                key: collection_key,
+            }),
+            vec![verified_creator.clone()],
+        );
+        assert_eq!(
+            pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None),
+            Err(NFTStakingError::UnverifiedCollection.into())
+        );
+
+        // the creator must be listed and have signed
+        nft.set_metadata(Some(verified_collection.clone()), vec![]);
+        assert_eq!(
+            pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None),
+            Err(NFTStakingError::CreatorMismatch.into())
+        );
+        nft.set_metadata(
+            Some(verified_collection.clone()),
+            vec![Creator {
+                verified: false,
+                ..verified_creator.clone()
+            }],
+        );
+        assert_eq!(
+            pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None),
+            Err(NFTStakingError::UnverifiedCreator.into())
+        );
+
+        // genuine metadata can't vouch for a token account of another mint
+        nft.set_metadata(Some(verified_collection.clone()), vec![verified_creator.clone()]);
+        let mut swapped_nft = TestNFT::new(&depositor_key);
+        std::mem::swap(&mut swapped_nft.stake_account, &mut nft.stake_account);
+        assert_eq!(
+            pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None),
+            Err(NFTStakingError::MintMismatch.into())
+        );
+        std::mem::swap(&mut swapped_nft.stake_account, &mut nft.stake_account);
+
+        pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+        assert_eq!(pool.staked_nfts().len(), 1);
+    }
+
+    #[test]
+    fn test_epoch_rewards() {
+        let mut pool = TestPool::new(4);
+        let mut rewards = TestRewards::new(&pool, 10_000);
+        let depositor_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut first_nft = TestNFT::new(&depositor_key);
+        let mut second_nft = TestNFT::new(&depositor_key);
+        let mut destination = rewards.destination(&depositor_key);
+
+        // only the reward operator funds epochs, into a vault owned by the pool
+        let manager_key = pool.manager_key;
+        pool.manager_key = pubkey_rand();
+        assert_eq!(
+            pool.fund_rewards(&mut rewards, 3600, 3600, ZERO_TS),
+            Err(NFTStakingError::InvalidRewardOperator.into())
+        );
+        pool.manager_key = manager_key;
+        assert_eq!(
+            pool.fund_rewards(&mut rewards, 3600, 3600, ZERO_TS),
+            Err(NFTStakingError::InvalidRewardVault.into())
+        );
+        pool.fund_reward_vault(&mut rewards, 0).unwrap();
+        assert_eq!(
+            pool.fund_rewards(&mut rewards, 3600, 0, ZERO_TS),
+            Err(NFTStakingError::InvalidRewardEpoch.into())
+        );
+
+        pool.deposit(&mut first_nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+        pool.fund_rewards(&mut rewards, 3600, 3600, ZERO_TS)
+            .unwrap();
+        assert_eq!(token_balance(&rewards.vault_account), 3600);
+        assert_eq!(token_balance(&rewards.source_account), 10_000 - 3600);
+        let stake_store = pool.stake_store();
+        assert_eq!(stake_store.reward_vault, rewards.vault_key);
+        assert_eq!(stake_store.reward_rate, 1);
+        assert_eq!(stake_store.reward_epoch_end, 3600);
+
+        // the first NFT earns alone for half the epoch, then shares evenly
+        pool.deposit(&mut second_nft, &mut staker_account, 1800, None, None)
+            .unwrap();
+        assert_eq!(pool.stake_store().total_shares, 2 * BASIS_POINTS);
+        pool.claim_rewards(&mut rewards, &mut first_nft, &mut destination, 3600)
+            .unwrap();
+        assert_eq!(token_balance(&destination.1), 2700);
+        pool.claim_rewards(&mut rewards, &mut first_nft, &mut destination, 3600)
+            .unwrap();
+        assert_eq!(token_balance(&destination.1), 2700);
+
+        // nothing is emitted past the end of the epoch
+        pool.claim_rewards(&mut rewards, &mut second_nft, &mut destination, 7200)
+            .unwrap();
+        assert_eq!(token_balance(&destination.1), 3600);
+        assert_eq!(token_balance(&rewards.vault_account), 0);
+
+        let mut unstaked_nft = TestNFT::new(&depositor_key);
+        assert_eq!(
+            pool.claim_rewards(&mut rewards, &mut unstaked_nft, &mut destination, 7200),
+            Err(NFTStakingError::StakedNFTNotFound.into())
+        );
+    }
+
+    #[test]
+    fn test_reward_vault() {
+        let mut pool = TestPool::new(4);
+        let mut rewards = TestRewards::new(&pool, 10_000);
+        let depositor_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut nft = TestNFT::new(&depositor_key);
+        let mut treasury = rewards.destination(&pool.manager_key);
+        let mut destination = rewards.destination(&depositor_key);
+
+        let manager_key = pool.manager_key;
+        pool.manager_key = pubkey_rand();
+        assert_eq!(
+            pool.fund_reward_vault(&mut rewards, 1000),
+            Err(NFTStakingError::InvalidRewardOperator.into())
+        );
+        pool.manager_key = manager_key;
+        pool.fund_reward_vault(&mut rewards, 1000).unwrap();
+        assert_eq!(pool.stake_store().reward_vault, rewards.vault_key);
+        let vault = unpack_token_account(&rewards.vault_account.data).unwrap();
+        assert_eq!(vault.mint, rewards.mint_key);
+        assert_eq!(
+            vault.owner,
+            find_reward_authority_address(&STAKE_PROGRAM_ID, &pool.stake_store_key).0
+        );
+        assert_eq!(vault.amount, 1000);
+
+        // topping up leaves the epoch untouched
+        pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+        pool.fund_rewards(&mut rewards, 1000, 100, ZERO_TS).unwrap();
+        pool.fund_reward_vault(&mut rewards, 500).unwrap();
+        let stake_store = pool.stake_store();
+        assert_eq!(stake_store.reward_rate, 10);
+        assert_eq!(stake_store.reward_epoch_end, 100);
+        assert_eq!(token_balance(&rewards.vault_account), 2500);
+
+        // everything the epoch emits or will emit stays in the vault
+        pool.withdraw_excess_rewards(&mut rewards, &mut treasury, 1000, 40)
+            .unwrap();
+        assert_eq!(token_balance(&treasury.1), 1000);
+        assert_eq!(
+            pool.withdraw_excess_rewards(&mut rewards, &mut treasury, 501, 40),
+            Err(NFTStakingError::InsufficientExcessRewards.into())
+        );
+        pool.claim_rewards(&mut rewards, &mut nft, &mut destination, 100)
+            .unwrap();
+        assert_eq!(token_balance(&destination.1), 1000);
+        let mut stake_store = pool.stake_store();
+        stake_store.update_rewards(100).unwrap();
+        assert_eq!(
+            stake_store.reward_vault_status(token_balance(&rewards.vault_account)),
+            Ok(RewardVaultStatus {
+                balance: 500,
+                unclaimed: 0,
+                committed: 0,
+                excess: 500,
+                shortfall: 0,
+            })
+        );
+        pool.withdraw_excess_rewards(&mut rewards, &mut treasury, 500, 100)
+            .unwrap();
+        assert_eq!(token_balance(&rewards.vault_account), 0);
+    }
+
+    #[test]
+    fn test_withdraw_settles_rewards() {
+        let mut pool = TestPool::new(4);
+        let mut rewards = TestRewards::new(&pool, 10_000);
+        let depositor_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut first_nft = TestNFT::new(&depositor_key);
+        let mut second_nft = TestNFT::new(&depositor_key);
+        let mut third_nft = TestNFT::new(&depositor_key);
+        let mut destination = rewards.destination(&depositor_key);
+
+        pool.fund_reward_vault(&mut rewards, 0).unwrap();
+        for nft in [&mut first_nft, &mut second_nft, &mut third_nft] {
+            pool.deposit(nft, &mut staker_account, ZERO_TS, None, None)
+                .unwrap();
+        }
+        pool.fund_rewards(&mut rewards, 3000, 100, ZERO_TS).unwrap();
+
+        assert_eq!(
+            pool.withdraw(&mut first_nft, &mut staker_account, 50, None, UnsettledRewards::Abort),
+            Err(NFTStakingError::MissingRewardAccounts.into())
+        );
+        pool.withdraw(
+            &mut first_nft,
+            &mut staker_account,
+            50,
+            Some((&mut rewards, &mut destination)),
+            UnsettledRewards::Abort,
+        )
+        .unwrap();
+        assert_eq!(token_balance(&destination.1), 500);
+        let stake_account = unpack_token_account(&first_nft.stake_account.data).unwrap();
+        assert_eq!(stake_account.owner, depositor_key);
+        assert_eq!(stake_account.amount, 1);
+        assert_eq!(pool.staked_nfts().len(), 2);
+        assert_eq!(pool.stake_store().total_shares, 2 * BASIS_POINTS);
+
+        // without reward accounts the rewards stay owed to the owner, unless
+        // they're explicitly forfeited
+        pool.withdraw(&mut second_nft, &mut staker_account, 100, None, UnsettledRewards::Credit)
+            .unwrap();
+        pool.withdraw(&mut third_nft, &mut staker_account, 100, None, UnsettledRewards::Forfeit)
+            .unwrap();
+        assert_eq!(token_balance(&destination.1), 500);
+        assert!(pool.staked_nfts().is_empty());
+        let staker = StakerAccount::load(&staker_account.data).unwrap();
+        assert_eq!(staker.credited_rewards, 1250);
+        let stake_store = pool.stake_store();
+        assert_eq!(stake_store.total_shares, 0);
+        assert_eq!(
+            stake_store.reward_vault_status(token_balance(&rewards.vault_account)),
+            Ok(RewardVaultStatus {
+                balance: 2500,
+                unclaimed: 1250,
+                committed: 0,
+                excess: 1250,
+                shortfall: 0,
+            })
+        );
+
+        for _ in 0..2 {
+            do_process_instruction(
+                claim_credited_rewards(
+                    &STAKE_PROGRAM_ID,
+                    &depositor_key,
+                    &pool.stake_store_key,
+                    &destination.0,
+                )
+                .unwrap(),
+                vec![
+                    &mut first_nft.owner_account,
+                    &mut pool.stake_store_account,
+                    &mut staker_account,
+                    &mut rewards.vault_account,
+                    &mut destination.1,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            assert_eq!(token_balance(&destination.1), 1750);
+        }
+        let staker = StakerAccount::load(&staker_account.data).unwrap();
+        assert_eq!(staker.credited_rewards, 0);
+        assert_eq!(
+            pool.stake_store()
+                .reward_vault_status(token_balance(&rewards.vault_account)),
+            Ok(RewardVaultStatus {
+                balance: 1250,
+                unclaimed: 0,
+                committed: 0,
+                excess: 1250,
+                shortfall: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_reclaim() {
+        const TIMEOUT: i64 = 1_000;
+        let mut pool = TestPool::with_config(
+            4,
+            PoolConfig {
+                reclaim_timeout: TIMEOUT as u64,
+                reclaim_bounty: 30,
+                ..PoolConfig::default()
+            },
+        )
+        .unwrap();
+        let mut rewards = TestRewards::new(&pool, 10_000);
+        let owner_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut cranker_destination = rewards.destination(&pubkey_rand());
+        // staked straight from its associated token account
+        let mut nft_in_ata = TestNFT::new(&owner_key);
+        nft_in_ata.stake_key = get_associated_token_address(&owner_key, &nft_in_ata.mint_key);
+        // staked from another account, the associated one is empty
+        let mut nft = TestNFT::new(&owner_key);
+        let (_, mut associated_token_account) = mint_token(
+            &spl_token::id(),
+            &nft.mint_key,
+            &mut nft.mint_account.clone(),
+            &pubkey_rand(),
+            &owner_key,
+            0,
+        );
+
+        pool.fund_reward_vault(&mut rewards, 0).unwrap();
+        pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+        pool.deposit(&mut nft_in_ata, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+        pool.fund_rewards(&mut rewards, 2000, 100, ZERO_TS).unwrap();
+
+        assert_eq!(
+            pool.reclaim(
+                &mut nft,
+                &mut staker_account,
+                &mut associated_token_account,
+                TIMEOUT - 1,
+                None
+            ),
+            Err(NFTStakingError::ReclaimTooEarly.into())
+        );
+        let mut wrong_destination = reclaim(
+            &STAKE_PROGRAM_ID,
+            &owner_key,
+            &nft.mint_key,
+            &pool.stake_store_key,
+            &pool.stake_list_key,
+            &nft.stake_key,
+            None,
+        )
+        .unwrap();
+        wrong_destination.accounts[7].pubkey = pubkey_rand();
+        assert_eq!(
+            do_process_instruction(
+                wrong_destination,
+                vec![
+                    &mut nft.owner_account,
+                    &mut nft.mint_account,
+                    &mut clock_account(TIMEOUT),
+                    &mut pool.stake_store_account,
+                    &mut pool.stake_list_account,
+                    &mut nft.stake_account,
+                    &mut Account::default(),
+                    &mut associated_token_account.clone(),
+                    &mut Account::default(),
+                    &mut staker_account,
+                ],
+            ),
+            Err(NFTStakingError::InvalidAssociatedTokenAccount.into())
+        );
+
+        // the cranker earns the bounty, the owner keeps the rest of the
+        // stake's 1000 rewards
+        pool.reclaim(
+            &mut nft,
+            &mut staker_account,
+            &mut associated_token_account,
+            TIMEOUT,
+            Some((&mut rewards, &mut cranker_destination)),
+        )
+        .unwrap();
+        assert_eq!(token_balance(&cranker_destination.1), 30);
+        let associated = unpack_token_account(&associated_token_account.data).unwrap();
+        assert_eq!(associated.amount, 1);
+        let stake_account = unpack_token_account(&nft.stake_account.data).unwrap();
+        assert_eq!(stake_account.owner, owner_key);
+        assert_eq!(stake_account.amount, 0);
+        assert_eq!(pool.staked_nfts().len(), 1);
+        assert_eq!(pool.stake_store().staked_count, 1);
+        assert_eq!(pool.stake_store().total_shares, BASIS_POINTS);
+        assert_eq!(
+            StakerAccount::load(&staker_account.data)
+                .unwrap()
+                .credited_rewards,
+            970
+        );
+
+        // a bounty is due, so the reward accounts can't be left off
+        let mut unused_account = nft_in_ata.stake_account.clone();
+        assert_eq!(
+            pool.reclaim(
+                &mut nft_in_ata,
+                &mut staker_account,
+                &mut unused_account,
+                TIMEOUT,
+                None,
+            ),
+            Err(NFTStakingError::MissingRewardAccounts.into())
+        );
+        // an NFT already in the associated account is only handed back
+        pool.reclaim(
+            &mut nft_in_ata,
+            &mut staker_account,
+            &mut unused_account,
+            TIMEOUT,
+            Some((&mut rewards, &mut cranker_destination)),
+        )
+        .unwrap();
+        assert_eq!(token_balance(&cranker_destination.1), 60);
+        let stake_account = unpack_token_account(&nft_in_ata.stake_account.data).unwrap();
+        assert_eq!(stake_account.owner, owner_key);
+        assert_eq!(stake_account.amount, 1);
+        assert!(pool.staked_nfts().is_empty());
+        let staker = StakerAccount::load(&staker_account.data).unwrap();
+        assert_eq!(staker.active_stakes, 0);
+        assert_eq!(staker.credited_rewards, 1940);
+
+        // the owner claims what the reclaims credited them
+        let mut owner_destination = rewards.destination(&owner_key);
+        do_process_instruction(
+            claim_credited_rewards(
+                &STAKE_PROGRAM_ID,
+                &owner_key,
+                &pool.stake_store_key,
+                &owner_destination.0,
+            )
+            .unwrap(),
+            vec![
+                &mut nft.owner_account,
+                &mut pool.stake_store_account,
+                &mut staker_account,
+                &mut rewards.vault_account,
+                &mut owner_destination.1,
+                &mut Account::default(),
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(token_balance(&owner_destination.1), 1940);
+    }
+
+    #[test]
+    fn test_reclaim_after_owner_inactivity() {
+        const TIMEOUT: i64 = 1_000;
+        let mut pool = TestPool::with_config(
+            4,
+            PoolConfig {
+                reclaim_timeout: TIMEOUT as u64,
+                ..PoolConfig::default()
+            },
+        )
+        .unwrap();
+        let mut rewards = TestRewards::new(&pool, 10_000);
+        let owner_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut nft = TestNFT::new(&owner_key);
+        nft.stake_key = get_associated_token_address(&owner_key, &nft.mint_key);
+        let mut destination = rewards.destination(&owner_key);
+
+        pool.fund_reward_vault(&mut rewards, 0).unwrap();
+        pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+
+        // a claim keeps the stake from being reclaimed
+        do_process_instruction(
+            claim_rewards(
+                &STAKE_PROGRAM_ID,
+                &owner_key,
+                &nft.mint_key,
+                &pool.stake_store_key,
+                &pool.stake_list_key,
+                &destination.0,
+            )
+            .unwrap(),
+            vec![
+                &mut nft.owner_account,
+                &mut nft.mint_account,
+                &mut clock_account(TIMEOUT / 2),
+                &mut pool.stake_store_account,
+                &mut pool.stake_list_account,
+                &mut rewards.vault_account,
+                &mut destination.1,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut staker_account,
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            StakerAccount::load(&staker_account.data)
+                .unwrap()
+                .last_active_ts,
+            TIMEOUT / 2
+        );
+        let mut unused_account = nft.stake_account.clone();
+        assert_eq!(
+            pool.reclaim(
+                &mut nft,
+                &mut staker_account,
+                &mut unused_account,
+                TIMEOUT,
+                None
+            ),
+            Err(NFTStakingError::ReclaimTooEarly.into())
+        );
+
+        // a checkpoint anyone can crank doesn't count as activity
+        do_process_instruction(
+            checkpoint(
+                &STAKE_PROGRAM_ID,
+                &pool.stake_store_key,
+                &pool.stake_list_key,
+                &owner_key,
+            )
+            .unwrap(),
+            vec![
+                &mut staker_account,
+                &mut pool.stake_store_account,
+                &mut pool.stake_list_account,
+                &mut clock_account(TIMEOUT),
+            ],
+        )
+        .unwrap();
+        pool.reclaim(
+            &mut nft,
+            &mut staker_account,
+            &mut unused_account,
+            TIMEOUT / 2 + TIMEOUT,
+            None,
+        )
+        .unwrap();
+        assert!(pool.staked_nfts().is_empty());
+    }
+
+    #[test]
+    fn test_emission_schedule() {
+        let mut pool = TestPool::new(4);
+        let mut rewards = TestRewards::new(&pool, 10_000);
+        let depositor_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut nft = TestNFT::new(&depositor_key);
+        let mut destination = rewards.destination(&depositor_key);
+
+        pool.fund_reward_vault(&mut rewards, 10_000).unwrap();
+        pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+
+        let manager_key = pool.manager_key;
+        pool.manager_key = pubkey_rand();
+        assert_eq!(
+            pool.append_emission_segments(&[(100, 10)], 300, ZERO_TS),
+            Err(NFTStakingError::InvalidRewardOperator.into())
+        );
+        pool.manager_key = manager_key;
+        pool.append_emission_segments(&[(100, 10), (200, 20)], 300, ZERO_TS)
+            .unwrap();
+        let mut stake_store = pool.stake_store();
+        stake_store.update_rewards(ZERO_TS).unwrap();
+        assert_eq!(stake_store.committed_rewards(), Ok(3000));
+
+        pool.claim_rewards(&mut rewards, &mut nft, &mut destination, 250)
+            .unwrap();
+        assert_eq!(token_balance(&destination.1), 1000 + 1000);
+
+        // the past can't be rewritten, the future can be extended
+        assert_eq!(
+            pool.append_emission_segments(&[(240, 50)], 400, 250),
+            Err(NFTStakingError::InvalidEmissionSchedule.into())
+        );
+        pool.append_emission_segments(&[(300, 5)], 400, 250).unwrap();
+        pool.claim_rewards(&mut rewards, &mut nft, &mut destination, 1000)
+            .unwrap();
+        assert_eq!(token_balance(&destination.1), 2000 + 50 * 20 + 100 * 5);
+    }
+
+    #[test]
+    fn test_rate_change_notice() {
+        const DELAY: i64 = 100;
+        let mut pool = TestPool::with_config(
+            4,
+            PoolConfig {
+                config_delay: DELAY as u64,
+                ..PoolConfig::default()
+            },
+        )
+        .unwrap();
+        let mut rewards = TestRewards::new(&pool, 10_000);
+        let depositor_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut nft = TestNFT::new(&depositor_key);
+
+        pool.fund_reward_vault(&mut rewards, 10_000).unwrap();
+        pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+
+        // a funded epoch would change the rate at once
+        assert_eq!(
+            pool.fund_rewards(&mut rewards, 1000, 100, ZERO_TS),
+            Err(NFTStakingError::RewardRateTimelocked.into())
+        );
+        assert_eq!(
+            pool.append_emission_segments(&[(DELAY - 1, 10)], 300, ZERO_TS),
+            Err(NFTStakingError::RewardRateTimelocked.into())
+        );
+        pool.append_emission_segments(&[(DELAY, 10)], 300, ZERO_TS)
+            .unwrap();
+
+        // cutting the schedule short or stretching its running segment
+        // changes the rate where the schedule used to end
+        assert_eq!(
+            pool.append_emission_segments(&[], 150, 100),
+            Err(NFTStakingError::RewardRateTimelocked.into())
+        );
+        assert_eq!(
+            pool.append_emission_segments(&[], 400, 250),
+            Err(NFTStakingError::RewardRateTimelocked.into())
+        );
+        pool.append_emission_segments(&[], 400, 200).unwrap();
+        assert_eq!(pool.stake_store().config.emission_schedule.end_ts, 400);
+    }
+
+    #[test]
+    fn test_close_pool() {
+        let mut pool = TestPool::new(4);
+        let mut rewards = TestRewards::new(&pool, 10_000);
+        let depositor_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut nft = TestNFT::new(&depositor_key);
+        let mut late_nft = TestNFT::new(&depositor_key);
+        let mut treasury = rewards.destination(&pool.manager_key);
+        let mut manager_account = Account::default();
+
+        pool.fund_reward_vault(&mut rewards, 100).unwrap();
+        pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+
+        let manager_key = pool.manager_key;
+        pool.manager_key = pubkey_rand();
+        assert_eq!(pool.sunset(), Err(NFTStakingError::InvalidManager.into()));
+        pool.manager_key = manager_key;
+        pool.sunset().unwrap();
+        assert!(pool.stake_store().sunset);
+        assert_eq!(
+            pool.deposit(&mut late_nft, &mut staker_account, ZERO_TS, None, None),
+            Err(NFTStakingError::PoolSunset.into())
+        );
+
+        assert_eq!(
+            pool.close(&mut rewards, (&manager_key, &mut manager_account)),
+            Err(NFTStakingError::PoolNotEmpty.into())
+        );
+        pool.withdraw(&mut nft, &mut staker_account, 10, None, UnsettledRewards::Credit)
+            .unwrap();
+        assert!(pool.stake_store().is_empty());
+        assert_eq!(
+            pool.close(&mut rewards, (&manager_key, &mut manager_account)),
+            Err(NFTStakingError::RewardVaultNotEmpty.into())
+        );
+        pool.withdraw_excess_rewards(&mut rewards, &mut treasury, 100, 10)
+            .unwrap();
+
+        let rent = pool.stake_store_account.lamports
+            + pool.stake_list_account.lamports
+            + rewards.vault_account.lamports;
+        pool.close(&mut rewards, (&manager_key, &mut manager_account)).unwrap();
+        assert_eq!(manager_account.lamports, rent);
+        for account in &[&pool.stake_store_account, &pool.stake_list_account] {
+            assert_eq!(account.lamports, 0);
+            assert!(account.data.iter().all(|byte| *byte == 0));
         }
+        assert_eq!(rewards.vault_account.lamports, 0);
     }
-}
 
-#[cfg(test)]
+    #[test]
+    fn test_close_pool_after_multi_amount_deposit() {
+        let mut pool = TestPool::new(4);
+        let mut rewards = TestRewards::new(&pool, 10_000);
+        let depositor_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut nft = TestNFT::new(&depositor_key);
+        let manager_key = pool.manager_key;
+        let mut manager_account = Account::default();
 
-mod tests {
-    use super::*;
-    use crate::{
-        instruction::{deposit_nft, initialize},
-        state::{StakeList, StakeListHeader, StakeStore, StakedNFT},
-        utils::{test_utils::*, unpack_token_account, MAX_ITEMS},
-    };
-    use borsh::BorshSerialize;
-    use solana_program::{
-        borsh::{get_instance_packed_len, get_packed_len, try_from_slice_unchecked},
-        clock::Epoch,
-        msg,
-        program_pack::Pack,
-        rent::Rent,
-        sysvar,
-    };
-    use solana_sdk::account::{create_account_for_test, Account, WritableAccount};
-    use spl_token::{
-        instruction::{initialize_account, initialize_mint, mint_to, transfer},
-        state::{Account as SplAccount, Mint as SplMint},
-    };
+        // the instruction's amount doesn't decide whether the pool is empty
+        do_process_instruction(
+            deposit_nft(
+                &STAKE_PROGRAM_ID,
+                &nft.owner_key,
+                &nft.mint_key,
+                &nft.stake_key,
+                &pool.stake_store_key,
+                &pool.stake_list_key,
+                None,
+                None,
+                2,
+                None,
+                None,
+            )
+            .unwrap(),
+            vec![
+                &mut nft.owner_account,
+                &mut nft.mint_account,
+                &mut clock_account(ZERO_TS),
+                &mut nft.stake_account,
+                &mut pool.stake_store_account,
+                &mut pool.stake_list_account,
+                &mut Account::default(),
+                &mut staker_account,
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(pool.staked_nfts().len(), 1);
+        assert_eq!(pool.stake_store().staked_count, 1);
+        pool.withdraw(&mut nft, &mut staker_account, 10, None, UnsettledRewards::Credit)
+            .unwrap();
+        assert!(pool.stake_store().is_empty());
+        pool.close(&mut rewards, (&manager_key, &mut manager_account)).unwrap();
+        assert_eq!(pool.stake_store_account.lamports, 0);
+    }
 
     #[test]
-    fn test_deposit_nft() {
-        msg!("starting test_deposit_nft {}", STAKE_PROGRAM_ID);
+    fn test_close_pool_checks() {
+        let mut pool = TestPool::new(4);
+        let mut rewards = TestRewards::new(&pool, 0);
+        let manager_key = pool.manager_key;
+        let mut manager_account = Account::default();
+
+        assert_eq!(
+            pool.close(&mut rewards, (&pubkey_rand(), &mut Account::default())),
+            Err(NFTStakingError::InvalidRentDestination.into())
+        );
+
+        // a counter left behind blocks the close even with an empty stake list
+        let stake_store = pool.stake_store();
+        for counters in &[(1, 0), (0, 5)] {
+            let mut corrupted = stake_store.clone();
+            corrupted.staked_count = counters.0;
+            corrupted.unclaimed_rewards = counters.1;
+            corrupted.save(&mut pool.stake_store_account.data).unwrap();
+            assert_eq!(
+                pool.close(&mut rewards, (&manager_key, &mut manager_account)),
+                Err(NFTStakingError::PoolNotEmpty.into())
+            );
+        }
+        stake_store.save(&mut pool.stake_store_account.data).unwrap();
+        pool.close(&mut rewards, (&manager_key, &mut manager_account)).unwrap();
+        assert!(manager_account.lamports > 0);
+
+        // a multisig manager's rent goes to one of its members
+        let member_keys = [pubkey_rand(), pubkey_rand()];
+        let [first_key, second_key] = &member_keys;
+        let manager_key = pubkey_rand();
+        let mut manager_account = multisig_account(1, &member_keys);
+        let mut pool = TestPool::with_manager(
+            4,
+            PoolConfig::default(),
+            (manager_key, &mut manager_account),
+            &[first_key],
+        )
+        .unwrap();
+        let mut close_paying = |pool: &mut TestPool, rent_destination_key: &Pubkey| {
+            let instruction = close_pool(
+                &STAKE_PROGRAM_ID,
+                &pool.stake_store_key,
+                &pool.stake_list_key,
+                &manager_key,
+                rent_destination_key,
+                None,
+                false,
+            )
+            .unwrap();
+            let mut rent_destination_account = Account::default();
+            do_process_instruction(
+                with_authority_signers(instruction, &manager_key, &[first_key]),
+                vec![
+                    &mut pool.stake_store_account,
+                    &mut pool.stake_list_account,
+                    &mut manager_account,
+                    &mut rent_destination_account,
+                    &mut Account::default(),
+                ],
+            )?;
+            Ok::<_, ProgramError>(rent_destination_account.lamports)
+        };
+        assert_eq!(
+            close_paying(&mut pool, &manager_key),
+            Err(NFTStakingError::InvalidRentDestination.into())
+        );
+        assert!(close_paying(&mut pool, second_key).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_stake_limits() {
+        let mut pool = TestPool::new(8);
+        let first_owner_key = pubkey_rand();
+        let second_owner_key = pubkey_rand();
+        let mut first_staker_account = staker_account();
+        let mut second_staker_account = staker_account();
+        let mut first_owner_nfts = (0..3)
+            .map(|_| TestNFT::new(&first_owner_key))
+            .collect::<Vec<_>>();
+        let mut second_owner_nfts = (0..2)
+            .map(|_| TestNFT::new(&second_owner_key))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            do_process_instruction(
+                set_stake_limits(&STAKE_PROGRAM_ID, &pool.stake_store_key, &pubkey_rand(), 2, 3)
+                    .unwrap(),
+                vec![&mut pool.stake_store_account, &mut Account::default()],
+            ),
+            Err(NFTStakingError::InvalidManager.into())
+        );
+        do_process_instruction(
+            set_stake_limits(&STAKE_PROGRAM_ID, &pool.stake_store_key, &pool.manager_key, 2, 3)
+                .unwrap(),
+            vec![&mut pool.stake_store_account, &mut Account::default()],
+        )
+        .unwrap();
+
+        for nft in first_owner_nfts[..2].iter_mut() {
+            pool.deposit(nft, &mut first_staker_account, ZERO_TS, None, None)
+                .unwrap();
+        }
+        assert_eq!(
+            pool.deposit(
+                &mut first_owner_nfts[2],
+                &mut first_staker_account,
+                ZERO_TS,
+                None,
+                None
+            ),
+            Err(NFTStakingError::OwnerStakeLimitExceeded.into())
+        );
+
+        pool.deposit(
+            &mut second_owner_nfts[0],
+            &mut second_staker_account,
+            ZERO_TS,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            pool.deposit(
+                &mut second_owner_nfts[1],
+                &mut second_staker_account,
+                ZERO_TS,
+                None,
+                None
+            ),
+            Err(NFTStakingError::PoolCapacityExceeded.into())
+        );
+
+        // withdrawing frees a slot both in the pool and for the owner
+        pool.withdraw(
+            &mut first_owner_nfts[0],
+            &mut first_staker_account,
+            10,
+            None,
+            UnsettledRewards::Credit,
+        )
+        .unwrap();
+        pool.deposit(
+            &mut first_owner_nfts[2],
+            &mut first_staker_account,
+            10,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(pool.staked_nfts().len(), 3);
+        assert_eq!(pool.stake_store().staked_count, 3);
+    }
+
+    #[test]
+    fn test_owner_limit_counts_migrated_stakes() {
+        let mut pool = TestPool::new(8);
         let owner_key = pubkey_rand();
-        let depositor_key = pubkey_rand();
-        let stake_store_key = pubkey_rand();
-        let stake_list_key = pubkey_rand();
+        let mut staker = staker_account();
+        let mut nfts = (0..3)
+            .map(|_| TestNFT::new(&owner_key))
+            .collect::<Vec<_>>();
+        for nft in nfts[..2].iter_mut() {
+            pool.deposit(nft, &mut staker, ZERO_TS, None, None).unwrap();
+        }
+        do_process_instruction(
+            set_stake_limits(&STAKE_PROGRAM_ID, &pool.stake_store_key, &pool.manager_key, 2, 8)
+                .unwrap(),
+            vec![&mut pool.stake_store_account, &mut Account::default()],
+        )
+        .unwrap();
 
-        // setup accounts
-        let mut depositor_account = Account::default();
-        let mut stake_store_account = Account::new(
-            Rent::default().minimum_balance(get_packed_len::<StakeStore>()),
-            get_packed_len::<StakeStore>(),
+        // stakes from the first release have no ledger behind them
+        let mut staker = staker_account();
+        assert_eq!(
+            pool.deposit(&mut nfts[2], &mut staker, 10, None, None),
+            Err(NFTStakingError::OwnerStakeLimitExceeded.into())
+        );
+        assert_eq!(staker.data, staker_account().data);
+
+        do_process_instruction(
+            set_stake_limits(&STAKE_PROGRAM_ID, &pool.stake_store_key, &pool.manager_key, 3, 8)
+                .unwrap(),
+            vec![&mut pool.stake_store_account, &mut Account::default()],
+        )
+        .unwrap();
+        pool.deposit(&mut nfts[2], &mut staker, 10, None, None)
+            .unwrap();
+        assert_eq!(StakerAccount::load(&staker.data).unwrap().active_stakes, 3);
+        let (header, _) =
+            StakeListHeader::deserialize_vec(&mut pool.stake_list_account.data).unwrap();
+        assert_eq!(header.count, 3);
+
+        pool.withdraw(&mut nfts[0], &mut staker, 20, None, UnsettledRewards::Credit)
+            .unwrap();
+        assert_eq!(StakerAccount::load(&staker.data).unwrap().active_stakes, 2);
+        let (header, _) =
+            StakeListHeader::deserialize_vec(&mut pool.stake_list_account.data).unwrap();
+        assert_eq!(header.count, 2);
+    }
+
+    #[test]
+    fn test_pool_config() {
+        const LOCKUP: i64 = 3_600;
+        assert_eq!(
+            TestPool::with_config(
+                4,
+                PoolConfig {
+                    fees: FeeConfig {
+                        deposit_fee: 1_000,
+                        ..FeeConfig::default()
+                    },
+                    ..PoolConfig::default()
+                }
+            )
+            .err(),
+            Some(NFTStakingError::InvalidFeeConfig.into())
+        );
+        assert_eq!(
+            TestPool::with_config(
+                4,
+                PoolConfig {
+                    max_items: 5,
+                    ..PoolConfig::default()
+                }
+            )
+            .err(),
+            Some(NFTStakingError::InvalidPoolConfig.into())
+        );
+
+        let config = PoolConfig {
+            max_items: 2,
+            lockup_seconds: LOCKUP as u64,
+            ..PoolConfig::default()
+        };
+        let mut pool = TestPool::with_config(4, config.clone()).unwrap();
+        assert_eq!(pool.stake_store().config, config);
+        let (header, _) =
+            StakeListHeader::deserialize_vec(&mut pool.stake_list_account.data).unwrap();
+        assert_eq!(header.max_items, 2);
+
+        let owner_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut nft = TestNFT::new(&owner_key);
+        let mut set_paused_as = |pool: &mut TestPool, pauser_key: &Pubkey, paused: bool| {
+            do_process_instruction(
+                set_paused(&STAKE_PROGRAM_ID, &pool.stake_store_key, pauser_key, paused)
+                    .unwrap(),
+                vec![&mut pool.stake_store_account, &mut Account::default()],
+            )
+        };
+        assert_eq!(
+            set_paused_as(&mut pool, &pubkey_rand(), true),
+            Err(NFTStakingError::InvalidPauser.into())
+        );
+        let manager_key = pool.manager_key;
+        set_paused_as(&mut pool, &manager_key, true).unwrap();
+        assert_eq!(
+            pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None),
+            Err(NFTStakingError::PoolPaused.into())
+        );
+        set_paused_as(&mut pool, &manager_key, false).unwrap();
+        pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+
+        assert_eq!(
+            pool.withdraw(
+                &mut nft,
+                &mut staker_account,
+                LOCKUP - 1,
+                None,
+                UnsettledRewards::Credit
+            ),
+            Err(NFTStakingError::StakeLocked.into())
+        );
+        pool.withdraw(&mut nft, &mut staker_account, LOCKUP, None, UnsettledRewards::Credit)
+            .unwrap();
+        assert!(pool.staked_nfts().is_empty());
+    }
+
+    /// An spl-token multisig account requiring `m` of `signer_keys`
+    fn multisig_account(m: u8, signer_keys: &[Pubkey]) -> Account {
+        let mut signers = [Pubkey::default(); MAX_SIGNERS];
+        signers[..signer_keys.len()].copy_from_slice(signer_keys);
+        let mut account = Account::new(
+            Rent::default().minimum_balance(Multisig::LEN),
+            Multisig::LEN,
+            &spl_token::id(),
+        );
+        Multisig {
+            m,
+            n: signer_keys.len() as u8,
+            is_initialized: true,
+            signers,
+        }
+        .pack_into_slice(&mut account.data);
+        account
+    }
+
+    #[test]
+    fn test_multisig_manager() {
+        let member_keys = [pubkey_rand(), pubkey_rand(), pubkey_rand()];
+        let [first_key, second_key, third_key] = &member_keys;
+        let manager_key = pubkey_rand();
+        let mut manager_account = multisig_account(2, &member_keys);
+        assert_eq!(
+            TestPool::with_manager(
+                4,
+                PoolConfig::default(),
+                (manager_key, &mut manager_account),
+                &[first_key]
+            )
+            .err(),
+            Some(NFTStakingError::SignatureMissing.into())
+        );
+        let mut pool = TestPool::with_manager(
+            4,
+            PoolConfig::default(),
+            (manager_key, &mut manager_account),
+            &[first_key, third_key],
+        )
+        .unwrap();
+        assert_eq!(pool.stake_store().manager, manager_key);
+
+        let mut set_paused_signed_by = |pool: &mut TestPool, signer_keys: &[&Pubkey]| {
+            let instruction =
+                set_paused(&STAKE_PROGRAM_ID, &pool.stake_store_key, &manager_key, true).unwrap();
+            let mut signer_accounts = vec![Account::default(); signer_keys.len()];
+            let mut accounts = vec![&mut pool.stake_store_account, &mut manager_account];
+            accounts.extend(signer_accounts.iter_mut());
+            do_process_instruction(
+                with_authority_signers(instruction, &manager_key, signer_keys),
+                accounts,
+            )
+        };
+        // a multisig can't sign by itself, nor count outsiders or a member twice
+        assert_eq!(
+            set_paused_signed_by(&mut pool, &[]),
+            Err(NFTStakingError::SignatureMissing.into())
+        );
+        assert_eq!(
+            set_paused_signed_by(&mut pool, &[second_key, &pubkey_rand()]),
+            Err(NFTStakingError::SignatureMissing.into())
+        );
+        assert_eq!(
+            set_paused_signed_by(&mut pool, &[second_key, second_key]),
+            Err(NFTStakingError::SignatureMissing.into())
+        );
+        assert!(!pool.stake_store().config.paused);
+        set_paused_signed_by(&mut pool, &[second_key, third_key]).unwrap();
+        assert!(pool.stake_store().config.paused);
+
+        // members only count when they trail the instruction's accounts
+        let mut instruction = with_authority_signers(
+            set_paused(&STAKE_PROGRAM_ID, &pool.stake_store_key, &manager_key, false).unwrap(),
+            &manager_key,
+            &[second_key, third_key],
+        );
+        instruction.accounts.push(AccountMeta::new_readonly(pubkey_rand(), false));
+        assert_eq!(
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut pool.stake_store_account,
+                    &mut manager_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            ),
+            Err(NFTStakingError::SignatureMissing.into())
+        );
+        assert!(pool.stake_store().config.paused);
+
+        // the reward source belongs to the multisig too
+        let mut rewards = TestRewards::new(&pool, 1_000);
+        let instruction = fund_reward_vault(
             &STAKE_PROGRAM_ID,
+            &pool.stake_store_key,
+            &manager_key,
+            &rewards.mint_key,
+            &rewards.source_key,
+            1_000,
+        )
+        .unwrap();
+        let mut first_account = Account::default();
+        let mut second_account = Account::default();
+        do_process_instruction(
+            with_authority_signers(instruction, &manager_key, &[first_key, second_key]),
+            vec![
+                &mut pool.stake_store_account,
+                &mut manager_account,
+                &mut rewards.mint_account,
+                &mut rewards.source_account,
+                &mut rewards.vault_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut create_account_for_test(&Rent::default()),
+                &mut first_account,
+                &mut second_account,
+            ],
+        )
+        .unwrap();
+        assert_eq!(token_balance(&rewards.source_account), 0);
+        assert_eq!(token_balance(&rewards.vault_account), 1_000);
+    }
+
+    #[test]
+    fn test_roles() {
+        let reward_operator_key = pubkey_rand();
+        let pauser_key = pubkey_rand();
+        let mut pool = TestPool::with_config(
+            4,
+            PoolConfig {
+                reward_operator: reward_operator_key,
+                pauser: pauser_key,
+                ..PoolConfig::default()
+            },
+        )
+        .unwrap();
+        let manager_key = pool.manager_key;
+        let stake_store = pool.stake_store();
+        assert_eq!(stake_store.authority(Role::Admin), manager_key);
+        assert_eq!(stake_store.authority(Role::RewardOperator), reward_operator_key);
+        assert_eq!(stake_store.authority(Role::Pauser), pauser_key);
+
+        let set_paused_as = |pool: &mut TestPool, pauser_key: &Pubkey| {
+            do_process_instruction(
+                set_paused(&STAKE_PROGRAM_ID, &pool.stake_store_key, pauser_key, true).unwrap(),
+                vec![&mut pool.stake_store_account, &mut Account::default()],
+            )
+        };
+        let set_authority_as =
+            |pool: &mut TestPool, manager_key: &Pubkey, role: Role, authority_key: &Pubkey| {
+                do_process_instruction(
+                    set_authority(
+                        &STAKE_PROGRAM_ID,
+                        &pool.stake_store_key,
+                        manager_key,
+                        role,
+                        authority_key,
+                    )
+                    .unwrap(),
+                    vec![&mut pool.stake_store_account, &mut Account::default()],
+                )
+            };
+
+        // each role only signs its own instructions
+        assert_eq!(
+            set_paused_as(&mut pool, &manager_key),
+            Err(NFTStakingError::InvalidPauser.into())
+        );
+        assert_eq!(
+            set_authority_as(&mut pool, &pauser_key, Role::Pauser, &pubkey_rand()),
+            Err(NFTStakingError::InvalidManager.into())
         );
+        let mut rewards = TestRewards::new(&pool, 1_000);
+        assert_eq!(
+            pool.fund_reward_vault(&mut rewards, 1_000),
+            Err(NFTStakingError::InvalidRewardOperator.into())
+        );
+        set_paused_as(&mut pool, &pauser_key).unwrap();
+        assert!(pool.stake_store().config.paused);
+        pool.manager_key = reward_operator_key;
+        let mut rewards = TestRewards::new(&pool, 1_000);
+        pool.fund_reward_vault(&mut rewards, 1_000).unwrap();
+        assert_eq!(token_balance(&rewards.vault_account), 1_000);
+        pool.manager_key = manager_key;
 
-        let mut stake_store =
-            try_from_slice_unchecked::<StakeStore>(&stake_store_account.data).unwrap();
-        let mut test_data = vec![1; stake_store_account.data.len()];
-        let mut fee = Rent::default().minimum_balance(get_packed_len::<StakeStore>());
+        // a delegated role returns to the manager, who can't be removed
+        set_authority_as(&mut pool, &manager_key, Role::Pauser, &Pubkey::default()).unwrap();
+        assert_eq!(pool.stake_store().authority(Role::Pauser), manager_key);
+        assert_eq!(
+            set_paused_as(&mut pool, &pauser_key),
+            Err(NFTStakingError::InvalidPauser.into())
+        );
+        set_paused_as(&mut pool, &manager_key).unwrap();
+        assert_eq!(
+            set_authority_as(&mut pool, &manager_key, Role::Admin, &Pubkey::default()),
+            Err(NFTStakingError::InvalidManager.into())
+        );
 
-        let stake_store_account_info = AccountInfo::new(
-            &stake_store_key,
-            false,
-            true,
-            &mut fee,
-            test_data.as_mut_slice(),
-            &depositor_key,
-            false,
-            Epoch::default(),
+        // rotating the manager hands over every role still held by it
+        let new_manager_key = pubkey_rand();
+        set_authority_as(&mut pool, &manager_key, Role::Admin, &new_manager_key).unwrap();
+        let stake_store = pool.stake_store();
+        assert_eq!(stake_store.manager, new_manager_key);
+        assert_eq!(stake_store.authority(Role::Pauser), new_manager_key);
+        assert_eq!(stake_store.authority(Role::RewardOperator), reward_operator_key);
+        assert_eq!(
+            set_authority_as(&mut pool, &manager_key, Role::Admin, &manager_key),
+            Err(NFTStakingError::InvalidManager.into())
+        );
+    }
+
+    #[test]
+    fn test_pending_config() {
+        const DELAY: i64 = 86_400;
+        let config = PoolConfig {
+            max_items: 4,
+            config_delay: DELAY as u64,
+            ..PoolConfig::default()
+        };
+        let mut pool = TestPool::with_config(4, config.clone()).unwrap();
+        let manager_key = pool.manager_key;
+        let propose_as = |pool: &mut TestPool, manager_key: &Pubkey, config: PoolConfig, ts| {
+            do_process_instruction(
+                propose_config(&STAKE_PROGRAM_ID, &pool.stake_store_key, manager_key, config)
+                    .unwrap(),
+                vec![
+                    &mut pool.stake_store_account,
+                    &mut Account::default(),
+                    &mut clock_account(ts),
+                ],
+            )
+        };
+        let apply = |pool: &mut TestPool, ts| {
+            do_process_instruction(
+                apply_pending_config(&STAKE_PROGRAM_ID, &pool.stake_store_key).unwrap(),
+                vec![&mut pool.stake_store_account, &mut clock_account(ts)],
+            )
+        };
+        let cancel = |pool: &mut TestPool| {
+            do_process_instruction(
+                cancel_pending_config(&STAKE_PROGRAM_ID, &pool.stake_store_key, &manager_key)
+                    .unwrap(),
+                vec![&mut pool.stake_store_account, &mut Account::default()],
+            )
+        };
+
+        let locked = PoolConfig {
+            lockup_seconds: 3_600,
+            config_delay: 0,
+            ..config.clone()
+        };
+        assert_eq!(
+            propose_as(&mut pool, &pubkey_rand(), locked.clone(), ZERO_TS),
+            Err(NFTStakingError::InvalidManager.into())
+        );
+        assert_eq!(
+            propose_as(
+                &mut pool,
+                &manager_key,
+                PoolConfig {
+                    max_items: 2,
+                    ..locked.clone()
+                },
+                ZERO_TS
+            ),
+            Err(NFTStakingError::InvalidPoolConfig.into())
+        );
+        assert_eq!(apply(&mut pool, ZERO_TS), Err(NFTStakingError::NoPendingConfig.into()));
+        assert_eq!(cancel(&mut pool), Err(NFTStakingError::NoPendingConfig.into()));
+
+        // a proposal waits out the delay of the config it replaces
+        propose_as(&mut pool, &manager_key, locked.clone(), ZERO_TS).unwrap();
+        assert_eq!(pool.stake_store().pending_config.effective_at, DELAY);
+        assert_eq!(
+            apply(&mut pool, DELAY - 1),
+            Err(NFTStakingError::ConfigTimelocked.into())
+        );
+        assert_eq!(pool.stake_store().config, config);
+        do_process_instruction(
+            set_paused(&STAKE_PROGRAM_ID, &pool.stake_store_key, &manager_key, true).unwrap(),
+            vec![&mut pool.stake_store_account, &mut Account::default()],
+        )
+        .unwrap();
+        let pauser_key = pubkey_rand();
+        do_process_instruction(
+            set_authority(
+                &STAKE_PROGRAM_ID,
+                &pool.stake_store_key,
+                &manager_key,
+                Role::Pauser,
+                &pauser_key,
+            )
+            .unwrap(),
+            vec![&mut pool.stake_store_account, &mut Account::default()],
+        )
+        .unwrap();
+        // the config itself only changes through proposals meanwhile
+        assert_eq!(
+            do_process_instruction(
+                set_stake_limits(&STAKE_PROGRAM_ID, &pool.stake_store_key, &manager_key, 1, 1)
+                    .unwrap(),
+                vec![&mut pool.stake_store_account, &mut Account::default()],
+            ),
+            Err(NFTStakingError::ConfigChangeNeedsProposal.into())
         );
-
-        let list_size = get_packed_len::<StakedNFT>() * MAX_ITEMS as usize
-            + get_packed_len::<StakeListHeader>(); //get_packed_len::<StakeList>();
-                                                   // msg!("list_size: {}", list_size);
-        let mut stake_list_account = Account::new(
-            Rent::default().minimum_balance(list_size),
-            list_size,
-            &STAKE_PROGRAM_ID,
+        apply(&mut pool, DELAY).unwrap();
+        let stake_store = pool.stake_store();
+        assert_eq!(
+            stake_store.config,
+            PoolConfig {
+                paused: true,
+                pauser: pauser_key,
+                ..locked.clone()
+            }
         );
-        let (nft1_mint_key, mut nft1_mint_account) =
-            create_mint(&spl_token::id(), &owner_key, DEFAULT_TOKEN_DECIMALS, None);
-        let (nft2_mint_key, mut nft2_mint_account) =
-            create_mint(&spl_token::id(), &owner_key, DEFAULT_TOKEN_DECIMALS, None);
+        assert!(!stake_store.pending_config.is_pending);
+        assert_eq!(apply(&mut pool, DELAY), Err(NFTStakingError::NoPendingConfig.into()));
 
-        let (user_nft1_key, mut user_nft1_account) = mint_token(
-            &spl_token::id(),
-            &nft1_mint_key,
-            &mut nft1_mint_account,
-            &owner_key,
-            &depositor_key,
-            1,
+        // without a delay left the next change applies right away, unless
+        // it's cancelled first
+        propose_as(&mut pool, &manager_key, config.clone(), DELAY).unwrap();
+        cancel(&mut pool).unwrap();
+        assert_eq!(apply(&mut pool, DELAY), Err(NFTStakingError::NoPendingConfig.into()));
+        assert_eq!(pool.stake_store().config.lockup_seconds, 3_600);
+        propose_as(&mut pool, &manager_key, config.clone(), DELAY).unwrap();
+        apply(&mut pool, DELAY).unwrap();
+        assert_eq!(pool.stake_store().config.config_delay, DELAY as u64);
+    }
+
+    #[test]
+    fn test_fees() {
+        let mut pool = TestPool::new(4);
+        let owner_key = pubkey_rand();
+        let exempt_owner_key = pubkey_rand();
+        let treasury_key = pubkey_rand();
+        let mut exempt_staker_account = staker_account();
+        let mut staker_account = staker_account();
+        let mut nft = TestNFT::new(&owner_key);
+        nft.owner_account.lamports = 1_000;
+        let mut exempt_nft = TestNFT::new(&exempt_owner_key);
+        let mut exemptions = FeeExemptions::default();
+        exemptions.set(&[exempt_owner_key]).unwrap();
+        let fees = FeeConfig {
+            deposit_fee: 100,
+            withdraw_fee: 50,
+            fee_mint: Pubkey::default(),
+            treasury: treasury_key,
+            exemptions,
+        };
+        let set_fees_as = |pool: &mut TestPool, manager_key: &Pubkey, fees: FeeConfig| {
+            do_process_instruction(
+                set_fees(&STAKE_PROGRAM_ID, &pool.stake_store_key, manager_key, fees).unwrap(),
+                vec![&mut pool.stake_store_account, &mut Account::default()],
+            )
+        };
+        let manager_key = pool.manager_key;
+        assert_eq!(
+            set_fees_as(&mut pool, &pubkey_rand(), fees.clone()),
+            Err(NFTStakingError::InvalidManager.into())
         );
+        assert_eq!(
+            set_fees_as(
+                &mut pool,
+                &manager_key,
+                FeeConfig {
+                    treasury: Pubkey::default(),
+                    ..fees.clone()
+                }
+            ),
+            Err(NFTStakingError::InvalidFeeConfig.into())
+        );
+        set_fees_as(&mut pool, &manager_key, fees.clone()).unwrap();
+        assert_eq!(pool.stake_store().config.fees, fees);
 
-        let (user_nft2_key, mut user_nft2_account) = mint_token(
-            &spl_token::id(),
-            &nft2_mint_key,
-            &mut nft2_mint_account,
-            &owner_key,
-            &depositor_key,
-            1,
+        // lamport fees
+        assert_eq!(
+            pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        let mut wrong_treasury = TestFeePayment {
+            payment: FeePayment::Lamports {
+                treasury: pubkey_rand(),
+            },
+            treasury_account: Account::default(),
+            payment_account: Account::default(),
+        };
+        assert_eq!(
+            pool.deposit_paying(
+                &mut nft,
+                &mut staker_account,
+                ZERO_TS,
+                None,
+                None,
+                Some(&mut wrong_treasury)
+            ),
+            Err(NFTStakingError::InvalidTreasury.into())
         );
+        let mut lamport_fee = TestFeePayment {
+            payment: FeePayment::Lamports {
+                treasury: treasury_key,
+            },
+            treasury_account: Account::default(),
+            payment_account: Account::default(),
+        };
+        pool.deposit_paying(
+            &mut nft,
+            &mut staker_account,
+            ZERO_TS,
+            None,
+            None,
+            Some(&mut lamport_fee),
+        )
+        .unwrap();
+        assert_eq!(lamport_fee.treasury_account.lamports, 100);
+        assert_eq!(nft.owner_account.lamports, 900);
+        pool.withdraw_paying(
+            &mut nft,
+            &mut staker_account,
+            10,
+            None,
+            UnsettledRewards::Credit,
+            Some(&mut lamport_fee),
+        )
+        .unwrap();
+        assert_eq!(lamport_fee.treasury_account.lamports, 150);
+        assert_eq!(nft.owner_account.lamports, 850);
 
-        let (stake_nft1_key, mut stake_nft1_account) = mint_token(
+        // exempt wallets pass no fee accounts
+        pool.deposit(&mut exempt_nft, &mut exempt_staker_account, ZERO_TS, None, None)
+            .unwrap();
+        pool.withdraw(
+            &mut exempt_nft,
+            &mut exempt_staker_account,
+            10,
+            None,
+            UnsettledRewards::Credit,
+        )
+        .unwrap();
+
+        // token fees
+        let fee_mint_authority_key = pubkey_rand();
+        let (fee_mint_key, mut fee_mint_account) =
+            create_mint(&spl_token::id(), &fee_mint_authority_key, 0, None);
+        let (source_key, source_account) = mint_token(
             &spl_token::id(),
-            &nft1_mint_key,
-            &mut nft1_mint_account,
+            &fee_mint_key,
+            &mut fee_mint_account,
+            &fee_mint_authority_key,
             &owner_key,
-            &depositor_key,
-            0,
+            1_000,
         );
-
-        let (stake_nft2_key, mut stake_nft2_account) = mint_token(
+        let (token_treasury_key, token_treasury_account) = mint_token(
             &spl_token::id(),
-            &nft2_mint_key,
-            &mut nft2_mint_account,
-            &owner_key,
-            &depositor_key,
+            &fee_mint_key,
+            &mut fee_mint_account,
+            &fee_mint_authority_key,
+            &pubkey_rand(),
             0,
         );
+        set_fees_as(
+            &mut pool,
+            &manager_key,
+            FeeConfig {
+                fee_mint: fee_mint_key,
+                treasury: token_treasury_key,
+                ..fees
+            },
+        )
+        .unwrap();
+        let mut token_fee = TestFeePayment {
+            payment: FeePayment::Token {
+                source: source_key,
+                treasury: token_treasury_key,
+            },
+            treasury_account: token_treasury_account,
+            payment_account: source_account,
+        };
+        pool.deposit_paying(
+            &mut nft,
+            &mut staker_account,
+            20,
+            None,
+            None,
+            Some(&mut token_fee),
+        )
+        .unwrap();
+        assert_eq!(token_balance(&token_fee.treasury_account), 100);
+        assert_eq!(token_balance(&token_fee.payment_account), 900);
+        assert_eq!(nft.owner_account.lamports, 850);
+    }
 
-        {
-            let user_nft1 = unpack_token_account(&user_nft1_account.data).unwrap();
-            assert_eq!(user_nft1.amount, 1);
-            let user_nft2 = unpack_token_account(&user_nft2_account.data).unwrap();
-            assert_eq!(user_nft1.amount, 1);
-            let stake_nft1 = unpack_token_account(&stake_nft1_account.data).unwrap();
-            assert_eq!(stake_nft1.amount, 0);
-            let stake_nft2 = unpack_token_account(&stake_nft2_account.data).unwrap();
-            assert_eq!(stake_nft2.amount, 0);
-        }
+    #[test]
+    fn test_stake_tiers() {
+        const DAY: i64 = 86_400;
+        let mut pool = TestPool::new(4);
+        let owner_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut nft = TestNFT::new(&owner_key);
+        let mut tier_record = tier_record_account();
+        let cranker_key = pubkey_rand();
+
+        let record_tier_at = |pool: &mut TestPool,
+                                  nft: &TestNFT,
+                                  tier_record: &mut Account,
+                                  ts: i64|
+         -> Result<TierRecord, ProgramError> {
+            do_process_instruction(
+                record_tier(
+                    &STAKE_PROGRAM_ID,
+                    &cranker_key,
+                    &nft.mint_key,
+                    &pool.stake_store_key,
+                    &pool.stake_list_key,
+                )
+                .unwrap(),
+                vec![
+                    &mut Account::default(),
+                    &mut nft.mint_account.clone(),
+                    &mut clock_account(ts),
+                    &mut pool.stake_store_account,
+                    &mut pool.stake_list_account,
+                    tier_record,
+                    &mut Account::default(),
+                ],
+            )?;
+            Ok(TierRecord::load(&tier_record.data).unwrap())
+        };
 
+        let thresholds = vec![30 * DAY as u64, 60 * DAY as u64, 90 * DAY as u64];
+        assert_eq!(
+            do_process_instruction(
+                set_stake_tiers(
+                    &STAKE_PROGRAM_ID,
+                    &pool.stake_store_key,
+                    &pubkey_rand(),
+                    thresholds.clone()
+                )
+                .unwrap(),
+                vec![&mut pool.stake_store_account, &mut Account::default()],
+            ),
+            Err(NFTStakingError::InvalidManager.into())
+        );
         do_process_instruction(
-            transfer(
-                &spl_token::id(),
-                &user_nft1_key,
-                &stake_nft1_key,
-                &depositor_key,
-                &[],
-                1,
+            set_stake_tiers(
+                &STAKE_PROGRAM_ID,
+                &pool.stake_store_key,
+                &pool.manager_key,
+                thresholds.clone(),
             )
             .unwrap(),
-            vec![
-                &mut user_nft1_account,
-                &mut stake_nft1_account,
-                &mut depositor_account,
-            ],
+            vec![&mut pool.stake_store_account, &mut Account::default()],
+        )
+        .unwrap();
+        assert_eq!(
+            pool.stake_store().config.stake_tiers.thresholds(),
+            &thresholds[..]
+        );
+
+        // nothing to record before the NFT is staked
+        assert_eq!(
+            record_tier_at(&mut pool, &nft, &mut tier_record, ZERO_TS),
+            Err(NFTStakingError::StakedNFTNotFound.into())
+        );
+
+        pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+        let record = record_tier_at(&mut pool, &nft, &mut tier_record, 29 * DAY).unwrap();
+        assert_eq!(record.tier, 0);
+        assert_eq!(record.owner, owner_key);
+        assert_eq!(record.token_mint, nft.mint_key);
+        assert_eq!(record.stake_time, ZERO_TS);
+
+        let record = record_tier_at(&mut pool, &nft, &mut tier_record, 61 * DAY).unwrap();
+        assert_eq!(record.tier, 2);
+        assert_eq!(record.recorded_at, 61 * DAY);
+        let staked_nft = pool.staked_nfts()[0];
+        assert_eq!(
+            staked_nft.tier(&pool.stake_store().config.stake_tiers, 95 * DAY),
+            3
+        );
+
+        // another mint's record can't be substituted
+        let other_nft = TestNFT::new(&owner_key);
+        let mut other_record = tier_record_account();
+        let mut wrong_record = record_tier(
+            &STAKE_PROGRAM_ID,
+            &cranker_key,
+            &other_nft.mint_key,
+            &pool.stake_store_key,
+            &pool.stake_list_key,
         )
         .unwrap();
+        wrong_record.accounts[1].pubkey = nft.mint_key;
+        assert_eq!(
+            do_process_instruction(
+                wrong_record,
+                vec![
+                    &mut Account::default(),
+                    &mut nft.mint_account.clone(),
+                    &mut clock_account(95 * DAY),
+                    &mut pool.stake_store_account,
+                    &mut pool.stake_list_account,
+                    &mut other_record,
+                    &mut Account::default(),
+                ],
+            ),
+            Err(NFTStakingError::InvalidTierRecord.into())
+        );
+
+        // withdrawing resets the tier
+        pool.withdraw(&mut nft, &mut staker_account, 95 * DAY, None, UnsettledRewards::Credit)
+            .unwrap();
+        let record = record_tier_at(&mut pool, &nft, &mut tier_record, 96 * DAY).unwrap();
+        assert_eq!(record.tier, 0);
+        assert_eq!(record.stake_time, ZERO_TS);
+    }
+
+    #[test]
+    fn test_verify_stake() {
+        let mut pool = TestPool::new(4);
+        let owner_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut nft = TestNFT::new(&owner_key);
+
+        let verify = |pool: &mut TestPool, owner_key: &Pubkey, nft: &TestNFT| {
+            let stake_store_data = pool.stake_store_account.data.clone();
+            let stake_list_data = pool.stake_list_account.data.clone();
+            let result = do_process_instruction(
+                verify_stake(
+                    &STAKE_PROGRAM_ID,
+                    owner_key,
+                    &nft.mint_key,
+                    &pool.stake_store_key,
+                    &pool.stake_list_key,
+                )
+                .unwrap(),
+                vec![
+                    &mut Account::default(),
+                    &mut nft.mint_account.clone(),
+                    &mut pool.stake_store_account,
+                    &mut pool.stake_list_account,
+                ],
+            );
+            assert_eq!(pool.stake_store_account.data, stake_store_data);
+            assert_eq!(pool.stake_list_account.data, stake_list_data);
+            result
+        };
+
+        let not_staked = Err(NFTStakingError::StakedNFTNotFound.into());
+        assert_eq!(verify(&mut pool, &owner_key, &nft), not_staked);
+
+        pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+        assert_eq!(verify(&mut pool, &owner_key, &nft), Ok(()));
+        assert_eq!(verify(&mut pool, &pubkey_rand(), &nft), not_staked);
+        assert_eq!(
+            verify(&mut pool, &owner_key, &TestNFT::new(&owner_key)),
+            not_staked
+        );
+
+        // the stake list must belong to the stake store
+        let other_pool = TestPool::new(4);
+        assert_eq!(
+            do_process_instruction(
+                verify_stake(
+                    &STAKE_PROGRAM_ID,
+                    &owner_key,
+                    &nft.mint_key,
+                    &pool.stake_store_key,
+                    &other_pool.stake_list_key,
+                )
+                .unwrap(),
+                vec![
+                    &mut Account::default(),
+                    &mut nft.mint_account.clone(),
+                    &mut pool.stake_store_account,
+                    &mut other_pool.stake_list_account.clone(),
+                ],
+            ),
+            Err(NFTStakingError::InvalidStakeList.into())
+        );
+
+        pool.withdraw(&mut nft, &mut staker_account, 10, None, UnsettledRewards::Credit)
+            .unwrap();
+        assert_eq!(verify(&mut pool, &owner_key, &nft), not_staked);
+    }
+
+    #[test]
+    fn test_view_return_data() {
+        let mut pool = TestPool::new(4);
+        let mut rewards = TestRewards::new(&pool, 10_000);
+        let owner_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut nft = TestNFT::new(&owner_key);
+        let mut other_nft = TestNFT::new(&owner_key);
+
+        let view = |pool: &mut TestPool,
+                    instruction: Instruction,
+                    nft: &TestNFT,
+                    ts: i64|
+         -> Result<Vec<u8>, ProgramError> {
+            let stake_store_data = pool.stake_store_account.data.clone();
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut nft.mint_account.clone(),
+                    &mut clock_account(ts),
+                    &mut pool.stake_store_account,
+                    &mut pool.stake_list_account,
+                ],
+            )?;
+            // rewards are only brought up to date in memory
+            assert_eq!(pool.stake_store_account.data, stake_store_data);
+            let (program_id, data) = return_data().unwrap();
+            assert_eq!(program_id, STAKE_PROGRAM_ID);
+            Ok(data)
+        };
+        let pending_rewards = |pool: &mut TestPool, nft: &TestNFT, ts: i64| {
+            let instruction = get_pending_rewards(
+                &STAKE_PROGRAM_ID,
+                &nft.mint_key,
+                &pool.stake_store_key,
+                &pool.stake_list_key,
+            )
+            .unwrap();
+            view(pool, instruction, nft, ts)
+                .map(|data| PendingRewards::try_from_slice(&data).unwrap())
+        };
+        let stake_info = |pool: &mut TestPool, nft: &TestNFT, ts: i64| {
+            let instruction = get_stake_info(
+                &STAKE_PROGRAM_ID,
+                &nft.mint_key,
+                &pool.stake_store_key,
+                &pool.stake_list_key,
+            )
+            .unwrap();
+            view(pool, instruction, nft, ts).map(|data| StakeInfo::try_from_slice(&data).unwrap())
+        };
+
+        assert_eq!(
+            stake_info(&mut pool, &nft, ZERO_TS),
+            Err(NFTStakingError::StakedNFTNotFound.into())
+        );
 
         do_process_instruction(
-            transfer(
-                &spl_token::id(),
-                &user_nft2_key,
-                &stake_nft2_key,
-                &depositor_key,
-                &[],
-                1,
+            set_stake_tiers(
+                &STAKE_PROGRAM_ID,
+                &pool.stake_store_key,
+                &pool.manager_key,
+                vec![1500],
             )
             .unwrap(),
-            vec![
-                &mut user_nft2_account,
-                &mut stake_nft2_account,
-                &mut depositor_account,
-            ],
+            vec![&mut pool.stake_store_account, &mut Account::default()],
         )
         .unwrap();
+        pool.fund_reward_vault(&mut rewards, 0).unwrap();
+        pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+        pool.deposit(&mut other_nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+        pool.fund_rewards(&mut rewards, 2000, 2000, ZERO_TS)
+            .unwrap();
 
-        {
-            let user_nft1 = unpack_token_account(&user_nft1_account.data).unwrap();
-            assert_eq!(user_nft1.amount, 0);
-            let user_nft2 = unpack_token_account(&user_nft2_account.data).unwrap();
-            assert_eq!(user_nft1.amount, 0);
-            let stake_nft1 = unpack_token_account(&stake_nft1_account.data).unwrap();
-            assert_eq!(stake_nft1.amount, 1);
-            let stake_nft2 = unpack_token_account(&stake_nft2_account.data).unwrap();
-            assert_eq!(stake_nft2.amount, 1);
+        assert_eq!(
+            pending_rewards(&mut pool, &nft, 1000),
+            Ok(PendingRewards {
+                owner: owner_key,
+                token_mint: nft.mint_key,
+                pending: 500,
+                as_of: 1000,
+            })
+        );
+        assert_eq!(
+            stake_info(&mut pool, &other_nft, 3000),
+            Ok(StakeInfo {
+                owner: owner_key,
+                token_mint: other_nft.mint_key,
+                stake_time: ZERO_TS,
+                stake_age: 3000,
+                multiplier_bps: BASIS_POINTS as u16,
+                tier: 1,
+                pending_rewards: 1000,
+                as_of: 3000,
+            })
+        );
+
+        // claimed rewards are no longer pending
+        let mut destination = rewards.destination(&owner_key);
+        pool.claim_rewards(&mut rewards, &mut nft, &mut destination, 1000)
+            .unwrap();
+        assert_eq!(token_balance(&destination.1), 500);
+        assert_eq!(
+            pending_rewards(&mut pool, &nft, 1000).map(|pending| pending.pending),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn test_account_substitution() {
+        let mut pool = TestPool::new(4);
+        let owner_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut nft = TestNFT::new(&owner_key);
+        pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+
+        let mut tier_record_account = tier_record_account();
+        TierRecord {
+            is_initialized: true,
+            stake_store: pool.stake_store_key,
+            token_mint: nft.mint_key,
+            owner: owner_key,
+            ..TierRecord::default()
         }
+        .save(&mut tier_record_account.data)
+        .unwrap();
+        let table_size = RarityTableHeader::packed_len() + 4;
+        let mut rarity_table_account = Account::new(
+            Rent::default().minimum_balance(table_size),
+            table_size,
+            &STAKE_PROGRAM_ID,
+        );
+        RarityTableHeader {
+            is_initialized: true,
+            stake_store: pool.stake_store_key,
+        }
+        .save(&mut rarity_table_account.data)
+        .unwrap();
 
-        msg!("*** before init: {}", stake_store_account.data.len());
-        msg!("{} {}", owner_key, stake_list_key);
-        do_process_instruction(
-            initialize(
-                &STAKE_PROGRAM_ID,
-                &stake_store_key,
-                &stake_list_key,
-                &owner_key,
+        // every other kind of account is rejected as the stake store
+        let wrong_kind = Err(NFTStakingError::InvalidAccountType.into());
+        let verify_with_store = |pool: &mut TestPool, stake_store_account: &Account| {
+            do_process_instruction(
+                verify_stake(
+                    &STAKE_PROGRAM_ID,
+                    &owner_key,
+                    &nft.mint_key,
+                    &pool.stake_store_key,
+                    &pool.stake_list_key,
+                )
+                .unwrap(),
+                vec![
+                    &mut Account::default(),
+                    &mut nft.mint_account.clone(),
+                    &mut stake_store_account.clone(),
+                    &mut pool.stake_list_account,
+                ],
             )
-            .unwrap(),
-            vec![
-                &mut stake_store_account,
-                &mut stake_list_account,
-                &mut Account::default(),
-                &mut create_account_for_test(&Rent::default()),
-            ],
+        };
+        let stake_list_account = pool.stake_list_account.clone();
+        for account in [
+            &stake_list_account,
+            &staker_account,
+            &tier_record_account,
+            &rarity_table_account,
+        ] {
+            assert_eq!(verify_with_store(&mut pool, account), wrong_kind);
+        }
+        let stake_store_account = pool.stake_store_account.clone();
+        assert_eq!(verify_with_store(&mut pool, &stake_store_account), Ok(()));
+
+        // and as the points ledger
+        let checkpoint_with_ledger = |pool: &mut TestPool, staker_account: &Account| {
+            do_process_instruction(
+                checkpoint(
+                    &STAKE_PROGRAM_ID,
+                    &pool.stake_store_key,
+                    &pool.stake_list_key,
+                    &owner_key,
+                )
+                .unwrap(),
+                vec![
+                    &mut staker_account.clone(),
+                    &mut pool.stake_store_account,
+                    &mut pool.stake_list_account,
+                    &mut clock_account(ZERO_TS),
+                ],
+            )
+        };
+        for account in [
+            &stake_store_account,
+            &stake_list_account,
+            &tier_record_account,
+            &rarity_table_account,
+        ] {
+            assert_eq!(checkpoint_with_ledger(&mut pool, account), wrong_kind);
+        }
+        assert_eq!(checkpoint_with_ledger(&mut pool, &staker_account), Ok(()));
+    }
+
+    /// Strips the header of an account, as written before versioning
+    fn strip_account_header(account: &mut Account) {
+        account.data.drain(..AccountHeader::LEN);
+        account.data.resize(account.data.len() + AccountHeader::LEN, 0);
+    }
+
+    /// Stake store as the v1 program would have written it
+    fn v1_stake_store(stake_store: &StakeStore) -> V1StakeStore {
+        V1StakeStore {
+            is_initialized: stake_store.is_initialized,
+            manager: stake_store.manager,
+            staked_count: stake_store.staked_count,
+            stake_list: stake_store.stake_list,
+            rarity_table: stake_store.rarity_table,
+            allow_list_root: stake_store.allow_list_root,
+            required_collection: stake_store.config.required_collection,
+            required_creator: stake_store.required_creator,
+            reward_vault: stake_store.reward_vault,
+            total_shares: stake_store.total_shares,
+            reward_per_share: stake_store.reward_per_share,
+            reward_rate: stake_store.reward_rate,
+            reward_epoch_end: stake_store.reward_epoch_end,
+            last_reward_ts: stake_store.last_reward_ts,
+            unclaimed_rewards: stake_store.unclaimed_rewards,
+            config: V1PoolConfig {
+                emission_schedule: stake_store.config.emission_schedule.clone(),
+                max_per_owner: stake_store.config.max_per_owner,
+                max_total: stake_store.config.max_total,
+                stake_tiers: stake_store.config.stake_tiers.clone(),
+            },
+            sunset: stake_store.sunset,
+        }
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut pool = TestPool::new(4);
+        let owner_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut nfts = (0..3).map(|_| TestNFT::new(&owner_key)).collect::<Vec<_>>();
+        for nft in nfts[..2].iter_mut() {
+            pool.deposit(nft, &mut staker_account, ZERO_TS, None, None)
+                .unwrap();
+        }
+        let stake_store = pool.stake_store();
+        let staked_nfts = pool.staked_nfts();
+        let (staker_key, _) =
+            find_staker_account_address(&STAKE_PROGRAM_ID, &pool.stake_store_key, &owner_key);
+        let staker = StakerAccount::load(&staker_account.data).unwrap();
+
+        // the v1 program wrote its own store layout, without a header
+        let v1_store = v1_stake_store(&stake_store);
+        let mut stake_store_data = v1_store.try_to_vec().unwrap();
+        stake_store_data.resize(pool.stake_store_account.data.len(), 0);
+        pool.stake_store_account.data = stake_store_data;
+        strip_account_header(&mut pool.stake_list_account);
+        strip_account_header(&mut staker_account);
+        assert_eq!(
+            StakeStore::decode(&pool.stake_store_account.data),
+            Ok((V1_VERSION, StakeStore::from(v1_store)))
+        );
+        assert_eq!(
+            do_process_instruction(
+                verify_stake(
+                    &STAKE_PROGRAM_ID,
+                    &owner_key,
+                    &nfts[0].mint_key,
+                    &pool.stake_store_key,
+                    &pool.stake_list_key,
+                )
+                .unwrap(),
+                vec![
+                    &mut Account::default(),
+                    &mut nfts[0].mint_account.clone(),
+                    &mut pool.stake_store_account,
+                    &mut pool.stake_list_account,
+                ],
+            ),
+            Err(NFTStakingError::AccountNeedsMigration.into())
+        );
+
+        let migrate_as =
+            |pool: &mut TestPool, staker_account: &mut Account, manager_key: &Pubkey| {
+                do_process_instruction(
+                    migrate(
+                        &STAKE_PROGRAM_ID,
+                        &pool.stake_store_key,
+                        &pool.stake_list_key,
+                        manager_key,
+                        &[staker_key],
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut pool.stake_store_account,
+                        &mut pool.stake_list_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        staker_account,
+                    ],
+                )
+            };
+        assert_eq!(
+            migrate_as(&mut pool, &mut staker_account, &pubkey_rand()),
+            Err(NFTStakingError::InvalidManager.into())
+        );
+        let manager_key = pool.manager_key;
+        migrate_as(&mut pool, &mut staker_account, &manager_key).unwrap();
+        assert_eq!(pool.stake_store(), stake_store);
+        assert_eq!(pool.staked_nfts(), staked_nfts);
+        assert_eq!(StakerAccount::load(&staker_account.data), Ok(staker));
+
+        // migrating again changes nothing
+        let stake_list_data = pool.stake_list_account.data.clone();
+        let staker_data = staker_account.data.clone();
+        migrate_as(&mut pool, &mut staker_account, &manager_key).unwrap();
+        assert_eq!(pool.stake_list_account.data, stake_list_data);
+        assert_eq!(staker_account.data, staker_data);
+
+        // a ledger copied to another address isn't taken as the pool's
+        let mut other_staker_account = staker_account.clone();
+        strip_account_header(&mut other_staker_account);
+        let instruction = migrate(
+            &STAKE_PROGRAM_ID,
+            &pool.stake_store_key,
+            &pool.stake_list_key,
+            &manager_key,
+            &[pubkey_rand()],
         )
         .unwrap();
+        assert_eq!(
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut pool.stake_store_account,
+                    &mut pool.stake_list_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut other_staker_account,
+                ],
+            ),
+            Err(NFTStakingError::InvalidAccountType.into())
+        );
 
-        msg!("*** after init: {}", stake_store_account.data.len());
-        msg!("=================================");
+        pool.deposit(&mut nfts[2], &mut staker_account, 10, None, None)
+            .unwrap();
+        pool.withdraw(&mut nfts[0], &mut staker_account, 20, None, UnsettledRewards::Credit)
+            .unwrap();
+        assert_eq!(pool.staked_nfts().len(), 2);
+        assert_eq!(pool.stake_store().staked_count, 2);
 
+        // older programs kept the rarity table in upload order
+        let rarity_table_key = pubkey_rand();
+        let mints = [[3; 32], [1; 32], [4; 32], [2; 32]].map(Pubkey::new_from_array);
+        let mut rarity_table_data = RarityTableHeader {
+            is_initialized: true,
+            stake_store: pool.stake_store_key,
+        }
+        .try_to_vec()
+        .unwrap();
+        rarity_table_data.extend_from_slice(&(mints.len() as u32).to_le_bytes());
+        for (i, mint) in mints.iter().enumerate() {
+            let entry = RarityEntry {
+                mint: *mint,
+                multiplier_bps: 10_000 + i as u16,
+            };
+            rarity_table_data.extend_from_slice(&entry.try_to_vec().unwrap());
+        }
+        // sized for the header up front, the test runtime can't resize
+        let table_size = AccountHeader::LEN + rarity_table_data.len();
+        rarity_table_data.resize(table_size, 0);
+        let mut rarity_table_account = Account::new(
+            Rent::default().minimum_balance(table_size),
+            table_size,
+            &STAKE_PROGRAM_ID,
+        );
+        rarity_table_account.data = rarity_table_data;
+        let mut stake_store = pool.stake_store();
+        stake_store.rarity_table = rarity_table_key;
+        stake_store
+            .save(&mut pool.stake_store_account.data)
+            .unwrap();
         do_process_instruction(
-            deposit_nft(
+            migrate(
                 &STAKE_PROGRAM_ID,
-                &depositor_key,
-                &nft1_mint_key,
-                &stake_nft1_key,
-                &stake_store_key,
-                &stake_list_key,
-                1,
+                &pool.stake_store_key,
+                &pool.stake_list_key,
+                &manager_key,
+                &[rarity_table_key],
             )
             .unwrap(),
             vec![
-                &mut depositor_account,
-                &mut nft1_mint_account,
-                &mut clock_account(ZERO_TS),
-                &mut stake_nft1_account,
-                &mut stake_store_account,
-                &mut stake_list_account,
+                &mut pool.stake_store_account,
+                &mut pool.stake_list_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut rarity_table_account,
             ],
         )
         .unwrap();
+        let (_header, rarity_table) =
+            RarityTableHeader::deserialize_vec(&mut rarity_table_account.data).unwrap();
+        let entries = rarity_table
+            .iter::<RarityEntry>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            entries.iter().map(|entry| entry.mint).collect::<Vec<_>>(),
+            [[1; 32], [2; 32], [3; 32], [4; 32]].map(Pubkey::new_from_array)
+        );
+        assert_eq!(
+            RarityTableHeader::multiplier_bps(&rarity_table, &mints[3]),
+            Ok(10_003)
+        );
+    }
+
+    #[test]
+    fn test_migrate_from_v2() {
+        let mut pool = TestPool::new(4);
+        let owner_key = pubkey_rand();
+        let mut staker_account = staker_account();
+        let mut nft = TestNFT::new(&owner_key);
+        pool.deposit(&mut nft, &mut staker_account, ZERO_TS, None, None)
+            .unwrap();
+        let stake_store = pool.stake_store();
+        let staked_nfts = pool.staked_nfts();
+        let staker = StakerAccount::load(&staker_account.data).unwrap();
+        let (staker_key, _) =
+            find_staker_account_address(&STAKE_PROGRAM_ID, &pool.stake_store_key, &owner_key);
 
+        // v2 ledgers ended before `credited_rewards` and `last_active_ts`
+        let v2_len = StakerAccount::LEN - 8 * 2;
+        assert!(staker_account.data[v2_len..].iter().all(|byte| *byte == 0));
+        for account in [
+            &mut pool.stake_store_account,
+            &mut pool.stake_list_account,
+            &mut staker_account,
+        ] {
+            account.data[AccountHeader::LEN - 1] = V2_VERSION;
+        }
+        assert_eq!(
+            StakerAccount::load(&staker_account.data),
+            Err(NFTStakingError::AccountNeedsMigration.into())
+        );
+
+        let manager_key = pool.manager_key;
         do_process_instruction(
-            deposit_nft(
+            migrate(
                 &STAKE_PROGRAM_ID,
-                &depositor_key,
-                &nft2_mint_key,
-                &stake_nft2_key,
-                &stake_store_key,
-                &stake_list_key,
-                1,
+                &pool.stake_store_key,
+                &pool.stake_list_key,
+                &manager_key,
+                &[staker_key],
             )
             .unwrap(),
             vec![
-                &mut depositor_account,
-                &mut nft2_mint_account,
-                &mut clock_account(ZERO_TS),
-                &mut stake_nft2_account,
-                &mut stake_store_account,
-                &mut stake_list_account,
+                &mut pool.stake_store_account,
+                &mut pool.stake_list_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut staker_account,
             ],
         )
         .unwrap();
-
-        // check stake store and stake list
-        // ...
+        assert_eq!(StakeStore::version(&pool.stake_store_account.data), Ok(CURRENT_VERSION));
+        assert_eq!(pool.stake_store(), stake_store);
+        assert_eq!(pool.staked_nfts(), staked_nfts);
+        assert_eq!(StakerAccount::load(&staker_account.data), Ok(staker));
     }
 }
@@ -1,8 +1,18 @@
-use crate::{big_vec::BigVec, error::NFTStakingError};
+use crate::{
+    big_vec::BigVec,
+    error::NFTStakingError,
+    merkle::HASH_BYTES,
+    metadata::Metadata,
+    utils::{
+        authority_signers, validate_authority, BASIS_POINTS, DEFAULT_MULTIPLIER_BPS,
+        REWARD_PRECISION, SECONDS_PER_POINT,
+    },
+};
+use arrayref::array_ref;
 use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
 use solana_program::{
     account_info::AccountInfo,
-    borsh::get_instance_packed_len,
+    borsh::{get_instance_packed_len, get_packed_len, try_from_slice_unchecked},
     clock::UnixTimestamp,
     msg,
     program_error::ProgramError,
@@ -10,10 +20,149 @@ use solana_program::{
     program_pack::{Pack, Sealed, IsInitialized},
     pubkey::Pubkey,
 };
+use std::{cmp::Ordering, convert::TryInto};
 
 /// Number of bytes in a pubkey
 pub const PUBKEY_BYTES: usize = 32;
 
+/// Headerless layout of the first release
+pub const LEGACY_VERSION: u8 = 0;
+
+/// Headerless layout with the reward, metadata and config fields
+pub const V1_VERSION: u8 = 1;
+
+/// First layout prefixed by an `AccountHeader`, before staker accounts
+/// gained `credited_rewards` and `last_active_ts`
+pub const V2_VERSION: u8 = 2;
+
+/// Layout written by this program, prefixed by an `AccountHeader`
+pub const CURRENT_VERSION: u8 = 3;
+
+/// Discriminator of a stake store
+pub const STAKE_STORE_DISCRIMINATOR: [u8; 8] = *b"nftstore";
+
+/// Discriminator of a stake list
+pub const STAKE_LIST_DISCRIMINATOR: [u8; 8] = *b"nftslist";
+
+/// Discriminator of an owner's points ledger
+pub const STAKER_ACCOUNT_DISCRIMINATOR: [u8; 8] = *b"nftstakr";
+
+/// Discriminator of a tier record
+pub const TIER_RECORD_DISCRIMINATOR: [u8; 8] = *b"nfttierr";
+
+/// Discriminator of a rarity table
+pub const RARITY_TABLE_DISCRIMINATOR: [u8; 8] = *b"nftrarty";
+
+/// Prefix of every versioned account. Headerless accounts start with their
+/// `is_initialized` flag, so a discriminator starting with a byte other than
+/// 0 or 1 can't be confused with them
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct AccountHeader {
+    pub discriminator: [u8; 8],
+    pub version: u8,
+}
+
+impl AccountHeader {
+    pub const LEN: usize = 8 + 1;
+
+    /// Layout version of the account, `headerless_version` if it predates
+    /// the header, or `None` if it's another kind of account
+    pub fn version(data: &[u8], discriminator: &[u8; 8], headerless_version: u8) -> Option<u8> {
+        match data.first() {
+            Some(0) | Some(1) => Some(headerless_version),
+            Some(_) if data.get(..8) == Some(&discriminator[..]) => data.get(8).copied(),
+            _ => None,
+        }
+    }
+
+    /// Checks the account is of the expected kind and current version,
+    /// returning the data that follows the header. A zeroed header is taken
+    /// as a current account that hasn't been initialized yet
+    pub fn check<'a>(
+        data: &'a [u8],
+        discriminator: &[u8; 8],
+        headerless_version: u8,
+    ) -> Result<&'a [u8], ProgramError> {
+        if matches!(data.get(..Self::LEN), Some(header) if header.iter().all(|byte| *byte == 0)) {
+            return Ok(&data[Self::LEN..]);
+        }
+        match Self::version(data, discriminator, headerless_version) {
+            Some(CURRENT_VERSION) => Ok(&data[Self::LEN..]),
+            Some(version) if version < CURRENT_VERSION => {
+                msg!("Account layout version {} must be migrated first", version);
+                Err(NFTStakingError::AccountNeedsMigration.into())
+            }
+            _ => Err(NFTStakingError::InvalidAccountType.into()),
+        }
+    }
+
+    /// Writes the current header of the given kind of account
+    pub fn write(data: &mut [u8], discriminator: &[u8; 8]) -> Result<(), ProgramError> {
+        let header = Self {
+            discriminator: *discriminator,
+            version: CURRENT_VERSION,
+        };
+        header
+            .serialize(&mut data.get_mut(..Self::LEN).ok_or(ProgramError::AccountDataTooSmall)?)
+            .map_err(|e| e.into())
+    }
+
+    /// Prefixes the first `len` bytes of a headerless account with the
+    /// current header of the given kind, the data must have room for it
+    pub fn migrate(
+        data: &mut [u8],
+        discriminator: &[u8; 8],
+        len: usize,
+    ) -> Result<(), ProgramError> {
+        if data.len() < Self::LEN + len {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        data.copy_within(0..len, Self::LEN);
+        Self::write(data, discriminator)
+    }
+}
+
+/// Account owned by the program, stored behind an `AccountHeader` naming
+/// its kind so that one kind of account can't be passed for another
+pub trait ProgramAccount: Default + BorshDeserialize + BorshSerialize {
+    const DISCRIMINATOR: [u8; 8];
+
+    /// Checks the account is of this kind and of the current version,
+    /// returning the data that follows the header
+    fn body(data: &[u8]) -> Result<&[u8], ProgramError> {
+        // every account without a header was written by the v1 program
+        AccountHeader::check(data, &Self::DISCRIMINATOR, V1_VERSION)
+    }
+
+    /// Decodes an account of this kind, older versions must be migrated
+    /// first. A zeroed account decodes as uninitialized
+    fn load(data: &[u8]) -> Result<Self, ProgramError> {
+        try_from_slice_unchecked::<Self>(Self::body(data)?).map_err(|e| e.into())
+    }
+
+    /// Writes the account behind a current header
+    fn save(&self, data: &mut [u8]) -> Result<(), ProgramError> {
+        AccountHeader::write(data, &Self::DISCRIMINATOR)?;
+        self.serialize(&mut &mut data[AccountHeader::LEN..])
+            .map_err(|e| e.into())
+    }
+}
+
+/// Splits an account of the current version made of a header followed by a
+/// BigVec
+fn split_big_vec<'data, T: ProgramAccount>(
+    data: &'data mut [u8],
+) -> Result<(T, BigVec<'data>), ProgramError> {
+    let mut body = T::body(data)?;
+    let header = T::deserialize(&mut body)?;
+    let length = AccountHeader::LEN + get_instance_packed_len(&header)?;
+    let big_vec = BigVec {
+        data: &mut data[length..],
+    };
+    Ok((header, big_vec))
+}
+
 #[repr(C)]
 #[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
 pub struct StakeStore {
@@ -21,9 +170,877 @@ pub struct StakeStore {
     pub manager: Pubkey,
     pub staked_count: u16,
     pub stake_list: Pubkey,
+    /// Rarity table consulted on deposit, `Pubkey::default()` if none
+    pub rarity_table: Pubkey,
+    /// Merkle root of the mints allowed to be deposited, all zeroes if any
+    /// mint is allowed
+    pub allow_list_root: [u8; HASH_BYTES],
+    /// Creator that must have verified every deposit, `Pubkey::default()`
+    /// if any
+    pub required_creator: Pubkey,
+    /// Token account holding the reward budget, see
+    /// `find_reward_vault_address`, `Pubkey::default()` until it's created
+    pub reward_vault: Pubkey,
+    /// Sum of the multipliers of every active stake, in basis points
+    pub total_shares: u64,
+    /// Rewards earned by a single share since the pool started, scaled by
+    /// `REWARD_PRECISION`
+    pub reward_per_share: u128,
+    /// Rewards emitted every second until the end of the current epoch
+    pub reward_rate: u64,
+    pub reward_epoch_end: UnixTimestamp,
+    /// Time up to which `reward_per_share` has been accumulated
+    pub last_reward_ts: UnixTimestamp,
+    /// Rewards emitted to stakers that they haven't claimed yet
+    pub unclaimed_rewards: u64,
+    pub config: PoolConfig,
+    /// Set once the pool is being decommissioned, new deposits are
+    /// rejected while withdrawals and claims go on
+    pub sunset: bool,
+    /// Config proposed by the manager, waiting out `PoolConfig::config_delay`
+    pub pending_config: PendingConfig,
+}
+
+/// Settings of a stake store chosen by its manager
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct PoolConfig {
+    /// Campaign emissions, on top of any funded epoch
+    pub emission_schedule: EmissionSchedule,
+    /// Most NFTs a single owner may have staked, 0 if unlimited
+    pub max_per_owner: u32,
+    /// Most NFTs staked in the pool at once, 0 if only bounded by the
+    /// stake list size
+    pub max_total: u32,
+    /// Stake durations unlocking community tiers
+    pub stake_tiers: StakeTiers,
+    /// Capacity of the stake list, 0 at initialization to fill the account
+    /// up to `MAX_ITEMS`
+    pub max_items: u16,
+    /// Seconds an NFT must stay staked before it can be withdrawn
+    pub lockup_seconds: u64,
+    /// Mint of the reward vault, `Pubkey::default()` if any mint may be
+    /// used when the vault is created
+    pub reward_mint: Pubkey,
+    /// Verified Metaplex collection every deposit must belong to,
+    /// `Pubkey::default()` if any
+    pub required_collection: Pubkey,
+    /// Fees charged on deposits and withdrawals
+    pub fees: FeeConfig,
+    /// Set while deposits, withdrawals and claims are halted
+    pub paused: bool,
+    /// Seconds of owner inactivity after which anyone may return a staked
+    /// NFT to its owner, 0 if stakes can't be reclaimed
+    pub reclaim_timeout: u64,
+    /// Most reward tokens paid to whoever reclaims a stake, out of the
+    /// stake's pending rewards
+    pub reclaim_bounty: u64,
+    /// Funds and adjusts rewards, the manager if `Pubkey::default()`
+    pub reward_operator: Pubkey,
+    /// Pauses and resumes the pool, the manager if `Pubkey::default()`
+    pub pauser: Pubkey,
+    /// Seconds a proposed config waits before it can be applied
+    pub config_delay: u64,
+}
+
+/// Config replacing the current one from `effective_at`
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct PendingConfig {
+    pub is_pending: bool,
+    pub effective_at: UnixTimestamp,
+    pub config: PoolConfig,
+}
+
+/// Authorities of a pool, each signing its own admin instructions
+#[derive(Clone, Copy, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub enum Role {
+    /// The manager: settings, key rotation and the pool's lifecycle
+    Admin,
+    /// Funds the reward vault and shapes emissions
+    RewardOperator,
+    /// Halts and resumes the pool
+    Pauser,
+}
+
+/// Fees charged to stakers, see `PoolConfig::fees`
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct FeeConfig {
+    /// Charged on every deposit, 0 if none
+    pub deposit_fee: u64,
+    /// Charged on every withdrawal, 0 if none
+    pub withdraw_fee: u64,
+    /// Token the fees are paid in, `Pubkey::default()` for lamports
+    pub fee_mint: Pubkey,
+    /// Wallet receiving lamport fees, or token account of `fee_mint`
+    pub treasury: Pubkey,
+    /// Wallets that never pay fees
+    pub exemptions: FeeExemptions,
+}
+
+impl FeeConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.deposit_fee > 0 || self.withdraw_fee > 0
+    }
+
+    /// Checks collected fees have somewhere to go
+    pub fn check(&self) -> Result<(), ProgramError> {
+        if self.is_enabled() && self.treasury == Pubkey::default() {
+            msg!("Fees require a treasury");
+            return Err(NFTStakingError::InvalidFeeConfig.into());
+        }
+        let mut exemptions = FeeExemptions::default();
+        exemptions.set(self.exemptions.wallets())?;
+        if exemptions != self.exemptions {
+            msg!("Unused fee exemptions must be zero");
+            return Err(NFTStakingError::InvalidFeeConfig.into());
+        }
+        Ok(())
+    }
+
+    /// Fee the wallet owes out of `fee`, 0 if it's exempt
+    pub fn fee_for(&self, fee: u64, wallet: &Pubkey) -> u64 {
+        if self.exemptions.contains(wallet) {
+            0
+        } else {
+            fee
+        }
+    }
+}
+
+/// Maximum number of wallets exempt from fees
+pub const MAX_FEE_EXEMPTIONS: usize = 8;
+
+/// Wallets exempt from fees, stored inline so the stake store keeps a
+/// fixed size
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct FeeExemptions {
+    pub wallet_count: u8,
+    pub wallets: [Pubkey; MAX_FEE_EXEMPTIONS],
+}
+
+impl FeeExemptions {
+    pub fn wallets(&self) -> &[Pubkey] {
+        &self.wallets[..(self.wallet_count as usize).min(MAX_FEE_EXEMPTIONS)]
+    }
+
+    /// Replaces the exempt wallets, which must be distinct
+    pub fn set(&mut self, wallets: &[Pubkey]) -> Result<(), ProgramError> {
+        if wallets.len() > MAX_FEE_EXEMPTIONS {
+            msg!("Pools exempt at most {} wallets from fees", MAX_FEE_EXEMPTIONS);
+            return Err(NFTStakingError::InvalidFeeConfig.into());
+        }
+        for (i, wallet) in wallets.iter().enumerate() {
+            if *wallet == Pubkey::default() || wallets[..i].contains(wallet) {
+                msg!("Fee exemption {} is invalid or repeated", wallet);
+                return Err(NFTStakingError::InvalidFeeConfig.into());
+            }
+        }
+        *self = Self::default();
+        self.wallets[..wallets.len()].copy_from_slice(wallets);
+        self.wallet_count = wallets.len() as u8;
+        Ok(())
+    }
+
+    pub fn contains(&self, wallet: &Pubkey) -> bool {
+        self.wallets().contains(wallet)
+    }
+}
+
+impl PoolConfig {
+    /// Checks the settings a pool is initialized with; emission campaigns
+    /// are appended once the pool is live
+    pub fn check(&self) -> Result<(), ProgramError> {
+        if self.emission_schedule != EmissionSchedule::default() {
+            msg!("Emission schedule must be appended after initialization");
+            return Err(NFTStakingError::InvalidEmissionSchedule.into());
+        }
+        if self.stake_tiers.tier_count as usize > MAX_STAKE_TIERS {
+            msg!("Pools have at most {} stake tiers", MAX_STAKE_TIERS);
+            return Err(NFTStakingError::InvalidStakeTiers.into());
+        }
+        let mut stake_tiers = StakeTiers::default();
+        stake_tiers.set(self.stake_tiers.thresholds())?;
+        if stake_tiers != self.stake_tiers {
+            msg!("Unused tier thresholds must be zero");
+            return Err(NFTStakingError::InvalidStakeTiers.into());
+        }
+        if self.lockup_seconds > i64::MAX as u64 {
+            msg!("Lockup of {} seconds is too long", self.lockup_seconds);
+            return Err(NFTStakingError::InvalidPoolConfig.into());
+        }
+        if self.reclaim_timeout > i64::MAX as u64 {
+            msg!("Reclaim timeout of {} seconds is too long", self.reclaim_timeout);
+            return Err(NFTStakingError::InvalidPoolConfig.into());
+        }
+        if self.config_delay > i64::MAX as u64 {
+            msg!("Config delay of {} seconds is too long", self.config_delay);
+            return Err(NFTStakingError::InvalidPoolConfig.into());
+        }
+        self.fees.check()
+    }
+
+    /// Checks the reward vault is created for the configured mint
+    pub fn check_reward_mint(&self, reward_mint: &Pubkey) -> Result<(), ProgramError> {
+        if self.reward_mint != Pubkey::default() && self.reward_mint != *reward_mint {
+            msg!(
+                "Rewards are paid in {}, received mint {}",
+                self.reward_mint,
+                reward_mint
+            );
+            return Err(NFTStakingError::MintMismatch.into());
+        }
+        Ok(())
+    }
+
+    /// Checks the pool isn't paused
+    pub fn check_not_paused(&self) -> Result<(), ProgramError> {
+        if self.paused {
+            msg!("Pool is paused");
+            return Err(NFTStakingError::PoolPaused.into());
+        }
+        Ok(())
+    }
+
+    /// Checks the NFT has stayed staked for the whole lockup
+    pub fn check_lockup(
+        &self,
+        staked_nft: &StakedNFT,
+        now: UnixTimestamp,
+    ) -> Result<(), ProgramError> {
+        let unlock_ts = staked_nft
+            .stake_time
+            .saturating_add(self.lockup_seconds as i64);
+        if now < unlock_ts {
+            msg!("Mint {} is locked until {}", staked_nft.token_mint, unlock_ts);
+            return Err(NFTStakingError::StakeLocked.into());
+        }
+        Ok(())
+    }
+
+    /// Checks the owner hasn't been active for the reclaim timeout since
+    /// staking the NFT, `last_active_ts` being the last instruction they
+    /// signed
+    pub fn check_reclaimable(
+        &self,
+        staked_nft: &StakedNFT,
+        last_active_ts: UnixTimestamp,
+        now: UnixTimestamp,
+    ) -> Result<(), ProgramError> {
+        if self.reclaim_timeout == 0 {
+            msg!("Stakes of this pool can't be reclaimed");
+            return Err(NFTStakingError::ReclaimTooEarly.into());
+        }
+        let reclaim_ts = staked_nft
+            .stake_time
+            .max(last_active_ts)
+            .saturating_add(self.reclaim_timeout as i64);
+        if now < reclaim_ts {
+            msg!("Mint {} can be reclaimed from {}", staked_nft.token_mint, reclaim_ts);
+            return Err(NFTStakingError::ReclaimTooEarly.into());
+        }
+        Ok(())
+    }
+
+    /// Checks the pool has room for one more NFT
+    pub fn check_pool_capacity(&self, staked_nfts: u16) -> Result<(), ProgramError> {
+        if self.max_total > 0 && staked_nfts as u32 >= self.max_total {
+            msg!("Pool is capped at {} staked NFTs", self.max_total);
+            return Err(NFTStakingError::PoolCapacityExceeded.into());
+        }
+        Ok(())
+    }
+
+    /// Checks the owner may stake one more NFT
+    pub fn check_owner_limit(&self, active_stakes: u32) -> Result<(), ProgramError> {
+        if self.max_per_owner > 0 && active_stakes >= self.max_per_owner {
+            msg!("Owners may stake at most {} NFTs", self.max_per_owner);
+            return Err(NFTStakingError::OwnerStakeLimitExceeded.into());
+        }
+        Ok(())
+    }
+}
+
+/// Maximum number of rate changes in an emission schedule
+pub const MAX_EMISSION_SEGMENTS: usize = 16;
+
+/// Rate emitted from `start_ts` until the next segment starts or the
+/// schedule ends
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct EmissionSegment {
+    pub start_ts: UnixTimestamp,
+    /// Rewards emitted every second
+    pub rate: u64,
+}
+
+/// Ordered segments of an emission campaign, stored inline so the stake
+/// store keeps a fixed size
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct EmissionSchedule {
+    pub segment_count: u8,
+    pub segments: [EmissionSegment; MAX_EMISSION_SEGMENTS],
+    /// Time the last segment stops emitting
+    pub end_ts: UnixTimestamp,
+}
+
+impl EmissionSchedule {
+    pub fn segments(&self) -> &[EmissionSegment] {
+        &self.segments[..self.segment_count as usize]
+    }
+
+    /// Rewards emitted between the two times, integrated segment by segment
+    pub fn emitted(&self, from: UnixTimestamp, to: UnixTimestamp) -> u128 {
+        let to = to.min(self.end_ts);
+        let segments = self.segments();
+        segments
+            .iter()
+            .enumerate()
+            .map(|(index, segment)| {
+                let segment_end = segments
+                    .get(index + 1)
+                    .map_or(self.end_ts, |next| next.start_ts);
+                let start = segment.start_ts.max(from);
+                let end = segment_end.min(to);
+                if end > start {
+                    (end - start) as u128 * segment.rate as u128
+                } else {
+                    0
+                }
+            })
+            .sum()
+    }
+
+    /// Appends segments starting from `now` and after the last segment,
+    /// moving the end of the schedule to `end_ts`. Segments starting after
+    /// the old end are preceded by a zero rate segment so the gap stays idle
+    pub fn append(
+        &mut self,
+        segments: &[EmissionSegment],
+        end_ts: UnixTimestamp,
+        now: UnixTimestamp,
+    ) -> Result<(), ProgramError> {
+        let gap = match (self.segments().last(), segments.first()) {
+            (Some(last), Some(first)) if last.rate > 0 && first.start_ts > self.end_ts => {
+                Some(EmissionSegment {
+                    start_ts: self.end_ts,
+                    rate: 0,
+                })
+            }
+            _ => None,
+        };
+        let count = self.segment_count as usize + gap.iter().count() + segments.len();
+        if count > MAX_EMISSION_SEGMENTS {
+            msg!(
+                "Emission schedule holds at most {} segments",
+                MAX_EMISSION_SEGMENTS
+            );
+            return Err(NFTStakingError::InvalidEmissionSchedule.into());
+        }
+        // only emissions from `now` on may change, the stake store has
+        // accumulated everything before
+        let mut last_start = self.segments().last().map(|last| last.start_ts);
+        for segment in segments {
+            if segment.start_ts < now || matches!(last_start, Some(last) if segment.start_ts <= last)
+            {
+                msg!("Segment starting at {} isn't in order", segment.start_ts);
+                return Err(NFTStakingError::InvalidEmissionSchedule.into());
+            }
+            last_start = Some(segment.start_ts);
+        }
+        if end_ts < now || matches!(last_start, Some(last) if end_ts < last) {
+            msg!("Emission schedule can't end at {}", end_ts);
+            return Err(NFTStakingError::InvalidEmissionSchedule.into());
+        }
+        for segment in gap.iter().chain(segments) {
+            self.segments[self.segment_count as usize] = *segment;
+            self.segment_count += 1;
+        }
+        self.end_ts = end_ts;
+        Ok(())
+    }
+
+    /// First time appending `segments` and moving the end to `end_ts` would
+    /// change the emitted rate, `None` if it wouldn't change it
+    pub fn first_change_ts(
+        &self,
+        segments: &[EmissionSegment],
+        end_ts: UnixTimestamp,
+    ) -> Option<UnixTimestamp> {
+        let running = matches!(self.segments().last(), Some(last) if last.rate > 0);
+        let moved_end = match end_ts.cmp(&self.end_ts) {
+            Ordering::Less => Some(end_ts),
+            // without new segments the last one runs on past its old end
+            Ordering::Greater if running && segments.is_empty() => Some(self.end_ts),
+            _ => None,
+        };
+        segments
+            .first()
+            .map(|first| first.start_ts)
+            .into_iter()
+            .chain(moved_end)
+            .min()
+    }
+}
+
+/// Maximum number of stake-age tiers
+pub const MAX_STAKE_TIERS: usize = 8;
+
+/// Stake durations, in seconds, an NFT must stay staked to reach each tier;
+/// tier 0 is below the first threshold
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct StakeTiers {
+    pub tier_count: u8,
+    pub thresholds: [u64; MAX_STAKE_TIERS],
+}
+
+impl StakeTiers {
+    pub fn thresholds(&self) -> &[u64] {
+        &self.thresholds[..self.tier_count as usize]
+    }
+
+    /// Replaces the thresholds, which must be positive and increasing
+    pub fn set(&mut self, thresholds: &[u64]) -> Result<(), ProgramError> {
+        if thresholds.len() > MAX_STAKE_TIERS {
+            msg!("Pools have at most {} stake tiers", MAX_STAKE_TIERS);
+            return Err(NFTStakingError::InvalidStakeTiers.into());
+        }
+        let mut last = 0;
+        for threshold in thresholds {
+            if *threshold <= last {
+                msg!("Tier threshold {} isn't in order", threshold);
+                return Err(NFTStakingError::InvalidStakeTiers.into());
+            }
+            last = *threshold;
+        }
+        *self = Self::default();
+        self.thresholds[..thresholds.len()].copy_from_slice(thresholds);
+        self.tier_count = thresholds.len() as u8;
+        Ok(())
+    }
+
+    /// Tier reached after staking for the given number of seconds
+    pub fn tier(&self, staked_seconds: u64) -> u8 {
+        self.thresholds()
+            .iter()
+            .take_while(|threshold| **threshold <= staked_seconds)
+            .count() as u8
+    }
 }
 
 impl StakeStore {
+    /// Size of a stake store account, header included
+    pub fn packed_len() -> usize {
+        AccountHeader::LEN + get_packed_len::<Self>()
+    }
+
+    /// Layout version of a stake store account
+    pub fn version(data: &[u8]) -> Result<u8, ProgramError> {
+        match AccountHeader::version(data, &STAKE_STORE_DISCRIMINATOR, V1_VERSION) {
+            // the first release sized the account for its four fields
+            Some(V1_VERSION) if data.len() < get_packed_len::<V1StakeStore>() => {
+                Ok(LEGACY_VERSION)
+            }
+            Some(version) if version <= CURRENT_VERSION => Ok(version),
+            _ => Err(NFTStakingError::InvalidAccountType.into()),
+        }
+    }
+
+    /// Decodes a stake store of any version, the fields an older version
+    /// lacks take their defaults
+    pub fn decode(data: &[u8]) -> Result<(u8, Self), ProgramError> {
+        let version = Self::version(data)?;
+        let stake_store = match version {
+            LEGACY_VERSION => {
+                let legacy = try_from_slice_unchecked::<LegacyStakeStore>(data)?;
+                Self {
+                    is_initialized: legacy.is_initialized,
+                    manager: legacy.manager,
+                    staked_count: legacy.staked_count,
+                    stake_list: legacy.stake_list,
+                    // every legacy stake counts at the default multiplier
+                    total_shares: legacy.staked_count as u64 * DEFAULT_MULTIPLIER_BPS as u64,
+                    ..Self::default()
+                }
+            }
+            V1_VERSION => try_from_slice_unchecked::<V1StakeStore>(data)?.into(),
+            _ => try_from_slice_unchecked::<Self>(&data[AccountHeader::LEN..])?,
+        };
+        Ok((version, stake_store))
+    }
+
+    /// Key holding the role, roles that were never assigned fall to the
+    /// manager
+    pub fn authority(&self, role: Role) -> Pubkey {
+        let authority = match role {
+            Role::Admin => self.manager,
+            Role::RewardOperator => self.config.reward_operator,
+            Role::Pauser => self.config.pauser,
+        };
+        if authority == Pubkey::default() {
+            self.manager
+        } else {
+            authority
+        }
+    }
+
+    /// Checks the holder of the role signed, itself or through enough
+    /// members of its multisig among the signers trailing `accounts`,
+    /// returning the signing members
+    pub fn check_authority<'a, 'b>(
+        &self,
+        role: Role,
+        authority_info: &'b AccountInfo<'a>,
+        accounts: &'b [AccountInfo<'a>],
+    ) -> Result<Vec<&'b AccountInfo<'a>>, ProgramError> {
+        let authority = self.authority(role);
+        if *authority_info.key != authority {
+            msg!(
+                "Incorrect {:?} provided, expected {}, received {}",
+                role,
+                authority,
+                authority_info.key
+            );
+            return Err(match role {
+                Role::Admin => NFTStakingError::InvalidManager,
+                Role::RewardOperator => NFTStakingError::InvalidRewardOperator,
+                Role::Pauser => NFTStakingError::InvalidPauser,
+            }
+            .into());
+        }
+        match validate_authority(authority_info, authority_signers(accounts)) {
+            Err(error) => {
+                msg!("{:?} {} did not sign", role, authority);
+                Err(error)
+            }
+            signing_members => signing_members,
+        }
+    }
+
+    /// Hands the role to another key, `Pubkey::default()` returns a
+    /// delegated role to the manager
+    pub fn set_authority(&mut self, role: Role, authority: Pubkey) -> Result<(), ProgramError> {
+        match role {
+            Role::Admin if authority == Pubkey::default() => {
+                msg!("Pools can't be left without a manager");
+                return Err(NFTStakingError::InvalidManager.into());
+            }
+            Role::Admin => self.manager = authority,
+            Role::RewardOperator => self.config.reward_operator = authority,
+            Role::Pauser => self.config.pauser = authority,
+        }
+        Ok(())
+    }
+
+    /// Checks a config can replace the current one: the stake list keeps
+    /// its capacity and the reward mint is fixed once the vault exists
+    pub fn check_config_update(&self, config: &PoolConfig) -> Result<(), ProgramError> {
+        config.check()?;
+        if config.max_items != self.config.max_items {
+            msg!("Stake list capacity is fixed at {}", self.config.max_items);
+            return Err(NFTStakingError::InvalidPoolConfig.into());
+        }
+        if self.reward_vault != Pubkey::default() && config.reward_mint != self.config.reward_mint
+        {
+            msg!("Reward mint can't change once the reward vault exists");
+            return Err(NFTStakingError::InvalidPoolConfig.into());
+        }
+        Ok(())
+    }
+
+    /// Queues a config to be applied once the current config delay has
+    /// passed, replacing any change still pending
+    pub fn propose_config(
+        &mut self,
+        config: PoolConfig,
+        now: UnixTimestamp,
+    ) -> Result<(), ProgramError> {
+        self.check_config_update(&config)?;
+        self.pending_config = PendingConfig {
+            is_pending: true,
+            effective_at: now.saturating_add(self.config.config_delay as i64),
+            config,
+        };
+        Ok(())
+    }
+
+    /// Checks the config may be set directly rather than proposed: only
+    /// pools without a config delay or a pending change allow it
+    pub fn check_config_unlocked(&self) -> Result<(), ProgramError> {
+        if self.config.config_delay > 0 || self.pending_config.is_pending {
+            msg!("Config changes must be proposed and wait out the config delay");
+            return Err(NFTStakingError::ConfigChangeNeedsProposal.into());
+        }
+        Ok(())
+    }
+
+    /// Checks a reward rate change taking effect at `change_ts` leaves
+    /// stakers the config delay as notice
+    pub fn check_rate_change_notice(
+        &self,
+        change_ts: UnixTimestamp,
+        now: UnixTimestamp,
+    ) -> Result<(), ProgramError> {
+        let earliest_ts = now.saturating_add(self.config.config_delay as i64);
+        if change_ts < earliest_ts {
+            msg!("The reward rate can't change before {}", earliest_ts);
+            return Err(NFTStakingError::RewardRateTimelocked.into());
+        }
+        Ok(())
+    }
+
+    /// Replaces the config with the pending one once it's effective. The
+    /// emission schedule, pause state and roles aren't part of a proposal
+    /// and carry over, schedule changes wait out the config delay when
+    /// they're appended instead
+    pub fn apply_pending_config(&mut self, now: UnixTimestamp) -> Result<(), ProgramError> {
+        self.check_pending_config()?;
+        if now < self.pending_config.effective_at {
+            msg!("Pending config takes effect at {}", self.pending_config.effective_at);
+            return Err(NFTStakingError::ConfigTimelocked.into());
+        }
+        let mut config = std::mem::take(&mut self.pending_config).config;
+        // the pool may have changed since the proposal
+        self.check_config_update(&config)?;
+        config.emission_schedule = std::mem::take(&mut self.config.emission_schedule);
+        config.paused = self.config.paused;
+        config.reward_operator = self.config.reward_operator;
+        config.pauser = self.config.pauser;
+        self.config = config;
+        Ok(())
+    }
+
+    /// Drops the pending config
+    pub fn cancel_pending_config(&mut self) -> Result<(), ProgramError> {
+        self.check_pending_config()?;
+        self.pending_config = PendingConfig::default();
+        Ok(())
+    }
+
+    fn check_pending_config(&self) -> Result<(), ProgramError> {
+        if !self.pending_config.is_pending {
+            msg!("No config change is pending");
+            return Err(NFTStakingError::NoPendingConfig.into());
+        }
+        Ok(())
+    }
+
+    /// Whether no shares are staked anymore. The pool can only be closed
+    /// once its stake list, `staked_count` and unclaimed rewards are empty too
+    pub fn is_empty(&self) -> bool {
+        self.total_shares == 0
+    }
+
+    pub fn has_allow_list(&self) -> bool {
+        self.allow_list_root != [0; HASH_BYTES]
+    }
+
+    /// Whether deposits must come with the mint's Metaplex metadata
+    pub fn has_metadata_requirements(&self) -> bool {
+        self.config.required_collection != Pubkey::default()
+            || self.required_creator != Pubkey::default()
+    }
+
+    /// Checks the NFT belongs to the required collection and was verified
+    /// by the required creator
+    pub fn check_metadata(&self, metadata: &Metadata) -> Result<(), ProgramError> {
+        let required_collection = self.config.required_collection;
+        if required_collection != Pubkey::default() {
+            match &metadata.collection {
+                Some(collection) if collection.key == required_collection => {
+                    if !collection.verified {
+                        msg!("Collection {} is not verified", collection.key);
+                        return Err(NFTStakingError::UnverifiedCollection.into());
+                    }
+                }
+                _ => {
+                    msg!(
+                        "Mint {} is not part of collection {}",
+                        metadata.mint,
+                        required_collection
+                    );
+                    return Err(NFTStakingError::CollectionMismatch.into());
+                }
+            }
+        }
+        if self.required_creator != Pubkey::default() {
+            match metadata.creator(&self.required_creator) {
+                Some(creator) if creator.verified => {}
+                Some(_) => {
+                    msg!("Creator {} is not verified", self.required_creator);
+                    return Err(NFTStakingError::UnverifiedCreator.into());
+                }
+                None => {
+                    msg!(
+                        "Mint {} is not created by {}",
+                        metadata.mint,
+                        self.required_creator
+                    );
+                    return Err(NFTStakingError::CreatorMismatch.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn has_rarity_table(&self) -> bool {
+        self.rarity_table != Pubkey::default()
+    }
+
+    pub fn check_rarity_table(&self, rarity_table_info: &AccountInfo) -> Result<(), ProgramError> {
+        if *rarity_table_info.key != self.rarity_table {
+            msg!(
+                "Invalid rarity table provided, expected {}, received {}",
+                self.rarity_table,
+                rarity_table_info.key
+            );
+            Err(NFTStakingError::InvalidRarityTable.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn check_reward_vault(&self, reward_vault_info: &AccountInfo) -> Result<(), ProgramError> {
+        if *reward_vault_info.key != self.reward_vault {
+            msg!(
+                "Invalid reward vault provided, expected {}, received {}",
+                self.reward_vault,
+                reward_vault_info.key
+            );
+            Err(NFTStakingError::InvalidRewardVault.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Accumulates the rewards emitted since the last update, split across
+    /// the active shares. Nothing accumulates while the pool is empty.
+    pub fn update_rewards(&mut self, now: UnixTimestamp) -> Result<(), ProgramError> {
+        let emitted = self.emitted_rewards(self.last_reward_ts, now);
+        if emitted > 0 && self.total_shares > 0 {
+            self.reward_per_share = emitted
+                .checked_mul(REWARD_PRECISION)
+                .map(|scaled| scaled / self.total_shares as u128)
+                .and_then(|increase| self.reward_per_share.checked_add(increase))
+                .ok_or(NFTStakingError::AmountOverflow)?;
+            self.unclaimed_rewards = (self.unclaimed_rewards as u128 + emitted)
+                .try_into()
+                .map_err(|_| NFTStakingError::AmountOverflow)?;
+        }
+        self.last_reward_ts = self.last_reward_ts.max(now);
+        Ok(())
+    }
+
+    /// Rewards emitted between the two times by the funded epoch and the
+    /// emission schedule together
+    pub fn emitted_rewards(&self, from: UnixTimestamp, to: UnixTimestamp) -> u128 {
+        let epoch_end = to.min(self.reward_epoch_end);
+        let epoch = if epoch_end > from {
+            (epoch_end - from) as u128 * self.reward_rate as u128
+        } else {
+            0
+        };
+        epoch + self.config.emission_schedule.emitted(from, to)
+    }
+
+    /// Starts a new epoch distributing `amount` over `duration` seconds,
+    /// rolling over whatever the current epoch hasn't emitted yet
+    pub fn fund_rewards(
+        &mut self,
+        amount: u64,
+        duration: u64,
+        now: UnixTimestamp,
+    ) -> Result<(), ProgramError> {
+        if duration == 0 || duration > i64::MAX as u64 {
+            return Err(NFTStakingError::InvalidRewardEpoch.into());
+        }
+        self.update_rewards(now)?;
+        let remaining = self.reward_epoch_end.saturating_sub(now).max(0) as u64;
+        let leftover = remaining
+            .checked_mul(self.reward_rate)
+            .ok_or(NFTStakingError::AmountOverflow)?;
+        let budget = amount
+            .checked_add(leftover)
+            .ok_or(NFTStakingError::AmountOverflow)?;
+        self.reward_rate = budget / duration;
+        self.reward_epoch_end = now
+            .checked_add(duration as i64)
+            .ok_or(NFTStakingError::AmountOverflow)?;
+        Ok(())
+    }
+
+    /// Rewards owed to `shares` at the current index, to be subtracted from
+    /// later claims
+    pub fn reward_debt(&self, shares: u16) -> Result<u64, ProgramError> {
+        let debt = self
+            .reward_per_share
+            .checked_mul(shares as u128)
+            .ok_or(NFTStakingError::AmountOverflow)?
+            / REWARD_PRECISION;
+        if debt > u64::MAX as u128 {
+            return Err(NFTStakingError::AmountOverflow.into());
+        }
+        Ok(debt as u64)
+    }
+
+    /// Rewards the stake can claim, as of the last update
+    pub fn pending_rewards(&self, staked_nft: &StakedNFT) -> Result<u64, ProgramError> {
+        Ok(self
+            .reward_debt(staked_nft.multiplier_bps)?
+            .saturating_sub(staked_nft.reward_debt))
+    }
+
+    /// Removes claimed or forfeited rewards from the unclaimed balance
+    pub fn release_rewards(&mut self, amount: u64) {
+        self.unclaimed_rewards = self.unclaimed_rewards.saturating_sub(amount);
+    }
+
+    /// Rewards the current epoch and the emission schedule will still emit,
+    /// the rewards must be up to date
+    pub fn committed_rewards(&self) -> Result<u64, ProgramError> {
+        let end = self
+            .reward_epoch_end
+            .max(self.config.emission_schedule.end_ts);
+        self.emitted_rewards(self.last_reward_ts, end)
+            .try_into()
+            .map_err(|_| NFTStakingError::AmountOverflow.into())
+    }
+
+    /// Splits the vault balance between what stakers are owed and what the
+    /// manager may withdraw, the rewards must be up to date
+    pub fn reward_vault_status(&self, balance: u64) -> Result<RewardVaultStatus, ProgramError> {
+        let committed = self.committed_rewards()?;
+        let owed = self
+            .unclaimed_rewards
+            .checked_add(committed)
+            .ok_or(NFTStakingError::AmountOverflow)?;
+        Ok(RewardVaultStatus {
+            balance,
+            unclaimed: self.unclaimed_rewards,
+            committed,
+            excess: balance.saturating_sub(owed),
+            shortfall: owed.saturating_sub(balance),
+        })
+    }
+
+    /// Records a newly staked share, the rewards must be up to date
+    pub fn add_shares(&mut self, shares: u16) -> Result<(), ProgramError> {
+        self.total_shares = self
+            .total_shares
+            .checked_add(shares as u64)
+            .ok_or(NFTStakingError::AmountOverflow)?;
+        Ok(())
+    }
+
+    /// Records a withdrawn share, the rewards must be up to date
+    pub fn remove_shares(&mut self, shares: u16) {
+        self.total_shares = self.total_shares.saturating_sub(shares as u64);
+    }
+
     pub fn check_stake_list(&self, stake_list_info: &AccountInfo) -> Result<(), ProgramError> {
         if *stake_list_info.key != self.stake_list {
             msg!(
@@ -38,6 +1055,100 @@ impl StakeStore {
     }
 }
 
+/// Breakdown of the reward vault balance
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RewardVaultStatus {
+    /// Tokens held by the vault
+    pub balance: u64,
+    /// Rewards emitted to stakers and not claimed yet
+    pub unclaimed: u64,
+    /// Rewards the current epoch and the emission schedule will still emit
+    pub committed: u64,
+    /// Tokens beyond what's owed, which the manager may withdraw
+    pub excess: u64,
+    /// Owed rewards the vault can't cover
+    pub shortfall: u64,
+}
+
+impl ProgramAccount for StakeStore {
+    const DISCRIMINATOR: [u8; 8] = STAKE_STORE_DISCRIMINATOR;
+}
+
+/// Stake store of the first release
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct LegacyStakeStore {
+    pub is_initialized: bool,
+    pub manager: Pubkey,
+    pub staked_count: u16,
+    pub stake_list: Pubkey,
+}
+
+/// Headerless stake store of the v1 program
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct V1StakeStore {
+    pub is_initialized: bool,
+    pub manager: Pubkey,
+    pub staked_count: u16,
+    pub stake_list: Pubkey,
+    pub rarity_table: Pubkey,
+    pub allow_list_root: [u8; HASH_BYTES],
+    pub required_collection: Pubkey,
+    pub required_creator: Pubkey,
+    pub reward_vault: Pubkey,
+    pub total_shares: u64,
+    pub reward_per_share: u128,
+    pub reward_rate: u64,
+    pub reward_epoch_end: UnixTimestamp,
+    pub last_reward_ts: UnixTimestamp,
+    pub unclaimed_rewards: u64,
+    pub config: V1PoolConfig,
+    pub sunset: bool,
+}
+
+/// Pool settings of the v1 program
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct V1PoolConfig {
+    pub emission_schedule: EmissionSchedule,
+    pub max_per_owner: u32,
+    pub max_total: u32,
+    pub stake_tiers: StakeTiers,
+}
+
+impl From<V1StakeStore> for StakeStore {
+    /// Settings the v1 program lacked are left at their defaults
+    fn from(v1: V1StakeStore) -> Self {
+        Self {
+            is_initialized: v1.is_initialized,
+            manager: v1.manager,
+            staked_count: v1.staked_count,
+            stake_list: v1.stake_list,
+            rarity_table: v1.rarity_table,
+            allow_list_root: v1.allow_list_root,
+            required_creator: v1.required_creator,
+            reward_vault: v1.reward_vault,
+            total_shares: v1.total_shares,
+            reward_per_share: v1.reward_per_share,
+            reward_rate: v1.reward_rate,
+            reward_epoch_end: v1.reward_epoch_end,
+            last_reward_ts: v1.last_reward_ts,
+            unclaimed_rewards: v1.unclaimed_rewards,
+            config: PoolConfig {
+                emission_schedule: v1.config.emission_schedule,
+                max_per_owner: v1.config.max_per_owner,
+                max_total: v1.config.max_total,
+                stake_tiers: v1.config.stake_tiers,
+                required_collection: v1.required_collection,
+                ..PoolConfig::default()
+            },
+            sunset: v1.sunset,
+            pending_config: PendingConfig::default(),
+        }
+    }
+}
+
 impl IsInitialized for StakeStore {
     fn is_initialized(&self) -> bool {
         self.is_initialized == true
@@ -73,21 +1184,92 @@ impl StakeList {
 }
 
 impl StakeListHeader {
-    // const LEN: usize = 3;
+    /// Size of the stake list's headers, before the BigVec
+    pub fn packed_len() -> usize {
+        AccountHeader::LEN + get_packed_len::<Self>()
+    }
 
-    /// Extracts the stake list into its header and internal BigVec
-    pub fn deserialize_vec(data: &mut [u8]) -> Result<(Self, BigVec), ProgramError> {
-        let mut data_mut = &data[..];
-        let header = StakeListHeader::deserialize(&mut data_mut)?;
-        let length = get_instance_packed_len(&header)?;
+    /// Extracts the stake list into its header and internal BigVec, the
+    /// list must be of the current version
+    pub fn deserialize_vec(data: &mut [u8]) -> Result<(Self, BigVec<'_>), ProgramError> {
+        split_big_vec(data)
+    }
 
-        let big_vec = BigVec {
-            data: &mut data[length..],
+    /// Decodes the stake list and its entries, whatever its version; a
+    /// headerless list has the version of its stake store
+    pub fn decode(data: &[u8], stake_store_version: u8) -> Result<(Self, Vec<StakedNFT>), ProgramError> {
+        let version = AccountHeader::version(data, &STAKE_LIST_DISCRIMINATOR, stake_store_version)
+            .ok_or(NFTStakingError::InvalidAccountType)?;
+        let mut data = match version {
+            LEGACY_VERSION | V1_VERSION => data,
+            V2_VERSION | CURRENT_VERSION => &data[AccountHeader::LEN..],
+            _ => return Err(NFTStakingError::InvalidAccountType.into()),
         };
-        Ok((header, big_vec))
+        let header = StakeListHeader::deserialize(&mut data)?;
+        let len = u32::deserialize(&mut data)? as usize;
+        let entries = if version == LEGACY_VERSION {
+            (0..len)
+                .map(|_| LegacyStakedNFT::deserialize(&mut data).map(StakedNFT::from))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            (0..len)
+                .map(|_| StakedNFT::deserialize(&mut data))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        Ok((header, entries))
+    }
+
+    /// Size a headerless stake list needs once migrated
+    pub fn migrated_len(data: &[u8]) -> Result<usize, ProgramError> {
+        let header_len = get_packed_len::<Self>();
+        let len = data
+            .get(header_len..header_len + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+            .ok_or(ProgramError::AccountDataTooSmall)?;
+        Ok(Self::packed_len() + 4 + len * StakedNFT::LEN)
+    }
+
+    /// Rewrites a headerless stake list in the current layout, within its
+    /// data; returns the number of entries
+    pub fn migrate(data: &mut [u8], version: u8) -> Result<u32, ProgramError> {
+        let header_len = get_packed_len::<Self>();
+        let migrated_len = Self::migrated_len(data)?;
+        let len = (migrated_len - Self::packed_len() - 4) / StakedNFT::LEN;
+        let entry_len = match version {
+            LEGACY_VERSION => LegacyStakedNFT::LEN,
+            V1_VERSION => StakedNFT::LEN,
+            _ => return Err(NFTStakingError::InvalidAccountType.into()),
+        };
+        let old_start = header_len + 4;
+        let new_start = Self::packed_len() + 4;
+        if data.len() < migrated_len || data.len() < old_start + len * entry_len {
+            msg!("Stake list is too small to hold {} migrated entries", len);
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+
+        if version == LEGACY_VERSION {
+            // entries grow, so move them from the last one to keep the
+            // ones not yet read intact
+            for index in (0..len).rev() {
+                let old = old_start + index * entry_len;
+                let staked_nft =
+                    StakedNFT::from(LegacyStakedNFT::try_from_slice(&data[old..old + entry_len])?);
+                let new = new_start + index * StakedNFT::LEN;
+                staked_nft.pack_into_slice(&mut data[new..new + StakedNFT::LEN]);
+            }
+            data.copy_within(0..old_start, AccountHeader::LEN);
+        } else {
+            data.copy_within(0..old_start + len * entry_len, AccountHeader::LEN);
+        }
+        AccountHeader::write(data, &STAKE_LIST_DISCRIMINATOR)?;
+        Ok(len as u32)
     }
 }
 
+impl ProgramAccount for StakeListHeader {
+    const DISCRIMINATOR: [u8; 8] = STAKE_LIST_DISCRIMINATOR;
+}
+
 impl IsInitialized for StakeListHeader {
     fn is_initialized(&self) -> bool {
         self.is_initialized
@@ -101,6 +1283,11 @@ pub struct StakedNFT {
     pub token_mint: Pubkey,
     pub holder: Pubkey,
     pub stake_time: UnixTimestamp,
+    /// Reward multiplier taken from the rarity table at deposit time
+    pub multiplier_bps: u16,
+    /// Rewards already accounted for at the stake store's reward index,
+    /// see `StakeStore::pending_rewards`
+    pub reward_debt: u64,
 }
 
 impl StakedNFT {
@@ -123,6 +1310,21 @@ impl StakedNFT {
             ) == 0
     }
 
+    /// Checks the owner of a packed stake info
+    pub fn is_owned_by(data: &[u8], owner_address_bytes: &[u8]) -> bool {
+        sol_memcmp(&data[0..PUBKEY_BYTES], owner_address_bytes, PUBKEY_BYTES) == 0
+    }
+
+    /// Reads the stake time of a packed stake info
+    pub fn stake_time_from_slice(data: &[u8]) -> UnixTimestamp {
+        UnixTimestamp::from_le_bytes(*array_ref![data, 96, 8])
+    }
+
+    /// Tier the NFT has reached by staying staked until `now`
+    pub fn tier(&self, stake_tiers: &StakeTiers, now: UnixTimestamp) -> u8 {
+        stake_tiers.tier(now.saturating_sub(self.stake_time).max(0) as u64)
+    }
+
     pub fn is_not_withdrawn(data: &[u8], holder_address_bytes: &[u8]) -> bool {
         sol_memcmp(
             &data[64..64 + PUBKEY_BYTES],
@@ -132,10 +1334,39 @@ impl StakedNFT {
     }
 }
 
+/// Stake list entry of the first release
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct LegacyStakedNFT {
+    pub owner: Pubkey,
+    pub token_mint: Pubkey,
+    pub holder: Pubkey,
+    pub stake_time: UnixTimestamp,
+}
+
+impl LegacyStakedNFT {
+    pub const LEN: usize = 32 * 3 + 8;
+}
+
+impl From<LegacyStakedNFT> for StakedNFT {
+    /// Legacy stakes weigh the default multiplier and haven't earned
+    /// rewards yet
+    fn from(legacy: LegacyStakedNFT) -> Self {
+        Self {
+            owner: legacy.owner,
+            token_mint: legacy.token_mint,
+            holder: legacy.holder,
+            stake_time: legacy.stake_time,
+            multiplier_bps: DEFAULT_MULTIPLIER_BPS,
+            reward_debt: 0,
+        }
+    }
+}
+
 impl Sealed for StakedNFT {}
 
 impl Pack for StakedNFT {
-    const LEN: usize = 32 * 3 + 8;
+    const LEN: usize = 32 * 3 + 8 + 2 + 8;
     fn pack_into_slice(&self, data: &mut [u8]) {
         let mut data = data;
         self.serialize(&mut data).unwrap();
@@ -146,3 +1377,668 @@ impl Pack for StakedNFT {
         Ok(unpacked)
     }
 }
+
+/// Points ledger of a single owner, stored in a PDA derived from the stake
+/// store and the owner (see `find_staker_account_address`)
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct StakerAccount {
+    pub is_initialized: bool,
+    pub stake_store: Pubkey,
+    pub owner: Pubkey,
+    /// Number of NFTs the owner currently has in the stake list
+    pub active_stakes: u32,
+    /// Sum of the rarity multipliers of the active stakes, in basis points
+    pub active_weight_bps: u64,
+    /// Cumulative seconds staked, summed over every NFT of the owner
+    pub stake_seconds: u64,
+    /// Cumulative seconds staked weighted by rarity, in basis points
+    pub weighted_stake_seconds: u64,
+    pub points: u64,
+    /// Time up to which `stake_seconds` has been accrued
+    pub last_update_ts: UnixTimestamp,
+    /// Rewards of NFTs withdrawn without the reward accounts, still owed to
+    /// the owner
+    pub credited_rewards: u64,
+    /// Time of the owner's last deposit, withdrawal or claim, which the
+    /// reclaim timeout runs from. Permissionless cranks don't move it
+    pub last_active_ts: UnixTimestamp,
+}
+
+impl StakerAccount {
+    /// Size of the account, header included
+    pub const LEN: usize = AccountHeader::LEN + 1 + 32 * 2 + 4 + 8 * 7;
+    /// Size of the headerless ledger the v1 program wrote
+    pub const V1_LEN: usize = Self::LEN - AccountHeader::LEN - 8 * 2;
+
+    /// Accrues stake-seconds for the active stakes up to `now`
+    pub fn accrue(&mut self, now: UnixTimestamp) -> Result<(), ProgramError> {
+        let elapsed = now.saturating_sub(self.last_update_ts).max(0) as u64;
+        let stake_seconds = elapsed
+            .checked_mul(self.active_stakes as u64)
+            .ok_or(NFTStakingError::AmountOverflow)?;
+        let weighted_stake_seconds = elapsed
+            .checked_mul(self.active_weight_bps)
+            .ok_or(NFTStakingError::AmountOverflow)?;
+        self.add_stake_seconds(stake_seconds, weighted_stake_seconds)?;
+        self.last_update_ts = self.last_update_ts.max(now);
+        Ok(())
+    }
+
+    /// Adds stake-seconds to the ledger and refreshes the points balance
+    pub fn add_stake_seconds(
+        &mut self,
+        stake_seconds: u64,
+        weighted_stake_seconds: u64,
+    ) -> Result<(), ProgramError> {
+        self.stake_seconds = self
+            .stake_seconds
+            .checked_add(stake_seconds)
+            .ok_or(NFTStakingError::AmountOverflow)?;
+        self.weighted_stake_seconds = self
+            .weighted_stake_seconds
+            .checked_add(weighted_stake_seconds)
+            .ok_or(NFTStakingError::AmountOverflow)?;
+        self.points = self.weighted_stake_seconds / (SECONDS_PER_POINT * BASIS_POINTS);
+        Ok(())
+    }
+
+    /// Records a newly staked NFT
+    pub fn add_stake(&mut self, multiplier_bps: u16) -> Result<(), ProgramError> {
+        self.active_stakes = self
+            .active_stakes
+            .checked_add(1)
+            .ok_or(NFTStakingError::AmountOverflow)?;
+        self.active_weight_bps = self
+            .active_weight_bps
+            .checked_add(multiplier_bps as u64)
+            .ok_or(NFTStakingError::AmountOverflow)?;
+        Ok(())
+    }
+
+    /// Credits the unsettled rewards of a withdrawn NFT
+    pub fn credit_rewards(&mut self, amount: u64) -> Result<(), ProgramError> {
+        self.credited_rewards = self
+            .credited_rewards
+            .checked_add(amount)
+            .ok_or(NFTStakingError::AmountOverflow)?;
+        Ok(())
+    }
+
+    /// Records an instruction the owner signed at `now`
+    pub fn record_activity(&mut self, now: UnixTimestamp) {
+        self.last_active_ts = self.last_active_ts.max(now);
+    }
+
+    /// Records a withdrawn NFT
+    pub fn remove_stake(&mut self, multiplier_bps: u16) {
+        self.active_stakes = self.active_stakes.saturating_sub(1);
+        self.active_weight_bps = self
+            .active_weight_bps
+            .saturating_sub(multiplier_bps as u64);
+    }
+
+    /// Points the owner would hold if checkpointed at `now`, without mutating
+    /// the ledger
+    pub fn projected_points(&self, now: UnixTimestamp) -> u64 {
+        let mut projected = self.clone();
+        match projected.accrue(now) {
+            Ok(()) => projected.points,
+            Err(_) => u64::MAX,
+        }
+    }
+
+    /// Orders ledgers for an off-chain leaderboard, highest projected points
+    /// first, keeping at most `limit` entries
+    pub fn leaderboard(
+        mut stakers: Vec<StakerAccount>,
+        now: UnixTimestamp,
+        limit: usize,
+    ) -> Vec<(StakerAccount, u64)> {
+        stakers.retain(|staker| staker.is_initialized);
+        let mut ranked = stakers
+            .into_iter()
+            .map(|staker| {
+                let points = staker.projected_points(now);
+                (staker, points)
+            })
+            .collect::<Vec<_>>();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.owner.cmp(&b.0.owner)));
+        ranked.truncate(limit);
+        ranked
+    }
+}
+
+impl ProgramAccount for StakerAccount {
+    const DISCRIMINATOR: [u8; 8] = STAKER_ACCOUNT_DISCRIMINATOR;
+}
+
+impl IsInitialized for StakerAccount {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+/// Stake tier reached by a staked NFT, stored in a PDA derived from the
+/// stake store and the mint (see `find_tier_record_address`) so other
+/// programs can check it without a CPI
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct TierRecord {
+    pub is_initialized: bool,
+    pub stake_store: Pubkey,
+    pub token_mint: Pubkey,
+    /// Owner of the stake when the tier was recorded
+    pub owner: Pubkey,
+    /// Stake time of the recorded stake, distinguishing restakes
+    pub stake_time: UnixTimestamp,
+    /// 0 once the NFT has been withdrawn
+    pub tier: u8,
+    pub recorded_at: UnixTimestamp,
+}
+
+impl TierRecord {
+    /// Size of the account, header included
+    pub const LEN: usize = AccountHeader::LEN + 1 + 32 * 3 + 8 + 1 + 8;
+}
+
+impl ProgramAccount for TierRecord {
+    const DISCRIMINATOR: [u8; 8] = TIER_RECORD_DISCRIMINATOR;
+}
+
+impl IsInitialized for TierRecord {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+/// Manager maintained table of reward multipliers, a header followed by a
+/// BigVec of `RarityEntry` sorted by mint
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct RarityTableHeader {
+    pub is_initialized: bool,
+    pub stake_store: Pubkey,
+}
+
+impl RarityTableHeader {
+    /// Size of the rarity table's headers, before the BigVec
+    pub fn packed_len() -> usize {
+        AccountHeader::LEN + get_packed_len::<Self>()
+    }
+
+    /// Extracts the rarity table into its header and internal BigVec, the
+    /// table must be of the current version
+    pub fn deserialize_vec(data: &mut [u8]) -> Result<(Self, BigVec<'_>), ProgramError> {
+        split_big_vec(data)
+    }
+
+    /// Multiplier of the mint, or the default one if the mint isn't listed
+    pub fn multiplier_bps(rarity_table: &BigVec, mint: &Pubkey) -> Result<u16, ProgramError> {
+        let entry = match rarity_table
+            .binary_search_by::<RarityEntry, _>(|data| RarityEntry::cmp_mint(data, mint.as_ref()))
+        {
+            Ok(index) => rarity_table.get::<RarityEntry>(index)?,
+            Err(_) => None,
+        };
+        Ok(entry
+            .map(|entry| entry.multiplier_bps)
+            .unwrap_or(DEFAULT_MULTIPLIER_BPS))
+    }
+
+    /// Bytes used by a headerless rarity table, entries included
+    pub fn headerless_len(data: &[u8]) -> Result<usize, ProgramError> {
+        let header_len = get_packed_len::<Self>();
+        let len = data
+            .get(header_len..header_len + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+            .ok_or(ProgramError::AccountDataTooSmall)?;
+        Ok(header_len + 4 + len * RarityEntry::LEN)
+    }
+}
+
+impl ProgramAccount for RarityTableHeader {
+    const DISCRIMINATOR: [u8; 8] = RARITY_TABLE_DISCRIMINATOR;
+}
+
+impl IsInitialized for RarityTableHeader {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct RarityEntry {
+    pub mint: Pubkey,
+    pub multiplier_bps: u16,
+}
+
+impl RarityEntry {
+    pub fn memcmp_mint(data: &[u8], mint_address_bytes: &[u8]) -> bool {
+        sol_memcmp(&data[0..PUBKEY_BYTES], mint_address_bytes, PUBKEY_BYTES) == 0
+    }
+
+    /// Orders a packed entry's mint against the given mint, or against the
+    /// mint of another packed entry; the rarity table is kept in this order
+    pub fn cmp_mint(data: &[u8], mint_address_bytes: &[u8]) -> Ordering {
+        data[0..PUBKEY_BYTES].cmp(&mint_address_bytes[0..PUBKEY_BYTES])
+    }
+}
+
+impl Sealed for RarityEntry {}
+
+impl Pack for RarityEntry {
+    const LEN: usize = 32 + 2;
+    fn pack_into_slice(&self, data: &mut [u8]) {
+        let mut data = data;
+        self.serialize(&mut data).unwrap();
+    }
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let unpacked = Self::try_from_slice(src)?;
+        Ok(unpacked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn staker(owner: Pubkey, active_stakes: u32, stake_seconds: u64) -> StakerAccount {
+        let mut staker = StakerAccount {
+            is_initialized: true,
+            owner,
+            active_stakes,
+            active_weight_bps: active_stakes as u64 * BASIS_POINTS,
+            ..StakerAccount::default()
+        };
+        staker
+            .add_stake_seconds(stake_seconds, stake_seconds * BASIS_POINTS)
+            .unwrap();
+        staker
+    }
+
+    #[test]
+    fn staker_accrue() {
+        let mut staker = staker(Pubkey::new_unique(), 3, 0);
+        staker.accrue(100).unwrap();
+        assert_eq!(staker.stake_seconds, 300);
+        assert_eq!(staker.last_update_ts, 100);
+
+        // a clock behind the last update accrues nothing
+        staker.accrue(50).unwrap();
+        assert_eq!(staker.stake_seconds, 300);
+        assert_eq!(staker.last_update_ts, 100);
+
+        staker.remove_stake(DEFAULT_MULTIPLIER_BPS);
+        staker.remove_stake(DEFAULT_MULTIPLIER_BPS);
+        staker.accrue(100 + SECONDS_PER_POINT as i64).unwrap();
+        assert_eq!(staker.stake_seconds, 300 + SECONDS_PER_POINT);
+        assert_eq!(staker.points, 1);
+    }
+
+    #[test]
+    fn staker_accrue_weighted() {
+        let mut staker = staker(Pubkey::new_unique(), 0, 0);
+        // a common NFT and a 2.5x rare one
+        staker.add_stake(DEFAULT_MULTIPLIER_BPS).unwrap();
+        staker.add_stake(25_000).unwrap();
+        staker.accrue(2 * SECONDS_PER_POINT as i64).unwrap();
+        assert_eq!(staker.stake_seconds, 4 * SECONDS_PER_POINT);
+        assert_eq!(staker.points, 7);
+
+        staker.remove_stake(25_000);
+        assert_eq!(staker.active_stakes, 1);
+        assert_eq!(staker.active_weight_bps, BASIS_POINTS);
+        staker.accrue(3 * SECONDS_PER_POINT as i64).unwrap();
+        assert_eq!(staker.points, 8);
+    }
+
+    #[test]
+    fn staker_leaderboard() {
+        let now = 10 * SECONDS_PER_POINT as i64;
+        let idle = staker(Pubkey::new_unique(), 0, 20 * SECONDS_PER_POINT);
+        let active = staker(Pubkey::new_unique(), 2, 5 * SECONDS_PER_POINT);
+        let newcomer = staker(Pubkey::new_unique(), 1, 0);
+        let uninitialized = StakerAccount::default();
+
+        let stakers = vec![newcomer, idle.clone(), uninitialized, active.clone()];
+        let ranked = StakerAccount::leaderboard(stakers, now, 2);
+        assert_eq!(ranked.len(), 2);
+        assert_eq!(ranked[0], (active.clone(), 25));
+        assert_eq!(ranked[1], (idle.clone(), 20));
+        assert_eq!(active.projected_points(now), 25);
+        assert_eq!(active.points, 5);
+    }
+
+    #[test]
+    fn stake_store_epoch_rollover() {
+        let mut stake_store = StakeStore {
+            total_shares: 2 * BASIS_POINTS,
+            ..StakeStore::default()
+        };
+        stake_store.fund_rewards(1000, 100, 0).unwrap();
+        assert_eq!(stake_store.reward_rate, 10);
+
+        // half the epoch is left when the next one is funded
+        stake_store.fund_rewards(500, 100, 50).unwrap();
+        assert_eq!(stake_store.reward_rate, 10);
+        assert_eq!(stake_store.reward_epoch_end, 150);
+        let staked_nft = StakedNFT {
+            multiplier_bps: DEFAULT_MULTIPLIER_BPS,
+            ..StakedNFT::default()
+        };
+        assert_eq!(stake_store.pending_rewards(&staked_nft).unwrap(), 250);
+
+        stake_store.update_rewards(1000).unwrap();
+        assert_eq!(stake_store.pending_rewards(&staked_nft).unwrap(), 750);
+        assert_eq!(stake_store.unclaimed_rewards, 1500);
+        assert_eq!(
+            stake_store.fund_rewards(1, 0, 1000),
+            Err(NFTStakingError::InvalidRewardEpoch.into())
+        );
+    }
+
+    #[test]
+    fn reward_vault_status() {
+        let mut stake_store = StakeStore {
+            total_shares: BASIS_POINTS,
+            ..StakeStore::default()
+        };
+        stake_store.fund_rewards(1000, 100, 0).unwrap();
+        stake_store.update_rewards(40).unwrap();
+        assert_eq!(
+            stake_store.reward_vault_status(1500).unwrap(),
+            RewardVaultStatus {
+                balance: 1500,
+                unclaimed: 400,
+                committed: 600,
+                excess: 500,
+                shortfall: 0,
+            }
+        );
+
+        // claims leave the vault with the unclaimed balance
+        stake_store.release_rewards(400);
+        let status = stake_store.reward_vault_status(1100).unwrap();
+        assert_eq!((status.unclaimed, status.excess), (0, 500));
+
+        // an underfunded vault reports what it lacks
+        stake_store.update_rewards(200).unwrap();
+        assert_eq!(
+            stake_store.reward_vault_status(100).unwrap(),
+            RewardVaultStatus {
+                balance: 100,
+                unclaimed: 600,
+                committed: 0,
+                excess: 0,
+                shortfall: 500,
+            }
+        );
+    }
+
+    fn segment(start_ts: UnixTimestamp, rate: u64) -> EmissionSegment {
+        EmissionSegment { start_ts, rate }
+    }
+
+    #[test]
+    fn emission_schedule_integration() {
+        let mut schedule = EmissionSchedule::default();
+        assert_eq!(schedule.emitted(0, 1000), 0);
+        schedule
+            .append(&[segment(100, 10), segment(200, 30)], 300, 0)
+            .unwrap();
+        assert_eq!(schedule.emitted(0, 100), 0);
+        assert_eq!(schedule.emitted(150, 250), 50 * 10 + 50 * 30);
+        assert_eq!(schedule.emitted(0, 1000), 1000 + 3000);
+
+        // segments start from now, after the last one, and before the end
+        let invalid = Err(NFTStakingError::InvalidEmissionSchedule.into());
+        assert_eq!(schedule.append(&[segment(250, 5)], 400, 260), invalid);
+        assert_eq!(schedule.append(&[segment(200, 5)], 400, 150), invalid);
+        assert_eq!(
+            schedule.append(&[segment(350, 5), segment(320, 5)], 400, 150),
+            invalid
+        );
+        assert_eq!(schedule.append(&[segment(500, 5)], 400, 150), invalid);
+        assert_eq!(schedule.append(&[], 100, 150), invalid);
+        assert_eq!(schedule.segments().len(), 2);
+
+        // a pause, then a resumed campaign
+        schedule
+            .append(&[segment(300, 0), segment(400, 20)], 500, 250)
+            .unwrap();
+        assert_eq!(schedule.emitted(0, 1000), 4000 + 2000);
+
+        // resuming after the old end doesn't emit the last rate over the gap,
+        // the zero rate segment filling it counts towards the limit
+        let too_many = (0..MAX_EMISSION_SEGMENTS as i64 - 4)
+            .map(|i| segment(600 + i, 1))
+            .collect::<Vec<_>>();
+        assert_eq!(schedule.append(&too_many, 1000, 500), invalid);
+        schedule.append(&too_many[1..], 1000, 500).unwrap();
+        assert_eq!(schedule.segments().len(), MAX_EMISSION_SEGMENTS);
+        assert_eq!(schedule.segments()[4], segment(500, 0));
+        assert_eq!(schedule.emitted(500, 601), 0);
+        assert_eq!(schedule.emitted(0, 1000), 6000 + 1000 - 601);
+    }
+
+    #[test]
+    fn stake_tiers() {
+        const DAY: u64 = 86_400;
+        let mut tiers = StakeTiers::default();
+        let staked_nft = StakedNFT {
+            stake_time: 1000,
+            ..StakedNFT::default()
+        };
+        assert_eq!(staked_nft.tier(&tiers, 1000 + 365 * DAY as i64), 0);
+
+        let invalid = Err(NFTStakingError::InvalidStakeTiers.into());
+        assert_eq!(tiers.set(&[0, DAY]), invalid);
+        assert_eq!(tiers.set(&[30 * DAY, 30 * DAY]), invalid);
+        assert_eq!(tiers.set(&[60 * DAY, 30 * DAY]), invalid);
+        assert_eq!(tiers.set(&[DAY; MAX_STAKE_TIERS + 1]), invalid);
+        tiers.set(&[30 * DAY, 60 * DAY, 90 * DAY]).unwrap();
+
+        let at = |days: u64| 1000 + (days * DAY) as i64;
+        assert_eq!(staked_nft.tier(&tiers, 0), 0);
+        assert_eq!(staked_nft.tier(&tiers, at(30) - 1), 0);
+        assert_eq!(staked_nft.tier(&tiers, at(30)), 1);
+        assert_eq!(staked_nft.tier(&tiers, at(89)), 2);
+        assert_eq!(staked_nft.tier(&tiers, at(400)), 3);
+
+        // shrinking the tiers clears the old thresholds
+        tiers.set(&[DAY]).unwrap();
+        assert_eq!(tiers.thresholds(), &[DAY]);
+        assert_eq!(tiers.thresholds[1], 0);
+        assert_eq!(staked_nft.tier(&tiers, at(400)), 1);
+    }
+
+    #[test]
+    fn legacy_stake_store_decode() {
+        let legacy = LegacyStakeStore {
+            is_initialized: true,
+            manager: Pubkey::new_unique(),
+            staked_count: 3,
+            stake_list: Pubkey::new_unique(),
+        };
+        let data = legacy.try_to_vec().unwrap();
+        assert_eq!(StakeStore::version(&data), Ok(LEGACY_VERSION));
+        assert_eq!(
+            StakeStore::load(&data),
+            Err(NFTStakingError::AccountNeedsMigration.into())
+        );
+        let (version, stake_store) = StakeStore::decode(&data).unwrap();
+        assert_eq!(version, LEGACY_VERSION);
+        assert_eq!(
+            stake_store,
+            StakeStore {
+                is_initialized: true,
+                manager: legacy.manager,
+                staked_count: 3,
+                stake_list: legacy.stake_list,
+                total_shares: 3 * DEFAULT_MULTIPLIER_BPS as u64,
+                ..StakeStore::default()
+            }
+        );
+
+        // headerless v1 store, then the same store behind a header
+        let v1 = V1StakeStore {
+            is_initialized: true,
+            manager: legacy.manager,
+            staked_count: 3,
+            stake_list: legacy.stake_list,
+            required_collection: Pubkey::new_unique(),
+            total_shares: 3 * DEFAULT_MULTIPLIER_BPS as u64,
+            config: V1PoolConfig {
+                max_total: 10,
+                ..V1PoolConfig::default()
+            },
+            ..V1StakeStore::default()
+        };
+        let mut data = v1.try_to_vec().unwrap();
+        let stake_store = StakeStore::from(v1.clone());
+        assert_eq!(stake_store.config.required_collection, v1.required_collection);
+        assert_eq!(stake_store.config.max_total, 10);
+        assert_eq!(StakeStore::decode(&data), Ok((V1_VERSION, stake_store.clone())));
+        data.resize(StakeStore::packed_len(), 0);
+        stake_store.save(&mut data).unwrap();
+        assert_eq!(&data[..8], &STAKE_STORE_DISCRIMINATOR);
+        assert_eq!(StakeStore::load(&data), Ok(stake_store.clone()));
+        assert_eq!(StakeStore::decode(&data), Ok((CURRENT_VERSION, stake_store)));
+
+        // other kinds of accounts are rejected
+        data[..8].copy_from_slice(&STAKE_LIST_DISCRIMINATOR);
+        assert_eq!(
+            StakeStore::load(&data),
+            Err(NFTStakingError::InvalidAccountType.into())
+        );
+        assert!(StakeStore::decode(&data).is_err());
+    }
+
+    fn legacy_stake_list(entries: &[LegacyStakedNFT], capacity: usize) -> Vec<u8> {
+        let header = StakeListHeader {
+            is_initialized: true,
+            max_items: 65535,
+            count: 0,
+        };
+        let mut data = (header, entries.to_vec()).try_to_vec().unwrap();
+        data.resize(capacity, 0);
+        data
+    }
+
+    #[test]
+    fn stake_list_migration() {
+        let entries = (0..3)
+            .map(|i| LegacyStakedNFT {
+                owner: Pubkey::new_unique(),
+                token_mint: Pubkey::new_unique(),
+                holder: Pubkey::new_unique(),
+                stake_time: i * 100,
+            })
+            .collect::<Vec<_>>();
+        let migrated = entries
+            .iter()
+            .map(|entry| StakedNFT::from(*entry))
+            .collect::<Vec<_>>();
+        let migrated_len = StakeListHeader::packed_len() + 4 + 3 * StakedNFT::LEN;
+
+        let mut data = legacy_stake_list(&entries, migrated_len - 1);
+        assert_eq!(StakeListHeader::migrated_len(&data), Ok(migrated_len));
+        let (_, decoded) = StakeListHeader::decode(&data, LEGACY_VERSION).unwrap();
+        assert_eq!(decoded, migrated);
+        assert_eq!(
+            StakeListHeader::migrate(&mut data, LEGACY_VERSION),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+
+        let mut data = legacy_stake_list(&entries, migrated_len);
+        assert!(StakeListHeader::deserialize_vec(&mut data).is_err());
+        assert_eq!(StakeListHeader::migrate(&mut data, LEGACY_VERSION), Ok(3));
+        let (header, stake_list) = StakeListHeader::deserialize_vec(&mut data).unwrap();
+        assert!(header.is_initialized);
+        assert_eq!(
+            stake_list
+                .iter::<StakedNFT>()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            migrated
+        );
+        assert_eq!(
+            StakeListHeader::decode(&data, LEGACY_VERSION).unwrap().1,
+            migrated
+        );
+
+        // a headerless v1 list only gains the header
+        let header = StakeListHeader {
+            is_initialized: true,
+            max_items: 65535,
+            count: 0,
+        };
+        let mut data = (header, migrated.clone()).try_to_vec().unwrap();
+        data.resize(migrated_len, 0);
+        assert_eq!(StakeListHeader::decode(&data, V1_VERSION).unwrap().1, migrated);
+        assert_eq!(StakeListHeader::migrate(&mut data, V1_VERSION), Ok(3));
+        assert_eq!(
+            StakeListHeader::decode(&data, V1_VERSION).unwrap().1,
+            migrated
+        );
+    }
+
+    #[test]
+    fn account_discriminators() {
+        fn saved<T: ProgramAccount>(account: T) -> Vec<u8> {
+            let mut data = vec![0; AccountHeader::LEN + get_instance_packed_len(&account).unwrap()];
+            account.save(&mut data).unwrap();
+            data
+        }
+        fn loads<T: ProgramAccount>(data: &[u8]) -> Result<(), ProgramError> {
+            T::load(data).map(|_| ())
+        }
+        let accounts = [
+            saved(StakeStore {
+                is_initialized: true,
+                ..StakeStore::default()
+            }),
+            saved(StakeListHeader {
+                is_initialized: true,
+                ..StakeListHeader::default()
+            }),
+            saved(StakerAccount {
+                is_initialized: true,
+                ..StakerAccount::default()
+            }),
+            saved(TierRecord {
+                is_initialized: true,
+                ..TierRecord::default()
+            }),
+            saved(RarityTableHeader {
+                is_initialized: true,
+                ..RarityTableHeader::default()
+            }),
+        ];
+        let loaders: [fn(&[u8]) -> Result<(), ProgramError>; 5] = [
+            loads::<StakeStore>,
+            loads::<StakeListHeader>,
+            loads::<StakerAccount>,
+            loads::<TierRecord>,
+            loads::<RarityTableHeader>,
+        ];
+
+        // each kind only loads as itself
+        for (index, data) in accounts.iter().enumerate() {
+            for (loader_index, load) in loaders.iter().enumerate() {
+                let expected = if index == loader_index {
+                    Ok(())
+                } else {
+                    Err(NFTStakingError::InvalidAccountType.into())
+                };
+                assert_eq!(load(data), expected);
+            }
+        }
+
+        // a zeroed account loads as uninitialized, whatever its kind
+        assert_eq!(StakerAccount::load(&[0; 512]), Ok(StakerAccount::default()));
+    }
+}
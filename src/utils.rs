@@ -1,27 +1,135 @@
 use crate::error::NFTStakingError;
-use solana_program::program_pack::Pack;
-use spl_token::state::Account;
+use solana_program::{
+    account_info::AccountInfo, msg, program_error::ProgramError, program_pack::Pack,
+    pubkey::Pubkey,
+};
+use spl_token::{
+    instruction::MAX_SIGNERS,
+    state::{Account, Multisig},
+};
+
+/// Signing members of a multisig authority, which `with_authority_signers`
+/// appends after the instruction's own accounts
+pub fn authority_signers<'a, 'b>(accounts: &'b [AccountInfo<'a>]) -> &'b [AccountInfo<'a>] {
+    let start = accounts
+        .iter()
+        .rposition(|account_info| !account_info.is_signer)
+        .map_or(0, |position| position + 1);
+    &accounts[start..]
+}
+
+/// Checks the wallet belongs to the authority: the authority itself or,
+/// when it's an spl_token multisig, one of its members
+pub fn check_authority_wallet(
+    authority_info: &AccountInfo,
+    wallet_info: &AccountInfo,
+) -> Result<(), ProgramError> {
+    let is_owned = if *authority_info.owner == spl_token::id()
+        && authority_info.data_len() == Multisig::LEN
+    {
+        let multisig = Multisig::unpack(&authority_info.data.borrow())?;
+        multisig.signers[..(multisig.n as usize).min(MAX_SIGNERS)].contains(wallet_info.key)
+    } else {
+        wallet_info.key == authority_info.key
+    };
+    if !is_owned {
+        msg!("{} doesn't belong to {}", wallet_info.key, authority_info.key);
+        return Err(NFTStakingError::InvalidRentDestination.into());
+    }
+    Ok(())
+}
+
+/// Associated token account program
+pub mod associated_token_program {
+    solana_program::declare_id!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+}
 
 pub const MAX_ITEMS: u16 = 65535;
 
+/// A point is earned for every hour a single NFT stays staked
+pub const SECONDS_PER_POINT: u64 = 3600;
+
+/// Denominator of every multiplier expressed in basis points
+pub const BASIS_POINTS: u64 = 10_000;
+
+/// Multiplier of NFTs that aren't listed in the rarity table
+pub const DEFAULT_MULTIPLIER_BPS: u16 = 10_000;
+
+/// Scale of the reward-per-share index, keeping precision when a small
+/// budget is split across many shares
+pub const REWARD_PRECISION: u128 = 1_000_000_000_000;
+
 /// Unpacks a spl_token `Account`.
 pub fn unpack_token_account(data: &[u8]) -> Result<Account, NFTStakingError> {
     Account::unpack(data).map_err(|_| NFTStakingError::ExpectedAccount)
 }
 
+/// Checks the authority signed, either itself or, when it's an spl_token
+/// multisig, through at least `m` of its members among `signers`. Returns the
+/// signing members of a multisig, none for a single key
+pub fn validate_authority<'a, 'b>(
+    authority_info: &'b AccountInfo<'a>,
+    signers: &'b [AccountInfo<'a>],
+) -> Result<Vec<&'b AccountInfo<'a>>, ProgramError> {
+    if *authority_info.owner == spl_token::id() && authority_info.data_len() == Multisig::LEN {
+        let multisig = Multisig::unpack(&authority_info.data.borrow())?;
+        let members = &multisig.signers[..(multisig.n as usize).min(MAX_SIGNERS)];
+        let mut matched = [false; MAX_SIGNERS];
+        let mut signing_members = vec![];
+        for signer in signers.iter().filter(|signer| signer.is_signer) {
+            if let Some(position) = members.iter().position(|member| member == signer.key) {
+                if !matched[position] {
+                    matched[position] = true;
+                    signing_members.push(signer);
+                }
+            }
+        }
+        if signing_members.len() < multisig.m as usize {
+            msg!(
+                "{} of the {} required members of multisig {} signed",
+                signing_members.len(),
+                multisig.m,
+                authority_info.key
+            );
+            return Err(NFTStakingError::SignatureMissing.into());
+        }
+        return Ok(signing_members);
+    }
+    if !authority_info.is_signer {
+        msg!("Signature of {} missing", authority_info.key);
+        return Err(NFTStakingError::SignatureMissing.into());
+    }
+    Ok(vec![])
+}
+
+/// Address of the wallet's associated token account of the mint
+pub fn get_associated_token_address(wallet_address: &Pubkey, mint_address: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            &wallet_address.to_bytes(),
+            &spl_token::id().to_bytes(),
+            &mint_address.to_bytes(),
+        ],
+        &associated_token_program::id(),
+    )
+    .0
+}
+
 #[cfg(test)]
 pub mod test_utils {
     use crate::processor::Processor;
     use solana_program::{
         account_info::AccountInfo, clock::Clock, entrypoint::ProgramResult,
         instruction::Instruction, msg, program_error::ProgramError, program_pack::Pack,
-        program_stubs, pubkey::Pubkey, rent::Rent, sysvar,
+        program_stubs, pubkey::Pubkey, rent::Rent, system_instruction::SystemInstruction,
+        system_program, sysvar,
     };
     use solana_sdk::account::{create_account_for_test, create_is_signer_account_infos, Account};
     use spl_token::{
         instruction::{approve, initialize_account, initialize_mint, mint_to},
         state::{Account as SplAccount, Mint as SplMint},
     };
+    use std::cell::RefCell;
     pub const STAKE_PROGRAM_ID: Pubkey = Pubkey::new_from_array([3u8; 32]);
     // pub const TOKEN_PROGRAM_ID: Pubkey = Pubkey::new_from_array([1u8; 32]); //TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA
     pub const DEFAULT_TOKEN_DECIMALS: u8 = 0; // for nft
@@ -37,8 +145,27 @@ pub mod test_utils {
         Pubkey::new_unique()
     }
 
+    thread_local! {
+        static RETURN_DATA: RefCell<Option<(Pubkey, Vec<u8>)>> = RefCell::new(None);
+    }
+
+    /// Return data set by the last instruction processed on this thread
+    pub fn return_data() -> Option<(Pubkey, Vec<u8>)> {
+        RETURN_DATA.with(|return_data| return_data.borrow().clone())
+    }
+
     struct TestSyscallStubs {}
     impl program_stubs::SyscallStubs for TestSyscallStubs {
+        fn sol_set_return_data(&self, data: &[u8]) {
+            RETURN_DATA.with(|return_data| {
+                *return_data.borrow_mut() = Some((STAKE_PROGRAM_ID, data.to_vec()))
+            });
+        }
+
+        fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+            return_data()
+        }
+
         fn sol_invoke_signed(
             &self,
             instruction: &Instruction,
@@ -47,6 +174,32 @@ pub mod test_utils {
         ) -> ProgramResult {
             msg!("TestSyscallStubs::sol_invoke_signed()");
 
+            // lamport transfers are the only system instruction emulated
+            if instruction.program_id == system_program::id() {
+                return match bincode::deserialize(&instruction.data) {
+                    Ok(SystemInstruction::Transfer { lamports }) => {
+                        let find = |key: &Pubkey| {
+                            account_infos
+                                .iter()
+                                .find(|account_info| account_info.key == key)
+                                .ok_or(ProgramError::NotEnoughAccountKeys)
+                        };
+                        let from = find(&instruction.accounts[0].pubkey)?;
+                        let to = find(&instruction.accounts[1].pubkey)?;
+                        if !from.is_signer {
+                            return Err(ProgramError::MissingRequiredSignature);
+                        }
+                        **from.lamports.borrow_mut() = from
+                            .lamports()
+                            .checked_sub(lamports)
+                            .ok_or(ProgramError::InsufficientFunds)?;
+                        **to.lamports.borrow_mut() += lamports;
+                        Ok(())
+                    }
+                    _ => Err(ProgramError::InvalidInstructionData),
+                };
+            }
+
             let mut new_account_infos = vec![];
 
             // mimic check for token program in accounts
@@ -92,43 +245,37 @@ pub mod test_utils {
         accounts: Vec<&mut Account>,
     ) -> ProgramResult {
         test_syscall_stubs();
+        RETURN_DATA.with(|return_data| *return_data.borrow_mut() = None);
 
         // approximate the logic in the actual runtime which runs the instruction
         // and only updates accounts if the instruction is successful
         let mut account_clones = accounts.iter().map(|x| (*x).clone()).collect::<Vec<_>>();
-        let mut meta = instruction
-            .accounts
-            .iter()
-            .zip(account_clones.iter_mut())
-            .map(|(account_meta, account)| (&account_meta.pubkey, account_meta.is_signer, account))
-            .collect::<Vec<_>>();
-        let mut account_infos = create_is_signer_account_infos(&mut meta);
-        let res = if instruction.program_id == STAKE_PROGRAM_ID {
-            Processor::process(&instruction.program_id, &account_infos, &instruction.data)
-        } else {
-            spl_token::processor::Processor::process(
-                &instruction.program_id,
-                &account_infos,
-                &instruction.data,
-            )
-        };
-
-        if res.is_ok() {
-            let mut account_metas = instruction
+        let res = {
+            let mut meta = instruction
                 .accounts
                 .iter()
-                .zip(accounts)
-                .map(|(account_meta, account)| (&account_meta.pubkey, account))
+                .zip(account_clones.iter_mut())
+                .map(|(account_meta, account)| {
+                    (&account_meta.pubkey, account_meta.is_signer, account)
+                })
                 .collect::<Vec<_>>();
-            for account_info in account_infos.iter_mut() {
-                for account_meta in account_metas.iter_mut() {
-                    if account_info.key == account_meta.0 {
-                        let account = &mut account_meta.1;
-                        account.owner = *account_info.owner;
-                        account.lamports = **account_info.lamports.borrow();
-                        account.data = account_info.data.borrow().to_vec();
-                    }
-                }
+            let account_infos = create_is_signer_account_infos(&mut meta);
+            if instruction.program_id == STAKE_PROGRAM_ID {
+                Processor::process(&instruction.program_id, &account_infos, &instruction.data)
+            } else {
+                spl_token::processor::Processor::process(
+                    &instruction.program_id,
+                    &account_infos,
+                    &instruction.data,
+                )
+            }
+        };
+
+        // copy back from the underlying buffers rather than the account
+        // infos, whose data slices may have been advanced by serialization
+        if res.is_ok() {
+            for (account, account_clone) in accounts.into_iter().zip(account_clones) {
+                *account = account_clone;
             }
         }
         res
@@ -0,0 +1,82 @@
+#![cfg(feature = "test-bpf")]
+
+use solana_program::{program_pack::Pack, rent::Rent};
+use solana_program_test::{processor, tokio, ProgramTest};
+use solana_sdk::{
+    account::Account,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use solana_staking_poc::{
+    id, instruction,
+    processor::Processor,
+    state::{PoolConfig, ProgramAccount, StakeListHeader, StakeStore, StakedNFT},
+    utils::MAX_ITEMS,
+};
+
+#[tokio::test]
+async fn test_initialize_full_capacity_list() {
+    let stake_store = Keypair::new();
+    let stake_list = Keypair::new();
+    let manager = Keypair::new();
+    let store_size = StakeStore::packed_len();
+    let list_size = StakeListHeader::packed_len() + 4 + StakedNFT::LEN * MAX_ITEMS as usize;
+
+    // a list this large can't be created within a single instruction, so
+    // the accounts are seeded in the test bank
+    let mut program_test = ProgramTest::new(
+        "solana_staking_poc",
+        id(),
+        processor!(Processor::process),
+    );
+    for (pubkey, size) in [
+        (stake_store.pubkey(), store_size),
+        (stake_list.pubkey(), list_size),
+    ] {
+        program_test.add_account(
+            pubkey,
+            Account::new(Rent::default().minimum_balance(size), size, &id()),
+        );
+    }
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::initialize(
+            &id(),
+            &stake_store.pubkey(),
+            &stake_list.pubkey(),
+            &manager.pubkey(),
+            PoolConfig::default(),
+        )
+        .unwrap()],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &manager], recent_blockhash);
+    banks_client.process_transaction(transaction).await.unwrap();
+
+    let stake_store_account = banks_client
+        .get_account(stake_store.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let stake_store_data = StakeStore::load(&stake_store_account.data).unwrap();
+    assert!(stake_store_data.is_initialized);
+    assert_eq!(stake_store_data.manager, manager.pubkey());
+    assert_eq!(stake_store_data.stake_list, stake_list.pubkey());
+
+    let mut stake_list_account = banks_client
+        .get_account(stake_list.pubkey())
+        .await
+        .unwrap()
+        .unwrap();
+    let (header, stake_list_vec) =
+        StakeListHeader::deserialize_vec(&mut stake_list_account.data).unwrap();
+    assert!(header.is_initialized);
+    assert_eq!(header.max_items, MAX_ITEMS);
+    assert_eq!(header.count, 0);
+    assert!(stake_list_vec.is_empty());
+    assert_eq!(
+        stake_list_vec.data.len(),
+        4 + StakedNFT::LEN * MAX_ITEMS as usize
+    );
+}